@@ -0,0 +1,197 @@
+//! Throughput of the inbound and outbound message path
+//!
+//! Every group compares the previous implementation (`before`, copied from the runtime as it was
+//! before [`codec`] was introduced) with the current one (`codec`). Both read lines from memory
+//! and encode into memory, so STDIN and STDOUT are not part of the measurement.
+//! Run with `cargo bench --bench message_path`.
+
+use std::{collections::HashMap, io::BufRead};
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use gossipy::{codec, Body, Event, Message};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+enum Payload {
+    Broadcast {
+        message: isize,
+    },
+    Topology {
+        topology: HashMap<String, Vec<String>>,
+    },
+}
+
+/// Message path of the runtime before [`codec`]
+mod before {
+    use std::io::BufRead;
+
+    use anyhow::Context;
+    use gossipy::{
+        admin::{self, AdminPayload},
+        batch, Event, Message,
+    };
+    use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(tag = "type")]
+    pub enum BatchPayload {
+        #[serde(rename = "__gossipy_batch")]
+        Batch { msgs: Vec<serde_json::Value> },
+    }
+
+    /// Reads all lines like the reader loop, which allocated a new string for every line
+    pub fn read_lines<Payload>(input: &[u8]) -> anyhow::Result<Vec<Event<Payload, ()>>>
+    where
+        Payload: DeserializeOwned,
+    {
+        let mut events = Vec::new();
+        for line in input.lines() {
+            let line = line.context("reading line from STDIN")?;
+            events.extend(parse_events(&line)?);
+        }
+        Ok(events)
+    }
+
+    /// Parses line received from STDIN either to an admin or to a regular message event,
+    /// batch of messages is unpacked to multiple events
+    pub fn parse_events<Payload>(line: &str) -> anyhow::Result<Vec<Event<Payload, ()>>>
+    where
+        Payload: DeserializeOwned,
+    {
+        // the line may also be a regular message that merely contains the strings
+        if line.contains(batch::BATCH_MSG_TYPE) {
+            if let Ok(msg) = serde_json::from_str::<Message<BatchPayload>>(line) {
+                let BatchPayload::Batch { msgs } = msg.body.payload;
+                return msgs
+                    .into_iter()
+                    .map(|msg| Ok(Event::Message(serde_json::from_value(msg)?)))
+                    .collect();
+            }
+        }
+        if line.contains(admin::STATE_MSG_TYPE) {
+            if let Ok(msg) = serde_json::from_str::<Message<AdminPayload>>(line) {
+                return Ok(vec![Event::Admin(msg)]);
+            }
+        }
+
+        let msg = serde_json::from_str(line)?;
+        Ok(vec![Event::Message(msg)])
+    }
+
+    /// Writes message as one line, `out` stands for STDOUT
+    pub fn write_message<T: Serialize>(msg: &T, out: &mut Vec<u8>) -> anyhow::Result<()> {
+        let mut line = serde_json::to_vec(msg).context("serializing message")?;
+        line.push(b'\n');
+
+        out.extend_from_slice(&line);
+        Ok(())
+    }
+}
+
+/// Reads all lines like the current reader loop, which reuses one string for all lines
+fn read_lines<Payload>(mut input: &[u8]) -> anyhow::Result<Vec<Event<Payload, ()>>>
+where
+    Payload: serde::de::DeserializeOwned,
+{
+    let mut events = Vec::new();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            break;
+        }
+        events.extend(codec::decode_line(&line)?);
+    }
+    Ok(events)
+}
+
+const BATCH_SIZE: usize = 32;
+
+fn message(i: usize) -> Message<Payload> {
+    Message {
+        src: "n2".to_string(),
+        dst: "n1".to_string(),
+        body: Body {
+            id: Some(gossipy::MsgId(i)),
+            in_reply_to: None,
+            payload: Payload::Broadcast {
+                message: i as isize,
+            },
+        },
+    }
+}
+
+/// Returns [`BATCH_SIZE`] messages, one per line
+fn message_lines() -> String {
+    (0..BATCH_SIZE)
+        .map(|i| serde_json::to_string(&message(i)).unwrap() + "\n")
+        .collect()
+}
+
+/// Returns one line with an envelope of [`BATCH_SIZE`] messages
+fn batch_line() -> String {
+    let msgs: Vec<_> = (0..BATCH_SIZE)
+        .map(|i| serde_json::to_value(message(i)).unwrap())
+        .collect();
+    let line = serde_json::json!({
+        "src": "n2",
+        "dest": "n1",
+        "body": { "type": "__gossipy_batch", "msgs": msgs },
+    });
+    line.to_string() + "\n"
+}
+
+fn decode(c: &mut Criterion) {
+    for (name, input) in [
+        ("decode_message", message_lines()),
+        ("decode_batch", batch_line()),
+    ] {
+        let mut group = c.benchmark_group(name);
+        group.throughput(Throughput::Elements(BATCH_SIZE as u64));
+        group.bench_function("before", |b| {
+            b.iter(|| before::read_lines::<Payload>(input.as_bytes()).unwrap())
+        });
+        group.bench_function("codec", |b| {
+            b.iter(|| read_lines::<Payload>(input.as_bytes()).unwrap())
+        });
+        group.finish();
+    }
+}
+
+fn encode(c: &mut Criterion) {
+    let msgs: Vec<_> = (0..BATCH_SIZE).map(message).collect();
+    let mut group = c.benchmark_group("encode");
+    group.throughput(Throughput::Elements(BATCH_SIZE as u64));
+    group.bench_function("before", |b| {
+        b.iter_batched_ref(
+            Vec::new,
+            |out| {
+                out.clear();
+                for msg in msgs.iter() {
+                    before::write_message(msg, out).unwrap();
+                }
+                out.len()
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("codec", |b| {
+        b.iter_batched_ref(
+            || (Vec::new(), Vec::new()),
+            |(buf, out)| {
+                out.clear();
+                for msg in msgs.iter() {
+                    codec::encode_line(msg, buf).unwrap();
+                    out.extend_from_slice(buf);
+                }
+                out.len()
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, decode, encode);
+criterion_main!(benches);
@@ -0,0 +1,28 @@
+#![no_main]
+
+use std::time::Duration;
+
+use gossipy::broadcast::{AntiEntropy, BroadcastHandler, Command, Mode, INITIAL_INTERVAL};
+use gossipy::failure::DetectorConfig;
+use gossipy::pacing::{Pacer, PacingConfig};
+use gossipy::{Handler, Node};
+
+libfuzzer_sys::fuzz_target!(|data: &[u8]| {
+    let node: Node<Command> = gossipy_fuzz::node();
+    // plumtree mode exercises the pushes, the tree and the periodic gossip,
+    // digests exercise the reconciliation of sync messages
+    let mut handler = BroadcastHandler::new(Mode::Plumtree, AntiEntropy::Digests, INITIAL_INTERVAL);
+    handler.set_detector(DetectorConfig::new(Duration::from_millis(100)));
+    // pacing exercises reduced fanout
+    handler.set_pacer(Pacer::new(PacingConfig::new(25.0), INITIAL_INTERVAL));
+    for line in gossipy_fuzz::lines(data) {
+        gossipy_fuzz::handle(&node, &mut handler, line);
+        // gossip round after every message
+        handler
+            .handle_command(Command::SendGossip, node.clone())
+            .expect("handling command");
+        handler
+            .handle_command(Command::Heartbeat, node.clone())
+            .expect("handling command");
+    }
+});
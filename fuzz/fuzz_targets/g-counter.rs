@@ -0,0 +1,15 @@
+#![no_main]
+#![allow(dead_code)]
+
+include!("../../src/bin/g-counter.rs");
+
+libfuzzer_sys::fuzz_target!(|data: &[u8]| {
+    let node: Node<Command> = gossipy_fuzz::node();
+    let mut handler = GCounter::default();
+    handler
+        .handle_command(Command::InitStore, node.clone())
+        .expect("handling command");
+    for line in gossipy_fuzz::lines(data) {
+        gossipy_fuzz::handle(&node, &mut handler, line);
+    }
+});
@@ -0,0 +1,12 @@
+#![no_main]
+#![allow(dead_code)]
+
+include!("../../src/bin/kafka-multi-node.rs");
+
+libfuzzer_sys::fuzz_target!(|data: &[u8]| {
+    let node: Node = gossipy_fuzz::node();
+    let mut handler = KafkaLog::default();
+    for line in gossipy_fuzz::lines(data) {
+        gossipy_fuzz::handle(&node, &mut handler, line);
+    }
+});
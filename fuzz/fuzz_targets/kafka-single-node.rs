@@ -0,0 +1,18 @@
+#![no_main]
+#![allow(dead_code)]
+
+include!("../../src/bin/kafka-single-node.rs");
+
+libfuzzer_sys::fuzz_target!(|data: &[u8]| {
+    let node: Node = gossipy_fuzz::node();
+    // two partitions, so the logs of different keys are split
+    let partitions = (0..2)
+        .map(|_| Mutex::new(Persisted::in_memory(Logs::default())))
+        .collect();
+    let mut handler = KafkaLog {
+        partitions: Arc::new(partitions),
+    };
+    for line in gossipy_fuzz::lines(data) {
+        gossipy_fuzz::handle(&node, &mut handler, line);
+    }
+});
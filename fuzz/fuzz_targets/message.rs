@@ -0,0 +1,31 @@
+//! Decoding of the message envelope, batches and admin messages
+
+#![no_main]
+
+use gossipy::{codec, Event, Message};
+use libfuzzer_sys::fuzz_target;
+use serde_json::Value;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(line) = std::str::from_utf8(data) else {
+        return;
+    };
+    let Ok(events) = codec::decode_line::<Value, ()>(line) else {
+        return;
+    };
+
+    let mut buf = Vec::new();
+    for event in events {
+        let Event::Message(msg) = event else {
+            continue;
+        };
+        // every decoded message survives encoding
+        codec::encode_line(&msg, &mut buf).expect("encoding decoded message");
+        let decoded: Message<Value> =
+            serde_json::from_slice(&buf).expect("decoding encoded message");
+        assert_eq!(
+            serde_json::to_value(&decoded).expect("serializing message"),
+            serde_json::to_value(&msg).expect("serializing message"),
+        );
+    }
+});
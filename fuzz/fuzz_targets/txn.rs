@@ -0,0 +1,19 @@
+#![no_main]
+#![allow(dead_code)]
+
+include!("../../src/bin/txn.rs");
+
+libfuzzer_sys::fuzz_target!(|data: &[u8]| {
+    let node: Node<Command> = gossipy_fuzz::node();
+    let (tx, rx) = std::sync::mpsc::channel();
+    let mut handler = TxnHandler::new(Persisted::in_memory(Store::default()), tx);
+    for line in gossipy_fuzz::lines(data) {
+        gossipy_fuzz::handle(&node, &mut handler, line);
+        // replicate the changes right away
+        while let Ok(cmd) = rx.try_recv() {
+            handler
+                .handle_command(cmd, node.clone())
+                .expect("handling command");
+        }
+    }
+});
@@ -0,0 +1,15 @@
+#![no_main]
+#![allow(dead_code)]
+
+include!("../../src/bin/unique-ids.rs");
+
+libfuzzer_sys::fuzz_target!(|data: &[u8]| {
+    let node: Node = gossipy_fuzz::node();
+    let mut handler = GenerateIdHandler {
+        id: 1,
+        reserved: Persisted::in_memory(Reservation::default()),
+    };
+    for line in gossipy_fuzz::lines(data) {
+        gossipy_fuzz::handle(&node, &mut handler, line);
+    }
+});
@@ -0,0 +1,34 @@
+//! Helpers shared by the fuzz targets
+//!
+//! Handler targets include the source of the challenge binary, create the handler the way its `main` does
+//! and feed it the fuzzed input line by line through [`handle`]. Malformed input must be answered with
+//! a Maelstrom error or logged and dropped: an error returned by the handler stops the node like a panic,
+//! so both are failures.
+
+use gossipy::{Handler, Node};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Creates node `n1` of a three-node cluster
+pub fn node<Command: Clone>() -> Node<Command> {
+    let node_ids = ["n1", "n2", "n3"].map(String::from);
+    Node::with_ids("n1", &node_ids).expect("creating node")
+}
+
+/// Splits fuzzed input into lines received from STDIN
+pub fn lines(data: &[u8]) -> impl Iterator<Item = &str> {
+    data.split(|b| *b == b'\n')
+        .filter_map(|line| std::str::from_utf8(line).ok())
+        .filter(|line| !line.trim().is_empty())
+}
+
+/// Feeds the line to the handler, panics if the handler returns an error
+pub fn handle<H, Payload, Command>(node: &Node<Command>, handler: &mut H, line: &str)
+where
+    H: Handler<Payload, Command>,
+    Payload: Serialize + DeserializeOwned,
+    Command: Clone,
+{
+    if let Err(err) = node.handle_line(handler, line) {
+        panic!("handler failed on {line}: {err:?}");
+    }
+}
@@ -0,0 +1,189 @@
+//! Outbound message coalescing
+//!
+//! Opt-in batching of node-to-node traffic. Messages sent to other nodes of the cluster are collected
+//! per destination and every batching window all collected messages for the same node are sent
+//! as one [`BATCH_MSG_TYPE`] envelope. The receiving runtime unpacks the envelope and handles
+//! the messages one by one in the original order, so handlers do not know about batching.
+//!
+//! Only messages to the nodes listed in `init` are batched, messages to clients and Maelstrom services
+//! (eg. `lin-kv`) are sent immediately, because they would not understand the envelope.
+//!
+//! Batching is enabled with [`Node::set_batch_config`](crate::Node::set_batch_config)
+//! or with environment variables:
+//! - `GOSSIPY_BATCH_WINDOW_MS` - batching window, batching is disabled if not set or `0`
+//! - `GOSSIPY_BATCH_MAX` - maximum number of messages in one envelope (default 64),
+//!   full batch is sent immediately
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Condvar, Mutex},
+    time::Duration,
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+
+use crate::{codec, metrics::Metrics};
+
+/// Type of the envelope message carrying a batch of messages
+pub const BATCH_MSG_TYPE: &str = "__gossipy_batch";
+
+/// Received envelope, the messages are borrowed from the received line
+#[derive(Deserialize)]
+pub(crate) struct Envelope<'a> {
+    #[serde(borrow)]
+    pub(crate) body: EnvelopeBody<'a>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct EnvelopeBody<'a> {
+    #[serde(rename = "type")]
+    pub(crate) ty: &'a str,
+    #[serde(borrow)]
+    pub(crate) msgs: Vec<&'a RawValue>,
+}
+
+/// Envelope to be sent
+#[derive(Serialize)]
+struct OutgoingEnvelope<'a> {
+    src: &'a str,
+    dest: &'a str,
+    body: OutgoingEnvelopeBody<'a>,
+}
+
+#[derive(Serialize)]
+struct OutgoingEnvelopeBody<'a> {
+    #[serde(rename = "type")]
+    ty: &'static str,
+    msgs: &'a [Box<RawValue>],
+}
+
+/// Batching configuration
+#[derive(Debug, Clone, Copy)]
+pub struct BatchConfig {
+    /// How long messages wait for other messages to the same node
+    pub window: Duration,
+    /// Maximum number of messages in one envelope
+    pub max_messages: usize,
+}
+
+impl BatchConfig {
+    /// Creates configuration with default maximum batch size
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            max_messages: 64,
+        }
+    }
+
+    /// Reads configuration from environment variables,
+    /// returns `None` if the batching is not enabled
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Ok(window) = std::env::var("GOSSIPY_BATCH_WINDOW_MS") else {
+            return Ok(None);
+        };
+        let window: u64 = window.parse().context("parsing GOSSIPY_BATCH_WINDOW_MS")?;
+        if window == 0 {
+            return Ok(None);
+        }
+
+        let mut config = Self::new(Duration::from_millis(window));
+        if let Ok(max) = std::env::var("GOSSIPY_BATCH_MAX") {
+            config.max_messages = max.parse().context("parsing GOSSIPY_BATCH_MAX")?;
+        }
+
+        Ok(Some(config))
+    }
+}
+
+#[derive(Default)]
+struct Pending {
+    /// dst => encoded messages waiting to be sent
+    batches: HashMap<String, Vec<Box<RawValue>>>,
+    stopped: bool,
+}
+
+/// Collects outbound messages and sends them in batches
+pub(crate) struct Batcher {
+    config: BatchConfig,
+    /// Our node ID used as a source of envelopes
+    src: String,
+    pending: Mutex<Pending>,
+    stop: Condvar,
+    metrics: Arc<Metrics>,
+}
+
+impl Batcher {
+    pub(crate) fn new(config: BatchConfig, src: String, metrics: Arc<Metrics>) -> Self {
+        Self {
+            config,
+            src,
+            pending: Mutex::new(Pending::default()),
+            stop: Condvar::new(),
+            metrics,
+        }
+    }
+
+    /// Adds message to the batch for its destination, sends the batch if it is full
+    pub(crate) fn push<T: Serialize>(&self, dst: &str, msg: &T) -> anyhow::Result<()> {
+        let raw = codec::encode_raw(msg)?;
+
+        let mut pending = self.pending.lock().expect("lock");
+        let batch = pending.batches.entry(dst.to_string()).or_default();
+        batch.push(raw);
+        if batch.len() >= self.config.max_messages {
+            let batch = std::mem::take(batch);
+            // written under the lock, so the batches for the same node are not reordered
+            self.send(dst, batch)?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends collected batches every batching window until stopped
+    pub(crate) fn run(&self) -> anyhow::Result<()> {
+        let mut pending = self.pending.lock().expect("lock");
+        loop {
+            (pending, _) = self
+                .stop
+                .wait_timeout(pending, self.config.window)
+                .expect("lock");
+
+            for (dst, batch) in pending.batches.iter_mut() {
+                if !batch.is_empty() {
+                    self.send(dst, std::mem::take(batch))?;
+                }
+            }
+
+            if pending.stopped {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Sends remaining batches and stops [`Batcher::run`]
+    pub(crate) fn stop(&self) {
+        self.pending.lock().expect("lock").stopped = true;
+        self.stop.notify_all();
+    }
+
+    fn send(&self, dst: &str, batch: Vec<Box<RawValue>>) -> anyhow::Result<()> {
+        if let [msg] = batch.as_slice() {
+            // there is nothing to coalesce
+            return codec::write_message(msg);
+        }
+
+        self.metrics.batch_sent(batch.len());
+        let envelope = OutgoingEnvelope {
+            src: &self.src,
+            dest: dst,
+            body: OutgoingEnvelopeBody {
+                ty: BATCH_MSG_TYPE,
+                msgs: &batch,
+            },
+        };
+
+        codec::write_message(&envelope)
+    }
+}
@@ -1,167 +1,88 @@
-use std::{
-    collections::{HashMap, HashSet},
-    thread::JoinHandle,
-};
+use std::time::Duration;
 
 use anyhow::Context;
-use gossipy::{Handler, Message, Node};
-use serde::{Deserialize, Serialize};
+use gossipy::broadcast::{AntiEntropy, BroadcastHandler, Command, Mode, INITIAL_INTERVAL};
+use gossipy::crdt::RangeSet;
+use gossipy::failure::DetectorConfig;
+use gossipy::gossip;
+use gossipy::pacing::{Pacer, PacingConfig};
+use gossipy::persist::{self, Storage};
+use gossipy::Node;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "snake_case", tag = "type")]
-enum Payload {
-    Broadcast {
-        message: isize,
-    },
-    BroadcastOk,
-    Read,
-    ReadOk {
-        messages: HashSet<isize>,
-    },
-    Topology {
-        topology: HashMap<String, Vec<String>>,
-    },
-    TopologyOk,
-    Gossip {
-        have: HashSet<isize>,
-    },
-    GossipOk {
-        have: HashSet<isize>,
-    },
-}
-
-#[derive(Debug, Clone)]
-enum Command {
-    SendGossip,
-}
-
-/// Multi-Node Broadcast system
-struct BroadcastHandler {
-    messages: HashSet<isize>,
-    topology: HashMap<String, Vec<String>>,
-    neighbours: Vec<String>,
-    others_know: HashMap<String, HashSet<isize>>,
-}
-
-impl Handler<Payload, Command> for BroadcastHandler {
-    fn handle(&mut self, msg: Message<Payload>, mut node: Node<Command>) -> anyhow::Result<()>
-    where
-        Payload: Serialize,
-    {
-        let reply = match msg.body.payload {
-            Payload::Broadcast { message } => {
-                self.messages.insert(message);
-
-                Payload::BroadcastOk
-            }
-            Payload::Gossip { ref have } => {
-                // add to what we know
-                self.messages.extend(have.iter());
-                // update what neighbour already knows
-                self.others_know
-                    .get_mut(&msg.src)
-                    .expect("message from unknown node in the cluster")
-                    .extend(have.iter());
-                // ackowledge what we just have received
-                Payload::GossipOk { have: have.clone() }
-            }
-            Payload::Read {} => Payload::ReadOk {
-                messages: self.messages.clone(),
-            },
-            Payload::ReadOk { messages } => {
-                self.others_know.insert(msg.src, messages);
-                return Ok(());
-            }
-            Payload::Topology { ref topology } => {
-                self.topology = topology.clone();
-                self.neighbours = self
-                    .topology
-                    .get(&node.id())
-                    .expect("our node must be included in topology map")
-                    .clone();
+fn main() -> anyhow::Result<()> {
+    // gossip interval in milliseconds is fixed if given (0 disables the periodic gossip),
+    // adapted to the load otherwise
+    let fixed_interval: Option<u64> = std::env::args()
+        .nth(1)
+        .map(|interval| interval.parse())
+        .transpose()
+        .context("parsing gossip interval")?;
+    let pacer = match fixed_interval {
+        Some(_) => None,
+        None => Some(Pacer::new(PacingConfig::from_env()?, INITIAL_INTERVAL)),
+    };
+    let gossip_interval = fixed_interval
+        .map(Duration::from_millis)
+        .unwrap_or(INITIAL_INTERVAL);
+    let mode = Mode::from_env()?;
+    let anti_entropy = AntiEntropy::from_env()?;
+    let detector = DetectorConfig::from_env()?;
+    let pacing = if pacer.is_some() { "adaptive" } else { "fixed" };
+    eprintln!(
+        "Using {pacing} gossip interval {gossip_interval:?}, mode {mode:?}, anti-entropy {anti_entropy:?}"
+    );
 
-                Payload::TopologyOk
-            }
-            Payload::BroadcastOk | Payload::TopologyOk => return Ok(()), // we ignore these messages
-            Payload::GossipOk { ref have } => {
-                // add to what the neighbour acknowledged to know
-                self.others_know
-                    .get_mut(&msg.src)
-                    .expect("message from unknown node in the cluster")
-                    .extend(have.iter());
-                return Ok(());
-            }
-        };
+    let mut node = Node::new()?;
 
-        node.reply(msg, reply)
+    let mut broadcast_handler = BroadcastHandler::new(mode, anti_entropy, gossip_interval);
+    if let Some(config) = detector {
+        broadcast_handler.set_detector(config);
     }
-
-    fn handle_command(&mut self, cmd: Command, mut node: Node<Command>) -> anyhow::Result<()> {
-        for neighbour in self.neighbours.iter() {
-            match cmd {
-                Command::SendGossip => {
-                    let mut we_know = HashSet::new();
-                    if let Some(neighbours_know) = self.others_know.get(neighbour) {
-                        // do not send what neighbour already knows
-                        we_know = self
-                            .messages
-                            .iter()
-                            .filter(|m| !neighbours_know.contains(m))
-                            .copied()
-                            .collect();
-                    }
-
-                    if we_know.is_empty() {
-                        // neighbour already knows about all our messages => do not send
-                        continue;
-                    }
-
-                    node.send_to(neighbour, Payload::Gossip { have: we_know })?;
-                }
-            }
-        }
-
-        Ok(())
+    if let Some(pacer) = pacer {
+        broadcast_handler.set_pacer(pacer);
     }
-}
-
-fn main() -> anyhow::Result<()> {
-    let mut args = std::env::args();
-    let gossip_interval = args.nth(1).unwrap_or("200".to_string()).parse()?;
-    eprintln!("Using gossip interval {} ms", gossip_interval);
-
-    let mut node = Node::new()?;
 
-    let broadcast_handler = BroadcastHandler {
-        messages: HashSet::new(),
-        topology: HashMap::new(),
-        neighbours: Vec::new(),
-        others_know: node
-            // preallocate with all the nodes in the cluster
-            .node_ids()
-            .iter()
-            .map(|id| (id.clone(), HashSet::new()))
-            .collect(),
-    };
+    if let Some(config) = persist::Config::from_env(&node.id())? {
+        let mut storage = Storage::open(config)?;
+        let mut messages = RangeSet::new();
+        let replayed = storage.recover(&mut messages)?;
+        eprintln!(
+            "INFO: recovered {} messages, {replayed} WAL records replayed",
+            messages.len()
+        );
+        broadcast_handler.set_storage(storage, &messages);
+    }
 
     let (tx, rx) = std::sync::mpsc::channel::<Command>();
 
     node.register_command_receiver(rx);
 
-    let jh: JoinHandle<Result<_, anyhow::Error>> = std::thread::spawn(move || {
-        // periodically gossip new messages to the other nodes in the cluster
-        loop {
-            std::thread::sleep(std::time::Duration::from_millis(gossip_interval));
-            tx.send(Command::SendGossip)
-                .context("sending Gossip command")?;
-        }
-    });
+    // periodically send heartbeats to the neighbours, if the failure detection is enabled
+    let heartbeats = detector
+        .map(|config| gossip::spawn_interval(config.interval, tx.clone(), Command::Heartbeat));
+
+    // periodically gossip new messages to the other nodes in the cluster
+    let jh = match fixed_interval {
+        Some(0) => None,
+        _ => Some(gossip::spawn_paced(
+            broadcast_handler.gossip_interval(),
+            tx,
+            Command::SendGossip,
+        )),
+    };
 
     node.run(broadcast_handler)?;
 
-    jh.join()
-        .expect("could not join gossip command thread")
-        .context("gossip command thread errored")?;
+    if let Some(jh) = jh {
+        jh.join()
+            .expect("could not join gossip command thread")
+            .context("gossip command thread errored")?;
+    }
+    if let Some(jh) = heartbeats {
+        jh.join()
+            .expect("could not join heartbeat command thread")
+            .context("heartbeat command thread errored")?;
+    }
 
     Ok(())
 }
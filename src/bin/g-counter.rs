@@ -4,9 +4,9 @@ use std::{
     time::{SystemTime, UNIX_EPOCH},
 };
 
-use anyhow::{bail, Context};
+use anyhow::Context;
 use gossipy::kv_store::{ERROR_PRECONDITION_FAILED, SEQ_KV_SERVICE_ID};
-use gossipy::{Handler, Message, Node};
+use gossipy::{error, Handler, Message, MsgId, Node, RequestKey};
 use serde::{Deserialize, Serialize};
 
 const COUNTER_KEY: &str = "g-counter";
@@ -48,15 +48,22 @@ enum Command {
     InitStore,
 }
 
+/// Delta added by a client, it gets `add_ok` when the delta is written to KV store
+#[derive(Debug, Serialize)]
+struct Add {
+    orig: RequestKey,
+    delta: usize,
+}
+
 /// Stateless Grow-Only Counter
 #[derive(Default)]
 struct GCounter {
     /// Messages sent to KV store
-    //    kv_msg_id => (orig_msg_src, orig_msg_id)
-    kv_msg_ids: HashMap<usize, (String, usize)>,
+    //    kv_msg_id => original request
+    kv_msg_ids: HashMap<MsgId, RequestKey>,
     /// Delta values added by clients
-    //    kv_msg_id => delta
-    deltas: HashMap<usize, usize>,
+    //    kv_msg_id => add request
+    deltas: HashMap<MsgId, Add>,
 }
 
 impl Handler<Payload, Command> for GCounter {
@@ -64,18 +71,28 @@ impl Handler<Payload, Command> for GCounter {
     where
         Payload: Serialize,
     {
-        let reply = match msg.body.payload {
+        match msg.body.payload {
             Payload::Add { delta } => {
+                let Some(orig) = RequestKey::of(&msg) else {
+                    return node.reply_error(msg, error::MALFORMED_REQUEST, "msg_id must be set");
+                };
+
                 // Read from KV store and later handle ReadOk message returned by KV store
                 let read = KvStorePayload::Read { key: COUNTER_KEY };
                 let kv_msg_id = node
                     .send_to(SEQ_KV_SERVICE_ID, read)
                     .context("read from kv store")?;
 
-                self.deltas.insert(kv_msg_id, delta);
-                Payload::AddOk
+                self.deltas.insert(kv_msg_id, Add { orig, delta });
+                Ok(())
             }
             Payload::Read => {
+                // Store the original message to be able to respond back
+                // when we receive the response message from the KV store
+                let Some(orig) = RequestKey::of(&msg) else {
+                    return node.reply_error(msg, error::MALFORMED_REQUEST, "msg_id must be set");
+                };
+
                 // Write timestamp to force the KV store read newest value,
                 // ie. to prevent Stale Read which is permitted in sequentially consistent system
                 // (https://jepsen.io/consistency/phenomena/stale-read)
@@ -94,19 +111,18 @@ impl Handler<Payload, Command> for GCounter {
                     .send_to(SEQ_KV_SERVICE_ID, payload)
                     .context("read from kv store")?;
 
-                // Store the original message to be able to respond back
-                // when we receive the response message from the KV store
-                let orig_msg_src = msg.src;
-                let orig_msg_id = msg.body.id.expect("message id must be set");
-                self.kv_msg_ids
-                    .insert(kv_msg_id, (orig_msg_src, orig_msg_id));
+                self.kv_msg_ids.insert(kv_msg_id, orig);
 
-                return Ok(());
+                Ok(())
             }
             Payload::ReadOk { value } => {
-                let kv_read_msg_id = msg.body.in_reply_to.expect("in_reply_to must be set");
+                let Some(kv_read_msg_id) = msg.body.in_reply_to else {
+                    return ignore_malformed_reply(&msg);
+                };
 
-                if let Some((orig_src, orig_msg_id)) = self.kv_msg_ids.remove(&kv_read_msg_id) {
+                if let Some(RequestKey(orig_src, orig_msg_id)) =
+                    self.kv_msg_ids.remove(&kv_read_msg_id)
+                {
                     // this is reply from kv_store => send reply back to the client
                     // that asked for the value of the counter
                     let mut fake_orig_msg = msg;
@@ -117,55 +133,69 @@ impl Handler<Payload, Command> for GCounter {
                 } else {
                     // this is reply to our request to kv_store when client wanted to add delta
                     // and we read current value of the counter
-                    if let Some(delta) = self.deltas.remove(&kv_read_msg_id) {
-                        let cas = KvStorePayload::Cas {
-                            key: COUNTER_KEY,
-                            from: value,
-                            to: value + delta,
-                            create_if_not_exists: false,
-                        };
-
-                        let kv_cas_msg_id = node
-                            .send_to(SEQ_KV_SERVICE_ID, cas)
-                            .context("add to kv store")?;
-
-                        self.deltas.insert(kv_cas_msg_id, delta);
-                    } else {
-                        bail!("msg_id {} is missing in deltas", kv_read_msg_id)
-                    }
+                    let Some(add) = self.deltas.remove(&kv_read_msg_id) else {
+                        return ignore_unexpected_reply(&msg);
+                    };
+                    let Some(to) = value.checked_add(add.delta) else {
+                        let text = format!("adding {} to {value} would overflow", add.delta);
+                        return reply_error_to(&mut node, add.orig, error::ABORT, &text);
+                    };
+                    let cas = KvStorePayload::Cas {
+                        key: COUNTER_KEY,
+                        from: value,
+                        to,
+                        create_if_not_exists: false,
+                    };
+
+                    let kv_cas_msg_id = node
+                        .send_to(SEQ_KV_SERVICE_ID, cas)
+                        .context("add to kv store")?;
+
+                    self.deltas.insert(kv_cas_msg_id, add);
                 }
-                return Ok(());
+                Ok(())
             }
             Payload::CasOk => {
-                // housekeeping
-                let cas_msg_id = msg.body.in_reply_to.expect("in_reply_to must be set");
-                self.deltas.remove(&cas_msg_id);
-                return Ok(());
+                let Some(cas_msg_id) = msg.body.in_reply_to else {
+                    return ignore_malformed_reply(&msg);
+                };
+                // the delta was added, the initialization of the store has no request
+                if let Some(Add { orig, .. }) = self.deltas.remove(&cas_msg_id) {
+                    let mut fake_orig_msg = msg;
+                    fake_orig_msg.src = orig.0;
+                    fake_orig_msg.body.id = Some(orig.1);
+                    node.reply(fake_orig_msg, Payload::AddOk)?;
+                }
+                Ok(())
             }
             Payload::Error { code, ref text } => {
                 if code == ERROR_PRECONDITION_FAILED {
                     // CAS operation failed (outdated 'from' value caused by stale read) => retry again
                     eprintln!("INFO: CAS operation failed: '{}', retrying", text);
 
-                    let kv_msg_id = msg.body.in_reply_to.expect("in_reply_to must be set");
-                    if let Some(delta) = self.deltas.remove(&kv_msg_id) {
+                    let Some(kv_msg_id) = msg.body.in_reply_to else {
+                        return ignore_malformed_reply(&msg);
+                    };
+                    if let Some(add) = self.deltas.remove(&kv_msg_id) {
                         let read = KvStorePayload::Read { key: COUNTER_KEY };
                         let kv_msg_id = node
                             .send_to(SEQ_KV_SERVICE_ID, read)
                             .context("read from kv store")?;
 
-                        self.deltas.insert(kv_msg_id, delta);
+                        self.deltas.insert(kv_msg_id, add);
                     }
                 } else {
-                    // other error encountered
+                    // other error encountered, the client of the add request gets it too
                     eprintln!("Error: {:?}", msg.body);
+                    let add = msg.body.in_reply_to.and_then(|id| self.deltas.remove(&id));
+                    if let Some(add) = add {
+                        return reply_error_to(&mut node, add.orig, code, text);
+                    }
                 }
-                return Ok(());
+                Ok(())
             }
-            Payload::AddOk | Payload::WriteOk => return Ok(()), // we do not care about these messages
-        };
-
-        node.reply(msg, reply)
+            Payload::AddOk | Payload::WriteOk => Ok(()), // we do not care about these messages
+        }
     }
 
     fn handle_command(&mut self, cmd: Command, mut node: Node<Command>) -> anyhow::Result<()> {
@@ -185,6 +215,42 @@ impl Handler<Payload, Command> for GCounter {
             }
         }
     }
+
+    fn debug_state(&self) -> Option<serde_json::Value> {
+        Some(serde_json::json!({
+            "pending_reads": self.kv_msg_ids,
+            "pending_deltas": self.deltas,
+        }))
+    }
+}
+
+/// Logs and drops reply of the KV store that does not say which request it answers,
+/// only requests are answered with an error
+fn ignore_malformed_reply(msg: &Message<Payload>) -> anyhow::Result<()> {
+    eprintln!(
+        "WARN: ignoring malformed message: in_reply_to must be set: {:?}",
+        msg.body
+    );
+    Ok(())
+}
+
+/// Logs and drops reply of the KV store to a request we do not wait for, eg. a duplicate reply
+fn ignore_unexpected_reply(msg: &Message<Payload>) -> anyhow::Result<()> {
+    eprintln!("WARN: ignoring unexpected reply: {:?}", msg.body);
+    Ok(())
+}
+
+/// Answers the client request with an error
+fn reply_error_to(
+    node: &mut Node<Command>,
+    orig: RequestKey,
+    code: u16,
+    text: &str,
+) -> anyhow::Result<()> {
+    let mut fake_orig_msg: Message<Payload> = Message::new_empty(Payload::AddOk);
+    fake_orig_msg.src = orig.0;
+    fake_orig_msg.body.id = Some(orig.1);
+    node.reply_error(fake_orig_msg, code, text)
 }
 
 fn main() -> anyhow::Result<()> {
@@ -0,0 +1,56 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, Context};
+use gossipy::checker::{self, ConsistencyModel};
+use gossipy::history;
+use gossipy::runner::{self, Config};
+
+const CHECK_USAGE: &str = "\
+Usage: gossipy-run check -w <workload> <history.jsonl> [--consistency-models <model>]";
+
+fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.is_empty() || args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        println!("{}\n\n{CHECK_USAGE}", runner::USAGE);
+        return Ok(());
+    }
+    if args[0] == "check" {
+        return check(&args[1..]);
+    }
+
+    let config = Config::from_args(args)?;
+    let summary = runner::run(&config)?;
+    println!("{}", serde_json::to_string_pretty(&summary)?);
+    if !summary.valid {
+        bail!("analysis invalid, see {}", summary.results.display());
+    }
+    Ok(())
+}
+
+/// Checks previously recorded history
+fn check(args: &[String]) -> anyhow::Result<()> {
+    let mut workload = None;
+    let mut model = ConsistencyModel::ReadCommitted;
+    let mut path = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-w" | "--workload" => workload = args.next(),
+            "--consistency-models" => {
+                model = args.next().context(CHECK_USAGE)?.parse()?;
+            }
+            _ => path = Some(PathBuf::from(arg)),
+        }
+    }
+    let (Some(workload), Some(path)) = (workload, path) else {
+        bail!(CHECK_USAGE);
+    };
+
+    let history = history::read(&path)?;
+    let results = checker::check(workload, &history, model)?;
+    print!("{}", checker::to_edn(&serde_json::to_value(&results)?));
+    if !results.valid {
+        bail!("analysis invalid");
+    }
+    Ok(())
+}
@@ -1,10 +1,10 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{hash_map::Entry, HashMap, HashSet};
 
-use anyhow::{bail, Context};
+use anyhow::Context;
 use gossipy::kv_store::{
     ERROR_KEY_DOES_NOT_EXIST, ERROR_PRECONDITION_FAILED, LIN_KV_SERVICE_ID, SEQ_KV_SERVICE_ID,
 };
-use gossipy::{Handler, Message, Node};
+use gossipy::{error, Handler, Message, MsgId, Node, RequestKey};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -65,17 +65,17 @@ pub enum KvStorePayload {
 }
 
 struct SendEntry {
-    orig_msg_id: usize,
-    orig_msg_src: String,
+    orig: RequestKey,
     key: String,
     offset: usize,
     msg: u64,
 }
 
+/// Maximum number of messages of a key returned by one poll, every message is read from lin-kv separately
+const MAX_POLLED_MESSAGES: usize = 100;
+
 struct PollEntry {
-    orig_msg_id: usize,
-    orig_msg_src: String,
-    requested_key_offsets: HashMap<String, usize>,
+    orig: RequestKey,
     key: String,
     offset: usize,
     max_offset: usize,
@@ -102,8 +102,7 @@ struct OffsetMsg {
 }
 
 struct CommitOffsetEntry {
-    orig_msg_id: usize,
-    orig_msg_src: String,
+    orig: RequestKey,
     key: String,
     requested_keys_count: usize,
 }
@@ -128,27 +127,25 @@ struct KafkaLog {
     node: Option<Node>,
 
     /// msg_id => SendEntry
-    send_entries: HashMap<usize, SendEntry>,
+    send_entries: HashMap<MsgId, SendEntry>,
     /// msg_id => offset key
-    send_offset_reads: HashMap<usize, String>,
+    send_offset_reads: HashMap<MsgId, String>,
     /// msg_id => (offset key, offset)
-    offset_updates: HashMap<usize, (String, usize)>,
+    offset_updates: HashMap<MsgId, (String, usize)>,
 
     /// msg_id => PollEntry
-    poll_entries: HashMap<usize, PollEntry>,
+    poll_entries: HashMap<MsgId, PollEntry>,
     /// msg_id => offset
-    poll_offset_reads: HashMap<usize, usize>,
-    /// "src-msg_id" => PolledMessages
-    polled_messages: HashMap<String, PolledMessages>,
+    poll_offset_reads: HashMap<MsgId, usize>,
+    polled_messages: HashMap<RequestKey, PolledMessages>,
 
     /// msg_id => CommitOffsetEntry
-    commit_entries: HashMap<usize, CommitOffsetEntry>,
+    commit_entries: HashMap<MsgId, CommitOffsetEntry>,
     /// msg_id => CommitOffsetEntry
-    committed_offset_reads: HashMap<usize, CommitOffsetEntry>,
-    /// "src-msg_id" => count
-    committed_offsets_written: HashMap<String, usize>,
-    /// "src-msg_id" => {key => offset}
-    list_committed_offsets: HashMap<String, CommittedOffsets>,
+    committed_offset_reads: HashMap<MsgId, CommitOffsetEntry>,
+    /// request => count
+    committed_offsets_written: HashMap<RequestKey, usize>,
+    list_committed_offsets: HashMap<RequestKey, CommittedOffsets>,
 }
 
 impl Handler<Payload> for KafkaLog {
@@ -167,6 +164,12 @@ impl Handler<Payload> for KafkaLog {
                 // 3) write one new log entry 'entry-key-offset' => msg
                 // 4) reply send_ok
 
+                // Store the original message to be able to respond back
+                // when we process the response message from the KV store
+                let Some(orig) = RequestKey::of(&message) else {
+                    return self.reject(message, "msg_id must be set");
+                };
+
                 // Send 1) read latest offset for the key
                 // Send Read msg to KV store and later handle ReadOk message returned by KV store
                 let kv_offset_key = self.offset_key(key);
@@ -180,16 +183,10 @@ impl Handler<Payload> for KafkaLog {
 
                 self.send_offset_reads.insert(read_msg_id, kv_offset_key);
 
-                // Store the original message to be able to respond back
-                // when we process the response message from the KV store
-                let orig_msg_src = message.src;
-                let orig_msg_id = message.body.id.expect("message id must be set");
-
                 self.send_entries.insert(
                     read_msg_id,
                     SendEntry {
-                        orig_msg_id,
-                        orig_msg_src,
+                        orig,
                         key: key.to_string(),
                         offset: 0, // unknown offset
                         msg,
@@ -219,8 +216,9 @@ impl Handler<Payload> for KafkaLog {
 
                 // Store the original message to be able to respond back
                 // when we process the response message from the KV store
-                let orig_msg_id = message.body.id.expect("message id must be set");
-                let orig_msg_src = message.src;
+                let Some(orig) = RequestKey::of(&message) else {
+                    return self.reject(message, "msg_id must be set");
+                };
 
                 // initialize empty container for polled messages
                 let mut messages = HashMap::new();
@@ -228,9 +226,8 @@ impl Handler<Payload> for KafkaLog {
                     messages.insert(key.clone(), vec![]);
                 }
 
-                let k = format!("{}-{}", orig_msg_src, orig_msg_id);
                 self.polled_messages.insert(
-                    k,
+                    orig.clone(),
                     PolledMessages {
                         messages,
                         requested_keys: offsets.keys().cloned().collect(),
@@ -260,9 +257,7 @@ impl Handler<Payload> for KafkaLog {
                     self.poll_entries.insert(
                         read_msg_id,
                         PollEntry {
-                            orig_msg_id,
-                            orig_msg_src: orig_msg_src.clone(),
-                            requested_key_offsets: offsets.clone(),
+                            orig: orig.clone(),
                             key: key.to_string(),
                             offset: 0,
                             max_offset: 0,
@@ -274,11 +269,11 @@ impl Handler<Payload> for KafkaLog {
             }
 
             Payload::CommitOffsets { ref offsets } => {
-                let orig_msg_src = message.src;
-                let orig_msg_id = message.body.id.expect("message id must be set");
+                let Some(orig) = RequestKey::of(&message) else {
+                    return self.reject(message, "msg_id must be set");
+                };
 
-                self.committed_offsets_written
-                    .insert(format!("{}-{}", orig_msg_src, orig_msg_id), 0);
+                self.committed_offsets_written.insert(orig.clone(), 0);
 
                 for (key, &offset) in offsets.iter() {
                     let write_msg_id = self
@@ -291,8 +286,7 @@ impl Handler<Payload> for KafkaLog {
                     self.commit_entries.insert(
                         write_msg_id,
                         CommitOffsetEntry {
-                            orig_msg_id,
-                            orig_msg_src: orig_msg_src.clone(),
+                            orig: orig.clone(),
                             key: key.clone(),
                             requested_keys_count: offsets.len(),
                         },
@@ -303,8 +297,9 @@ impl Handler<Payload> for KafkaLog {
             }
 
             Payload::ListCommittedOffsets { ref keys } => {
-                let orig_msg_src = message.src;
-                let orig_msg_id = message.body.id.expect("message id must be set");
+                let Some(orig) = RequestKey::of(&message) else {
+                    return self.reject(message, "msg_id must be set");
+                };
 
                 for key in keys.iter() {
                     let committed_offset_key = self.committed_offset_key(key);
@@ -317,18 +312,15 @@ impl Handler<Payload> for KafkaLog {
                     self.committed_offset_reads.insert(
                         read_msg_id,
                         CommitOffsetEntry {
-                            orig_msg_id,
-                            orig_msg_src: orig_msg_src.clone(),
+                            orig: orig.clone(),
                             key: key.clone(),
                             requested_keys_count: keys.len(),
                         },
                     );
                 }
 
-                let k = format!("{}-{}", orig_msg_src, orig_msg_id);
-
                 self.list_committed_offsets.insert(
-                    k,
+                    orig,
                     CommittedOffsets {
                         requested_keys: keys.to_vec(),
                         offsets: HashMap::new(),
@@ -339,11 +331,15 @@ impl Handler<Payload> for KafkaLog {
             }
 
             Payload::ReadOk { value } => {
-                let msg_id = message.body.in_reply_to.expect("in_reply_to must be set");
+                let Some(msg_id) = message.body.in_reply_to else {
+                    return ignore_malformed_reply(&message);
+                };
 
                 if let Some(kv_offset_key) = self.send_offset_reads.remove(&msg_id) {
                     // Send 2) we've read latest offset, increment it and update it
-                    let incremented_offset = value + 1;
+                    let Some(incremented_offset) = value.checked_add(1) else {
+                        return self.fail_send(msg_id, &format!("{kv_offset_key} is at maximum"));
+                    };
                     let new_offset = KvStorePayload::Cas {
                         key: kv_offset_key.clone(),
                         from: value,
@@ -367,33 +363,37 @@ impl Handler<Payload> for KafkaLog {
                 }
 
                 if let Some(offset_start) = self.poll_offset_reads.remove(&msg_id) {
-                    let max_offset = value;
-
                     // Poll 2) We've received response to max offset request, ask fot all logged messages (ie. until max_offset)
-                    if let Some(entry) = self.poll_entries.remove(&msg_id) {
-                        let key = entry.key;
-                        for i in offset_start..=max_offset {
-                            let logged_msg_key = self.logged_msg_key(&key, i);
-                            let read_msg_id = self
-                                .send_to_lin_kv(KvStorePayload::Read {
-                                    key: logged_msg_key,
-                                })
-                                .context("read message from the log")?;
-
-                            self.poll_entries.insert(
-                                read_msg_id,
-                                PollEntry {
-                                    orig_msg_id: entry.orig_msg_id,
-                                    orig_msg_src: entry.orig_msg_src.clone(),
-                                    requested_key_offsets: entry.requested_key_offsets.clone(),
-                                    key: key.clone(),
-                                    offset: i,
-                                    max_offset,
-                                },
-                            );
-                        }
-                    } else {
-                        bail!("MISSING item in poll_entries!!!! (ReadOk - reading offset)");
+                    let Some(entry) = self.poll_entries.remove(&msg_id) else {
+                        eprintln!("MISSING item in poll_entries!!!! (ReadOk - reading offset)");
+                        return Ok(());
+                    };
+                    // the client polls the rest of the messages later
+                    let max_offset =
+                        value.min(offset_start.saturating_add(MAX_POLLED_MESSAGES - 1));
+                    if offset_start > max_offset {
+                        // no messages from the requested offset yet
+                        return self.complete_poll_key(entry.orig, &entry.key);
+                    }
+
+                    let key = entry.key;
+                    for i in offset_start..=max_offset {
+                        let logged_msg_key = self.logged_msg_key(&key, i);
+                        let read_msg_id = self
+                            .send_to_lin_kv(KvStorePayload::Read {
+                                key: logged_msg_key,
+                            })
+                            .context("read message from the log")?;
+
+                        self.poll_entries.insert(
+                            read_msg_id,
+                            PollEntry {
+                                orig: entry.orig.clone(),
+                                key: key.clone(),
+                                offset: i,
+                                max_offset,
+                            },
+                        );
                     }
 
                     return Ok(());
@@ -402,13 +402,9 @@ impl Handler<Payload> for KafkaLog {
                 if let Some(entry) = self.poll_entries.remove(&msg_id) {
                     // Poll 3) store returned message (offset and value), return it later when error 'key does not exist' would be encountered
 
-                    let orig_msg_id = entry.orig_msg_id;
-
-                    let k = format!("{}-{}", entry.orig_msg_src, orig_msg_id);
-                    let polled_messages = self
-                        .polled_messages
-                        .get_mut(&k)
-                        .expect("entry in polled_messages was inserted in Poll msg branch");
+                    let Some(polled_messages) = self.polled_messages.get_mut(&entry.orig) else {
+                        return ignore_answered_request(&entry.orig);
+                    };
 
                     polled_messages
                         .messages
@@ -420,55 +416,26 @@ impl Handler<Payload> for KafkaLog {
                             })
                         });
 
-                    if entry.offset == entry.max_offset {
-                        // we have all requested messages for this key
-                        polled_messages.completed_keys.insert(entry.key.clone());
-                    }
-
-                    if !polled_messages.all_completed() {
+                    if entry.offset != entry.max_offset {
                         // still not finished => continue
                         return Ok(());
                     }
 
-                    // All requested messages were polled, return them
-                    let mut returned_msgs = HashMap::new();
-                    for (key, _) in entry.requested_key_offsets {
-                        let msgs_for_key = polled_messages.messages.get(&key).unwrap();
-
-                        let mut returned_messages_for_key = vec![];
-                        for offset_msg in msgs_for_key {
-                            returned_messages_for_key.push((offset_msg.offset, offset_msg.msg));
-                        }
-
-                        returned_msgs.insert(key.to_string(), returned_messages_for_key);
-                    }
-
+                    // we have all requested messages for this key
                     eprintln!(
-                            "INFO: Poll Read (key {}) all completed, replying with PollOk msg, k:{}. messages len: {}",
-                            entry.key, k, polled_messages.messages.len()
-                        );
-
-                    self.polled_messages.remove(&k); // clean up
-
-                    self.reply(
-                        entry.orig_msg_src,
-                        orig_msg_id,
-                        Payload::PollOk {
-                            msgs: returned_msgs,
-                        },
-                    )?;
-
-                    return Ok(());
+                        "INFO: Poll Read (key {}) completed, k:{}",
+                        entry.key, entry.orig
+                    );
+                    return self.complete_poll_key(entry.orig, &entry.key);
                 }
 
                 if let Some(entry) = self.committed_offset_reads.remove(&msg_id) {
                     // ListCommittedOffsets
-                    let k = format!("{}-{}", entry.orig_msg_src, entry.orig_msg_id);
 
-                    let committed_offsets = self
-                        .list_committed_offsets
-                        .get_mut(&k)
-                        .expect("item is present");
+                    let Some(committed_offsets) = self.list_committed_offsets.get_mut(&entry.orig)
+                    else {
+                        return ignore_answered_request(&entry.orig);
+                    };
 
                     committed_offsets.offsets.insert(entry.key.clone(), value);
 
@@ -482,22 +449,20 @@ impl Handler<Payload> for KafkaLog {
                         offsets.insert(key.to_string(), *committed);
                     }
 
-                    self.list_committed_offsets.remove(&k); // clean up
+                    self.list_committed_offsets.remove(&entry.orig); // clean up
 
-                    self.reply(
-                        entry.orig_msg_src,
-                        entry.orig_msg_id,
-                        Payload::ListCommittedOffsetsOk { offsets },
-                    )?;
+                    self.reply(entry.orig, Payload::ListCommittedOffsetsOk { offsets })?;
 
                     return Ok(());
                 }
 
-                bail!("Unhandled ReadOk message");
+                ignore_unexpected_reply(&message)
             }
 
             Payload::CasOk => {
-                let msg_id = message.body.in_reply_to.expect("in_reply_to must be set");
+                let Some(msg_id) = message.body.in_reply_to else {
+                    return ignore_malformed_reply(&message);
+                };
                 if let Some((_, incremented_offset)) = self.offset_updates.remove(&msg_id) {
                     // offset was incremented
                     if let Some(mut entry) = self.send_entries.remove(&msg_id) {
@@ -514,21 +479,23 @@ impl Handler<Payload> for KafkaLog {
                         self.send_entries.insert(write_msg_id, entry);
                         return Ok(());
                     } else {
-                        bail!("MISSING item in msg_keys!!!! (CasOk)");
+                        eprintln!("MISSING item in msg_keys!!!! (CasOk)");
+                        return Ok(());
                     }
                 }
 
-                bail!("Unhandled CasOk message");
+                ignore_unexpected_reply(&message)
             }
 
             Payload::WriteOk => {
-                let msg_id = message.body.in_reply_to.expect("in_reply_to must be set");
+                let Some(msg_id) = message.body.in_reply_to else {
+                    return ignore_malformed_reply(&message);
+                };
                 if let Some(entry) = self.send_entries.remove(&msg_id) {
                     // message was logged (written into KV store)
 
                     self.reply(
-                        entry.orig_msg_src,
-                        entry.orig_msg_id,
+                        entry.orig,
                         Payload::SendOk {
                             offset: entry.offset,
                         },
@@ -540,12 +507,12 @@ impl Handler<Payload> for KafkaLog {
                 if let Some(entry) = self.commit_entries.remove(&msg_id) {
                     // CommitOffsets
                     // One CommitOffset was written
-                    let k = format!("{}-{}", entry.orig_msg_src, entry.orig_msg_id);
 
-                    let commits_written_count = self
-                        .committed_offsets_written
-                        .get_mut(&k)
-                        .expect("item must be present");
+                    let Some(commits_written_count) =
+                        self.committed_offsets_written.get_mut(&entry.orig)
+                    else {
+                        return ignore_answered_request(&entry.orig);
+                    };
 
                     *commits_written_count += 1;
 
@@ -555,21 +522,19 @@ impl Handler<Payload> for KafkaLog {
                     }
 
                     // All committed offsets were written => reply with ok message
-                    self.reply(
-                        entry.orig_msg_src,
-                        entry.orig_msg_id,
-                        Payload::CommitOffsetsOk,
-                    )?;
+                    self.committed_offsets_written.remove(&entry.orig); // clean up
 
-                    self.committed_offsets_written.remove(&k); // clean up
+                    self.reply(entry.orig, Payload::CommitOffsetsOk)?;
                     return Ok(());
                 }
 
-                bail!("Unhandled WriteOk message");
+                ignore_unexpected_reply(&message)
             }
 
             Payload::Error { code, ref text } => {
-                let msg_id = message.body.in_reply_to.expect("in_reply_to must be set");
+                let Some(msg_id) = message.body.in_reply_to else {
+                    return ignore_malformed_reply(&message);
+                };
                 if code == ERROR_KEY_DOES_NOT_EXIST {
                     if let Some(kv_offset_key) = self.send_offset_reads.remove(&msg_id) {
                         // Offset key does not exist, create it with value 0
@@ -588,7 +553,7 @@ impl Handler<Payload> for KafkaLog {
                         if let Some(entry) = self.send_entries.remove(&msg_id) {
                             self.send_entries.insert(cas_msg_id, entry);
                         } else {
-                            bail!("MISSING item in msg_keys!!!! (Error msg)");
+                            eprintln!("MISSING item in msg_keys!!!! (Error msg)");
                         }
 
                         return Ok(());
@@ -604,54 +569,14 @@ impl Handler<Payload> for KafkaLog {
                             entry.key
                         );
 
-                        let k = format!("{}-{}", entry.orig_msg_src, entry.orig_msg_id);
-                        let polled_messages = self
-                            .polled_messages
-                            .get_mut(&k)
-                            .expect("entry in polled_messages should be set here");
-
                         // there are no more messages for this key
-                        polled_messages.completed_keys.insert(entry.key.clone());
-
-                        if !polled_messages.all_completed() {
-                            return Ok(());
-                        }
-
-                        // Reply back with all the messages, we got them already
-                        let mut returned_msgs = HashMap::new();
-                        for (key, _) in entry.requested_key_offsets {
-                            let msgs_for_key = polled_messages.messages.get(&key).unwrap();
-
-                            let mut returned_messages_for_key = vec![];
-                            for offset_msg in msgs_for_key {
-                                returned_messages_for_key.push((offset_msg.offset, offset_msg.msg));
-                            }
-
-                            returned_msgs.insert(key.to_string(), returned_messages_for_key);
-                        }
-
-                        self.polled_messages.remove(&k); // clean up
-                        eprintln!(
-                            "INFO: Poll Error (key {}) all completed, replying with PollOk msg",
-                            entry.key
-                        );
-
-                        self.reply(
-                            entry.orig_msg_src,
-                            entry.orig_msg_id,
-                            Payload::PollOk {
-                                msgs: returned_msgs,
-                            },
-                        )?;
-
-                        return Ok(());
+                        return self.complete_poll_key(entry.orig, &entry.key);
                     }
 
                     if let Some(entry) = self.committed_offset_reads.remove(&msg_id) {
                         // empty committed offsets
                         self.reply(
-                            entry.orig_msg_src,
-                            entry.orig_msg_id,
+                            entry.orig,
                             Payload::ListCommittedOffsetsOk {
                                 offsets: HashMap::new(),
                             },
@@ -660,12 +585,14 @@ impl Handler<Payload> for KafkaLog {
                         return Ok(());
                     }
 
-                    bail!("Uncaught ERROR_KEY_DOES_NOT_EXIST: {:?}", message.body);
+                    ignore_unexpected_reply(&message)
                 } else if code == ERROR_PRECONDITION_FAILED {
                     eprintln!("INFO: CAS operation failed: '{}', retrying", text);
 
                     if let Some((offset_key, value)) = self.offset_updates.remove(&msg_id) {
-                        let incremented_offset = value + 1;
+                        let Some(incremented_offset) = value.checked_add(1) else {
+                            return self.fail_send(msg_id, &format!("{offset_key} is at maximum"));
+                        };
                         let new_offset = KvStorePayload::Cas {
                             key: offset_key.clone(),
                             from: value,
@@ -682,25 +609,45 @@ impl Handler<Payload> for KafkaLog {
                         if let Some(entry) = self.send_entries.remove(&msg_id) {
                             self.send_entries.insert(cas_msg_id, entry);
                         } else {
-                            bail!("MISSING item in msg_keys!!!! (Error msg)");
+                            eprintln!("MISSING item in msg_keys!!!! (Error msg)");
                         }
 
                         return Ok(());
                     }
 
-                    bail!("Uncaught ERROR_PRECONDITION_FAILED: {:?}", message.body);
+                    ignore_unexpected_reply(&message)
                 } else {
                     // other error encountered
-                    bail!("Uncaught Error message: {:?}", message.body);
+                    ignore_unexpected_reply(&message)
                 }
             }
 
             Payload::SendOk { .. }
             | Payload::PollOk { .. }
-            | Payload::CommitOffsetsOk { .. }
+            | Payload::CommitOffsetsOk
             | Payload::ListCommittedOffsetsOk { .. } => Ok(()), // do nothing
         }
     }
+
+    fn debug_state(&self) -> Option<serde_json::Value> {
+        // number of requests in every phase waiting for a reply from KV store
+        Some(serde_json::json!({
+            "send_entries": self.send_entries.len(),
+            "send_offset_reads": self.send_offset_reads.len(),
+            "offset_updates": self.offset_updates,
+            "poll_entries": self.poll_entries.len(),
+            "poll_offset_reads": self.poll_offset_reads.len(),
+            "polled_messages": self.polled_messages.keys().map(ToString::to_string).collect::<Vec<_>>(),
+            "commit_entries": self.commit_entries.len(),
+            "committed_offset_reads": self.committed_offset_reads.len(),
+            "committed_offsets_written": self
+                .committed_offsets_written
+                .iter()
+                .map(|(request, count)| (request.to_string(), count))
+                .collect::<HashMap<_, _>>(),
+            "list_committed_offsets": self.list_committed_offsets.keys().map(ToString::to_string).collect::<Vec<_>>(),
+        }))
+    }
 }
 
 impl KafkaLog {
@@ -717,18 +664,18 @@ impl KafkaLog {
     }
 
     /// Sends a message to the linearizable key-value store
-    fn send_to_lin_kv(&mut self, payload: KvStorePayload) -> anyhow::Result<usize> {
+    fn send_to_lin_kv(&mut self, payload: KvStorePayload) -> anyhow::Result<MsgId> {
         let msg_id = self.send_to_kv_store(LIN_KV_SERVICE_ID, payload)?;
         Ok(msg_id)
     }
 
     /// Sends a message to the sequentially consistent key-value store
-    fn send_to_seq_kv(&mut self, payload: KvStorePayload) -> anyhow::Result<usize> {
+    fn send_to_seq_kv(&mut self, payload: KvStorePayload) -> anyhow::Result<MsgId> {
         let msg_id = self.send_to_kv_store(SEQ_KV_SERVICE_ID, payload)?;
         Ok(msg_id)
     }
 
-    fn send_to_kv_store(&mut self, dst: &str, payload: KvStorePayload) -> anyhow::Result<usize> {
+    fn send_to_kv_store(&mut self, dst: &str, payload: KvStorePayload) -> anyhow::Result<MsgId> {
         if self.node.is_none() {
             panic!("cannot send to KV store, node was not initialized")
         }
@@ -742,17 +689,63 @@ impl KafkaLog {
         Ok(msg_id)
     }
 
+    /// Marks the key of the poll request as completed, replies with all polled messages
+    /// when all requested keys are completed
+    fn complete_poll_key(&mut self, orig: RequestKey, key: &str) -> anyhow::Result<()> {
+        let mut polled_messages = match self.polled_messages.entry(orig) {
+            Entry::Occupied(polled_messages) => polled_messages,
+            Entry::Vacant(entry) => return ignore_answered_request(entry.key()),
+        };
+        polled_messages
+            .get_mut()
+            .completed_keys
+            .insert(key.to_string());
+        if !polled_messages.get().all_completed() {
+            return Ok(());
+        }
+
+        // Reply back with all the messages, we got them already
+        let (orig, polled_messages) = polled_messages.remove_entry();
+        let msgs = polled_messages
+            .messages
+            .into_iter()
+            .map(|(key, msgs)| {
+                let msgs = msgs.iter().map(|m| (m.offset, m.msg)).collect();
+                (key, msgs)
+            })
+            .collect();
+        eprintln!("INFO: Poll (key {key}) all completed, replying with PollOk msg, k:{orig}");
+
+        self.reply(orig, Payload::PollOk { msgs })
+    }
+
+    /// Answers the send request waiting for the KV store reply with ID `msg_id` with an error
+    fn fail_send(&mut self, msg_id: MsgId, text: &str) -> anyhow::Result<()> {
+        let Some(entry) = self.send_entries.remove(&msg_id) else {
+            eprintln!("MISSING item in msg_keys!!!! ({text})");
+            return Ok(());
+        };
+        let payload = Payload::Error {
+            code: error::ABORT,
+            text: text.to_string(),
+        };
+        self.reply(entry.orig, payload)
+    }
+
+    /// Replies to malformed request with Maelstrom error
+    fn reject(&mut self, message: Message<Payload>, text: &str) -> anyhow::Result<()> {
+        self.node
+            .as_mut()
+            .expect("node was set in handle fn")
+            .reply_error(message, error::MALFORMED_REQUEST, text)
+    }
+
     /// Reply to incoming message with result of requested operation
-    fn reply(
-        &mut self,
-        orig_msg_src: String,
-        orig_msg_id: usize,
-        payload: Payload,
-    ) -> anyhow::Result<()> {
+    fn reply(&mut self, orig: RequestKey, payload: Payload) -> anyhow::Result<()> {
         let fake_payload = Payload::WriteOk;
         let mut fake_message: Message<Payload> = Message::new_empty(fake_payload);
-        fake_message.src = orig_msg_src;
-        fake_message.body.id = Some(orig_msg_id);
+        fake_message.src = orig.0;
+        fake_message.body.id = Some(orig.1);
 
         self.node
             .as_mut()
@@ -763,6 +756,30 @@ impl KafkaLog {
     }
 }
 
+/// Logs and drops reply of the KV store that does not say which request it answers,
+/// only requests are answered with an error
+fn ignore_malformed_reply(message: &Message<Payload>) -> anyhow::Result<()> {
+    eprintln!(
+        "WARN: ignoring malformed message: in_reply_to must be set: {:?}",
+        message.body
+    );
+    Ok(())
+}
+
+/// Logs and drops reply of the KV store to a request we do not wait for,
+/// eg. a duplicate reply or a reply sent by a client
+fn ignore_unexpected_reply(message: &Message<Payload>) -> anyhow::Result<()> {
+    eprintln!("WARN: ignoring unexpected reply: {:?}", message.body);
+    Ok(())
+}
+
+/// Logs and drops reply of the KV store for a client request that was already answered,
+/// eg. one of two requests with the same `msg_id`
+fn ignore_answered_request(orig: &RequestKey) -> anyhow::Result<()> {
+    eprintln!("WARN: ignoring reply for already answered request {orig}");
+    Ok(())
+}
+
 fn main() -> anyhow::Result<()> {
     let mut node = Node::new()?;
 
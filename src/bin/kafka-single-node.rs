@@ -1,5 +1,8 @@
 use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 
+use gossipy::persist::{self, Durable, Persisted, Storage};
+use gossipy::shard::{self, ShardKey, ShardedHandler};
 use gossipy::{Handler, Message, Node};
 use serde::{Deserialize, Serialize};
 
@@ -32,10 +35,60 @@ enum Payload {
 }
 
 /// Single-Node Kafka-Style Log
-#[derive(Default)]
+///
+/// Logs are split into partitions by key, one partition for every shard of the handler.
+/// Sends are handled by the shard owning the key, other requests touch several keys and run as barriers.
 struct KafkaLog {
-    //    log_id => ([messages], committed)
-    logs: HashMap<String, (Vec<u64>, usize)>,
+    partitions: Arc<Vec<Mutex<Persisted<Logs>>>>,
+}
+
+impl KafkaLog {
+    /// Returns partition containing the log
+    fn partition(&self, key: &str) -> &Mutex<Persisted<Logs>> {
+        &self.partitions[shard::shard_of(key, self.partitions.len())]
+    }
+}
+
+/// All the logs
+//    log_id => ([messages], committed)
+#[derive(Default)]
+struct Logs(HashMap<String, (Vec<u64>, usize)>);
+
+/// Change of the logs recorded in the write-ahead log
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+enum LogRecord {
+    Append { key: String, msg: u64 },
+    Commit { key: String, offset: usize },
+}
+
+impl Durable for Logs {
+    type Record = LogRecord;
+    type Snapshot = HashMap<String, (Vec<u64>, usize)>;
+
+    fn apply(&mut self, record: LogRecord) {
+        match record {
+            LogRecord::Append { key, msg } => {
+                self.0
+                    .entry(key)
+                    .and_modify(|(v, _c)| v.push(msg))
+                    .or_insert((vec![msg], 0));
+            }
+            LogRecord::Commit { key, offset } => {
+                self.0
+                    .entry(key)
+                    .and_modify(|(_v, committed)| *committed = offset);
+            }
+        }
+    }
+
+    fn snapshot(&self) -> Self::Snapshot {
+        self.0.clone()
+    }
+
+    fn restore(&mut self, snapshot: Self::Snapshot) {
+        self.0 = snapshot;
+    }
 }
 
 impl Handler<Payload> for KafkaLog {
@@ -45,18 +98,20 @@ impl Handler<Payload> for KafkaLog {
     {
         let reply = match msg.body.payload {
             Payload::Send { ref key, msg } => {
-                self.logs
-                    .entry(key.to_string())
-                    .and_modify(|(v, _c)| v.push(msg))
-                    .or_insert((vec![msg], 0));
-                let offset = self.logs.get(key).expect("log is present").0.len() - 1;
+                let mut logs = self.partition(key).lock().expect("lock");
+                logs.apply(LogRecord::Append {
+                    key: key.to_string(),
+                    msg,
+                })?;
+                let offset = logs.0.get(key).expect("log is present").0.len() - 1;
                 Payload::SendOk { offset }
             }
             Payload::Poll { ref offsets } => {
                 let mut msgs = HashMap::new();
 
                 for (key, &offset) in offsets.iter() {
-                    let log = self.logs.get(key);
+                    let logs = self.partition(key).lock().expect("lock");
+                    let log = logs.0.get(key);
                     if log.is_none() {
                         continue;
                     }
@@ -64,7 +119,7 @@ impl Handler<Payload> for KafkaLog {
                     let log = log.expect("log exists");
 
                     let mut messages = vec![];
-                    for i in offset..=(offset + 2) {
+                    for i in offset..=offset.saturating_add(2) {
                         if let Some(msg) = log.0.get(i) {
                             messages.push((i, *msg));
                         }
@@ -76,10 +131,12 @@ impl Handler<Payload> for KafkaLog {
                 Payload::PollOk { msgs }
             }
             Payload::CommitOffsets { ref offsets } => {
-                for (key, offset) in offsets.iter() {
-                    self.logs
-                        .entry(key.to_string())
-                        .and_modify(|(_v, committed)| *committed = *offset);
+                for (key, &offset) in offsets.iter() {
+                    let mut logs = self.partition(key).lock().expect("lock");
+                    logs.apply(LogRecord::Commit {
+                        key: key.to_string(),
+                        offset,
+                    })?;
                 }
                 Payload::CommitOffsetsOk
             }
@@ -87,7 +144,8 @@ impl Handler<Payload> for KafkaLog {
             Payload::ListCommittedOffsets { ref keys } => {
                 let mut offsets = HashMap::new();
                 for key in keys.iter() {
-                    if let Some((_, committed)) = self.logs.get(key) {
+                    let logs = self.partition(key).lock().expect("lock");
+                    if let Some((_, committed)) = logs.0.get(key) {
                         offsets.insert(key.to_string(), *committed);
                     }
                 }
@@ -96,20 +154,67 @@ impl Handler<Payload> for KafkaLog {
 
             Payload::SendOk { .. }
             | Payload::PollOk { .. }
-            | Payload::CommitOffsetsOk { .. }
+            | Payload::CommitOffsetsOk
             | Payload::ListCommittedOffsetsOk { .. } => return Ok(()),
         };
 
         node.reply(msg, reply)
     }
+
+    fn debug_state(&self) -> Option<serde_json::Value> {
+        let mut logs = HashMap::new();
+        for partition in self.partitions.iter() {
+            let partition = partition.lock().expect("lock");
+            for (key, (messages, committed)) in partition.0.iter() {
+                let state = serde_json::json!({ "len": messages.len(), "committed": committed });
+                logs.insert(key.clone(), state);
+            }
+        }
+
+        Some(serde_json::json!({ "logs": logs }))
+    }
+}
+
+impl ShardedHandler<Payload> for KafkaLog {
+    fn shard_key(msg: &Message<Payload>) -> ShardKey {
+        match &msg.body.payload {
+            Payload::Send { key, .. } => ShardKey::of(key.as_str()),
+            _ => ShardKey::Barrier,
+        }
+    }
 }
 
 fn main() -> anyhow::Result<()> {
-    let mut node = Node::new()?;
+    let mut args = std::env::args();
+    let shards: usize = args.nth(1).unwrap_or("1".to_string()).parse()?;
+    eprintln!("Using {} shards", shards);
 
-    let handler = KafkaLog::default();
+    let mut node = Node::new()?;
 
-    node.run(handler)?;
+    let config = persist::Config::from_env(&node.id())?;
+    if let Some(config) = &config {
+        // keys are partitioned by the number of shards
+        persist::check_shards(&config.dir, shards.max(1))?;
+    }
+    let partitions = (0..shards.max(1))
+        .map(|i| {
+            let logs = match config.clone() {
+                // every partition has its own WAL
+                Some(mut config) if shards > 1 => {
+                    config.dir = config.dir.join(format!("shard-{i}"));
+                    Persisted::with_storage(Storage::open(config)?, Logs::default())?
+                }
+                Some(config) => Persisted::with_storage(Storage::open(config)?, Logs::default())?,
+                None => Persisted::in_memory(Logs::default()),
+            };
+            Ok(Mutex::new(logs))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let partitions = Arc::new(partitions);
+
+    node.run_sharded(shards, |_| KafkaLog {
+        partitions: partitions.clone(),
+    })?;
 
     Ok(())
 }
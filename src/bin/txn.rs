@@ -1,7 +1,8 @@
 use std::{collections::HashMap, sync::mpsc::Sender};
 
 use anyhow::Context;
-use gossipy::{Handler, Message, Node};
+use gossipy::persist::{Durable, Persisted};
+use gossipy::{error, Handler, Message, Node};
 use serde::{de, ser::SerializeSeq, Deserialize, Deserializer, Serialize, Serializer};
 
 #[derive(Debug, Clone, Copy)]
@@ -21,7 +22,7 @@ enum Payload {
 
 /// Multi-Node, Totally-Available Transactions System
 struct TxnHandler {
-    store: Store,
+    store: Persisted<Store>,
     changes: HashMap<String, usize>,
     command_tx: Sender<Command>,
 }
@@ -42,7 +43,8 @@ impl Handler<Payload, Command> for TxnHandler {
                             *val = v.copied();
                         }
                         Operation::Write { key, val } => {
-                            let changed = self.store.write(*key, *val);
+                            let changed = self.store.is_change(key, val);
+                            self.store.apply((*key, *val))?;
                             if changed {
                                 self.changes.insert(key.to_string(), *val);
                             }
@@ -64,8 +66,17 @@ impl Handler<Payload, Command> for TxnHandler {
             Payload::TxnOk { .. } => return Ok(()),
 
             Payload::Replicate { ref changes } => {
-                for (k, v) in changes {
-                    self.store.write(k.parse()?, *v);
+                // keys are checked first, so the changes are applied all or none
+                let changes: Result<Vec<(usize, usize)>, _> = changes
+                    .iter()
+                    .map(|(k, v)| k.parse().map(|k| (k, *v)))
+                    .collect();
+                let Ok(changes) = changes else {
+                    let text = "replicated keys must be numbers";
+                    return node.reply_error(msg, error::MALFORMED_REQUEST, text);
+                };
+                for change in changes {
+                    self.store.apply(change)?;
                 }
                 Payload::ReplicateOk
             }
@@ -83,12 +94,13 @@ impl Handler<Payload, Command> for TxnHandler {
                     return Ok(());
                 }
                 // send changes to other nodes in the cluster
-                for node_id in node.node_ids() {
-                    if node_id == node.id() {
+                let id = node.id();
+                for node_id in node.node_ids().iter() {
+                    if *node_id == id {
                         continue;
                     }
                     node.send_to(
-                        &node_id,
+                        node_id,
                         Payload::Replicate {
                             changes: self.changes.clone(),
                         },
@@ -99,12 +111,19 @@ impl Handler<Payload, Command> for TxnHandler {
         }
         Ok(())
     }
+
+    fn debug_state(&self) -> Option<serde_json::Value> {
+        Some(serde_json::json!({
+            "store": self.store.0,
+            "unreplicated_changes": self.changes,
+        }))
+    }
 }
 
 impl TxnHandler {
-    fn new(command_tx: Sender<Command>) -> Self {
+    fn new(store: Persisted<Store>, command_tx: Sender<Command>) -> Self {
         Self {
-            store: Default::default(),
+            store,
             changes: Default::default(),
             command_tx,
         }
@@ -118,7 +137,8 @@ fn main() -> anyhow::Result<()> {
 
     node.register_command_receiver(rx);
 
-    let txn_handler = TxnHandler::new(tx);
+    let store = Persisted::open(&node.id(), Store::default())?;
+    let txn_handler = TxnHandler::new(store, tx);
 
     node.run(txn_handler)?;
 
@@ -139,8 +159,9 @@ impl Store {
         self.0.get(key)
     }
 
-    fn write(&mut self, key: usize, val: usize) -> bool {
-        if let Some(old) = self.0.insert(key, val) {
+    /// Returns `true` if writing the value would change the stored one
+    fn is_change(&self, key: &usize, val: &usize) -> bool {
+        if let Some(old) = self.0.get(key) {
             old != val // value was changed
         } else {
             false // value is still the same, no change
@@ -148,6 +169,24 @@ impl Store {
     }
 }
 
+impl Durable for Store {
+    /// (key, value) write
+    type Record = (usize, usize);
+    type Snapshot = HashMap<usize, usize>;
+
+    fn apply(&mut self, (key, val): (usize, usize)) {
+        self.0.insert(key, val);
+    }
+
+    fn snapshot(&self) -> Self::Snapshot {
+        self.0.clone()
+    }
+
+    fn restore(&mut self, snapshot: Self::Snapshot) {
+        self.0 = snapshot;
+    }
+}
+
 impl Serialize for Operation {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -186,7 +225,9 @@ impl<'de> Deserialize<'de> for Operation {
             }),
             'w' => Ok(Operation::Write {
                 key: op.1,
-                val: op.2.expect("must be a value"),
+                val: op
+                    .2
+                    .ok_or_else(|| de::Error::custom("write operation without a value"))?,
             }),
             x => Err(de::Error::custom(format!(
                 "unexpected operation type '{}'",
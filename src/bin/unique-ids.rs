@@ -1,6 +1,10 @@
+use gossipy::persist::{Durable, Persisted};
 use gossipy::{Handler, Message, Node};
 use serde::{Deserialize, Serialize};
 
+/// Number of IDs reserved at once in the persistent storage
+const ID_BLOCK_SIZE: usize = 1000;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case", tag = "type")]
 enum Payload {
@@ -11,6 +15,8 @@ enum Payload {
 /// Generates globally unique IDs
 struct GenerateIdHandler {
     id: usize,
+    /// IDs below this limit were already reserved, so they are never issued again after restart
+    reserved: Persisted<Reservation>,
 }
 
 impl Handler<Payload> for GenerateIdHandler {
@@ -18,6 +24,10 @@ impl Handler<Payload> for GenerateIdHandler {
     where
         Payload: Serialize,
     {
+        if self.id >= self.reserved.limit {
+            self.reserved.apply(self.id + ID_BLOCK_SIZE)?;
+        }
+
         let unique_id = format!("{}-{}", node.id(), self.id);
 
         let reply = match msg.body.payload {
@@ -29,12 +39,44 @@ impl Handler<Payload> for GenerateIdHandler {
 
         node.reply(msg, reply)
     }
+
+    fn debug_state(&self) -> Option<serde_json::Value> {
+        Some(serde_json::json!({ "next_id": self.id, "reserved": self.reserved.limit }))
+    }
+}
+
+/// Upper limit (exclusive) of reserved IDs
+#[derive(Default)]
+struct Reservation {
+    limit: usize,
+}
+
+impl Durable for Reservation {
+    type Record = usize;
+    type Snapshot = usize;
+
+    fn apply(&mut self, limit: usize) {
+        self.limit = limit;
+    }
+
+    fn snapshot(&self) -> usize {
+        self.limit
+    }
+
+    fn restore(&mut self, limit: usize) {
+        self.limit = limit;
+    }
 }
 
 fn main() -> anyhow::Result<()> {
     let mut node = Node::new()?;
 
-    let handler = GenerateIdHandler { id: 1 };
+    let reserved = Persisted::open(&node.id(), Reservation::default())?;
+    // continue after the IDs that could have been issued before restart
+    let handler = GenerateIdHandler {
+        id: reserved.limit.max(1),
+        reserved,
+    };
 
     node.run(handler)
 }
@@ -0,0 +1,513 @@
+//! Multi-node broadcast
+//!
+//! Handler of the `broadcast` binary. Values broadcast by clients are disseminated to all nodes by
+//! the [`Gossip`] engine, pushed eagerly or along a [`Plumtree`] depending on [`Mode`], and repaired
+//! by [`AntiEntropy`]. It lives in the library, so that tests and fuzz targets can drive it directly.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    crdt::{Crdt, RangeSet},
+    error,
+    failure::{DetectorConfig, FailureDetector},
+    gossip::{Gossip, PeerSelection},
+    pacing::{Observation, Pacer},
+    persist::Storage,
+    plumtree::Plumtree,
+    reconcile::{self, Part},
+    Handler, Message, Node,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum Payload {
+    Broadcast {
+        message: isize,
+    },
+    BroadcastOk,
+    Read,
+    ReadOk {
+        messages: Vec<isize>,
+    },
+    Topology {
+        topology: HashMap<String, Vec<String>>,
+    },
+    TopologyOk,
+    Gossip {
+        have: RangeSet<isize>,
+    },
+    GossipOk,
+    IHave {
+        messages: RangeSet<isize>,
+    },
+    IHaveOk,
+    Graft {
+        messages: RangeSet<isize>,
+    },
+    Prune,
+    Sync {
+        parts: Vec<Part<isize>>,
+    },
+    Heartbeat,
+    HeartbeatOk,
+}
+
+/// Maximum number of messages a node accepts, a single range in gossip could otherwise
+/// make `read` list an unbounded number of them
+pub const MAX_MESSAGES: usize = 1 << 20;
+
+/// Gossip interval until the pacer learns the load
+pub const INITIAL_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone)]
+pub enum Command {
+    SendGossip,
+    Heartbeat,
+}
+
+/// How new messages are disseminated, set by `GOSSIPY_BROADCAST_MODE`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Messages are sent only in periodic gossip rounds (default)
+    Gossip,
+    /// Newly learned messages are pushed to the neighbours right away,
+    /// periodic gossip only repairs lost messages
+    Eager,
+    /// Newly learned messages are pushed along a spanning tree, other neighbours get only announcements
+    Plumtree,
+}
+
+impl Mode {
+    /// Reads the mode from `GOSSIPY_BROADCAST_MODE`
+    pub fn from_env() -> anyhow::Result<Self> {
+        match std::env::var("GOSSIPY_BROADCAST_MODE").as_deref() {
+            Err(_) | Ok("gossip") => Ok(Mode::Gossip),
+            Ok("eager") => Ok(Mode::Eager),
+            Ok("plumtree") => Ok(Mode::Plumtree),
+            Ok(mode) => bail!("unknown GOSSIPY_BROADCAST_MODE '{mode}'"),
+        }
+    }
+}
+
+/// How nodes repair messages lost on the way, set by `GOSSIPY_ANTI_ENTROPY`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AntiEntropy {
+    /// Messages not acknowledged by a neighbour are gossiped to it again (default)
+    Deltas,
+    /// Nodes compare digests of their messages with the neighbours and send only the missing ones,
+    /// see [`reconcile`]
+    Digests,
+}
+
+impl AntiEntropy {
+    /// Reads the anti-entropy from `GOSSIPY_ANTI_ENTROPY`
+    pub fn from_env() -> anyhow::Result<Self> {
+        match std::env::var("GOSSIPY_ANTI_ENTROPY").as_deref() {
+            Err(_) | Ok("deltas") => Ok(AntiEntropy::Deltas),
+            Ok("digests") => Ok(AntiEntropy::Digests),
+            Ok(anti_entropy) => bail!("unknown GOSSIPY_ANTI_ENTROPY '{anti_entropy}'"),
+        }
+    }
+}
+
+/// Multi-Node Broadcast system
+pub struct BroadcastHandler {
+    mode: Mode,
+    anti_entropy: AntiEntropy,
+    topology: HashMap<String, Vec<String>>,
+    /// Broadcast messages gossiped to our neighbours, eager peers of the tree in plumtree mode
+    gossip: Gossip<RangeSet<isize>>,
+    tree: Plumtree<RangeSet<isize>>,
+    /// Failure detector of the neighbours, if they exchange heartbeats
+    detector: Option<FailureDetector>,
+    /// Adapts gossip interval and fanout to the load, unless the interval is fixed
+    pacer: Option<Pacer>,
+    /// Gossip interval in milliseconds, read by the gossip command thread
+    gossip_interval: Arc<AtomicU64>,
+    /// Optional persistent storage of broadcast messages
+    storage: Option<Storage>,
+}
+
+impl Handler<Payload, Command> for BroadcastHandler {
+    fn handle(&mut self, msg: Message<Payload>, mut node: Node<Command>) -> anyhow::Result<()>
+    where
+        Payload: Serialize,
+    {
+        let reply = match msg.body.payload {
+            Payload::Broadcast { message } => {
+                let new = RangeSet::from_iter([message]);
+                self.persist(&new)?;
+                if self.gossip.update(|messages| messages.insert(message)) {
+                    self.push(&mut node, &new, None)?;
+                }
+
+                Payload::BroadcastOk
+            }
+            Payload::Gossip { ref have } => {
+                if self.exceeds_limit(have) {
+                    let text = format!("gossip would exceed {MAX_MESSAGES} messages");
+                    return node.reply_error(msg, error::MALFORMED_REQUEST, &text);
+                }
+                // add to what we know and remember that the neighbour already knows it
+                self.persist(have)?;
+                match self.gossip.receive(&msg.src, have) {
+                    Some(new) => self.push(&mut node, &new, Some(&msg.src))?,
+                    // nothing new from an eager peer, it is not needed in the tree
+                    None if self.mode == Mode::Plumtree
+                        && self.tree.prune(&mut self.gossip, &msg.src) =>
+                    {
+                        node.notify(&msg.src, Payload::Prune)?;
+                    }
+                    None => {}
+                }
+                // ackowledge what we just have received
+                Payload::GossipOk
+            }
+            Payload::Read => Payload::ReadOk {
+                messages: self.gossip.state().iter().collect(),
+            },
+            Payload::Topology { ref topology } => {
+                let Some(neighbours) = topology.get(&*node.id()).cloned() else {
+                    let text = format!("topology does not include node {}", node.id());
+                    return node.reply_error(msg, error::MALFORMED_REQUEST, &text);
+                };
+                self.topology = topology.clone();
+                if let Some(detector) = self.detector.as_mut() {
+                    // the neighbours are monitored from now on
+                    let now = Instant::now();
+                    neighbours
+                        .iter()
+                        .for_each(|peer| detector.heartbeat(peer, now));
+                }
+                if self.mode == Mode::Plumtree {
+                    self.tree
+                        .set_topology(&mut self.gossip, &node.id(), topology);
+                } else {
+                    self.gossip.set_peers(neighbours);
+                }
+
+                Payload::TopologyOk
+            }
+            Payload::IHave { ref messages } => {
+                self.tree.announced(&self.gossip, &msg.src, messages);
+                Payload::IHaveOk
+            }
+            Payload::Graft { ref messages } => {
+                // send the requested messages we have through the repaired link
+                if self.tree.graft(&mut self.gossip, &msg.src) {
+                    let have = messages.intersection(self.gossip.state());
+                    if !have.is_empty() {
+                        self.gossip
+                            .send(&mut node, &msg.src, have, |have| Payload::Gossip { have })?;
+                    }
+                }
+                return Ok(());
+            }
+            Payload::Prune => {
+                self.tree.prune(&mut self.gossip, &msg.src);
+                return Ok(());
+            }
+            Payload::Sync { ref parts } => {
+                let reply = reconcile::reply(self.gossip.state(), parts);
+                if self.exceeds_limit(&reply.learned) {
+                    let text = format!("sync would exceed {MAX_MESSAGES} messages");
+                    return node.reply_error(msg, error::MALFORMED_REQUEST, &text);
+                }
+                self.persist(&reply.learned)?;
+                if let Some(new) = self.gossip.receive(&msg.src, &reply.learned) {
+                    self.push(&mut node, &new, Some(&msg.src))?;
+                }
+                if !reply.missing.is_empty() {
+                    self.gossip
+                        .send(&mut node, &msg.src, reply.missing, |have| Payload::Gossip {
+                            have,
+                        })?;
+                }
+                if !reply.parts.is_empty() {
+                    node.notify(&msg.src, Payload::Sync { parts: reply.parts })?;
+                }
+                return Ok(());
+            }
+            Payload::Heartbeat => Payload::HeartbeatOk,
+            Payload::HeartbeatOk => {
+                self.alive(&mut node, &msg.src)?;
+                return Ok(());
+            }
+            Payload::BroadcastOk | Payload::ReadOk { .. } | Payload::TopologyOk => return Ok(()), // we ignore these messages
+            Payload::GossipOk | Payload::IHaveOk => {
+                // the neighbour acknowledged to know what we have sent
+                let rtt = msg.body.in_reply_to.and_then(|id| self.gossip.ack(id));
+                if let (Some(pacer), Some(rtt)) = (self.pacer.as_mut(), rtt) {
+                    pacer.acked(rtt);
+                }
+                self.alive(&mut node, &msg.src)?;
+                return Ok(());
+            }
+        };
+
+        node.reply(msg, reply)
+    }
+
+    fn handle_command(&mut self, cmd: Command, mut node: Node<Command>) -> anyhow::Result<()> {
+        match cmd {
+            Command::SendGossip => {
+                self.pace(&node, Instant::now());
+                match self.anti_entropy {
+                    // send only what neighbours do not know yet
+                    AntiEntropy::Deltas => self
+                        .gossip
+                        .gossip(&mut node, |have| Payload::Gossip { have })?,
+                    AntiEntropy::Digests => {
+                        let parts = reconcile::start(self.gossip.state());
+                        for peer in self.gossip.start_round() {
+                            node.notify(
+                                &peer,
+                                Payload::Sync {
+                                    parts: parts.clone(),
+                                },
+                            )
+                            .with_context(|| format!("sending sync to {peer}"))?;
+                        }
+                    }
+                }
+                if self.mode == Mode::Plumtree {
+                    self.tree.round(
+                        &mut node,
+                        &mut self.gossip,
+                        |messages| Payload::IHave { messages },
+                        |messages| Payload::Graft { messages },
+                    )?;
+                }
+                Ok(())
+            }
+            Command::Heartbeat => self.heartbeat(&mut node, Instant::now()),
+        }
+    }
+
+    fn debug_state(&self) -> Option<serde_json::Value> {
+        // number of messages every neighbour does not know yet
+        let unacknowledged: HashMap<_, _> = self
+            .gossip
+            .peers()
+            .iter()
+            .map(|peer| {
+                let count = self.gossip.delta_for(peer).map_or(0, |d| d.len());
+                (peer.clone(), count)
+            })
+            .collect();
+        let suspected: BTreeSet<_> = self
+            .gossip
+            .peers()
+            .iter()
+            .chain(self.tree.lazy())
+            .filter(|peer| self.gossip.is_suspected(peer))
+            .collect();
+
+        let pace = self.pacer.as_ref().map(|pacer| {
+            let pace = pacer.pace();
+            serde_json::json!({
+                "interval_ms": pace.interval.as_millis(),
+                "fanout": pace.fanout,
+            })
+        });
+
+        Some(serde_json::json!({
+            "messages": self.gossip.state(),
+            "neighbours": self.gossip.peers(),
+            "lazy": self.tree.lazy(),
+            "suspected": suspected,
+            "unacknowledged": unacknowledged,
+            "pace": pace,
+        }))
+    }
+}
+
+impl BroadcastHandler {
+    /// Creates handler gossiping at the fixed interval, without failure detection and persistence
+    pub fn new(mode: Mode, anti_entropy: AntiEntropy, interval: Duration) -> Self {
+        Self {
+            mode,
+            anti_entropy,
+            topology: HashMap::new(),
+            gossip: Gossip::new(PeerSelection::All),
+            tree: Plumtree::new(),
+            detector: None,
+            pacer: None,
+            gossip_interval: Arc::new(AtomicU64::new(interval.as_millis() as u64)),
+            storage: None,
+        }
+    }
+
+    /// Monitors the neighbours by heartbeats, the handler then expects [`Command::Heartbeat`] every interval
+    pub fn set_detector(&mut self, config: DetectorConfig) {
+        self.detector = Some(FailureDetector::new(config));
+    }
+
+    /// Adapts gossip interval and fanout to the load by the pacer
+    pub fn set_pacer(&mut self, pacer: Pacer) {
+        self.pacer = Some(pacer);
+    }
+
+    /// Logs new messages to the storage, the messages recovered from it are added to the state
+    pub fn set_storage(&mut self, storage: Storage, recovered: &RangeSet<isize>) {
+        self.gossip.update(|state| state.merge(recovered));
+        self.storage = Some(storage);
+    }
+
+    /// Returns gossip interval in milliseconds, the gossip command thread should read it before every round
+    pub fn gossip_interval(&self) -> Arc<AtomicU64> {
+        self.gossip_interval.clone()
+    }
+
+    /// Returns all messages known to the node
+    pub fn messages(&self) -> &RangeSet<isize> {
+        self.gossip.state()
+    }
+
+    /// Returns neighbours the messages are gossiped to, the eager ones in plumtree mode
+    pub fn neighbours(&self) -> &[String] {
+        self.gossip.peers()
+    }
+
+    /// Returns lazy neighbours in plumtree mode
+    pub fn lazy(&self) -> &BTreeSet<String> {
+        self.tree.lazy()
+    }
+
+    /// Forwards newly learned messages to the (eager) neighbours in eager and plumtree mode
+    fn push(
+        &mut self,
+        node: &mut Node<Command>,
+        new: &RangeSet<isize>,
+        from: Option<&str>,
+    ) -> anyhow::Result<()> {
+        if self.mode == Mode::Gossip {
+            return Ok(());
+        }
+        self.gossip
+            .push(node, new, from, |have| Payload::Gossip { have })
+    }
+
+    /// Adapts gossip interval and fanout of the next rounds to what we observed since the last round
+    fn pace(&mut self, node: &Node<Command>, now: Instant) {
+        let Some(pacer) = self.pacer.as_mut() else {
+            return;
+        };
+        let backlog = self
+            .gossip
+            .peers()
+            .iter()
+            .map(|peer| self.gossip.delta_for(peer).map_or(0, |d| d.len()))
+            .sum();
+        let observed = Observation {
+            values: self.gossip.state().len(),
+            // all messages to other nodes, the acknowledgements we send stand in for those of our gossip
+            messages: node.metrics().messages_sent_to_nodes as usize,
+            backlog,
+            peers: self.gossip.peers().len(),
+            nodes: node.node_ids().len(),
+        };
+
+        let pace = pacer.round(observed, now);
+        self.gossip.set_selection(
+            pace.fanout
+                .map_or(PeerSelection::All, PeerSelection::Random),
+        );
+        self.gossip_interval
+            .store(pace.interval.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// Updates suspected neighbours and sends them heartbeats, suspected ones included,
+    /// so we notice when they recover
+    fn heartbeat(&mut self, node: &mut Node<Command>, now: Instant) -> anyhow::Result<()> {
+        let Some(detector) = &self.detector else {
+            return Ok(());
+        };
+        let neighbours: Vec<(String, bool)> = self
+            .topology
+            .get(&*node.id())
+            .into_iter()
+            .flatten()
+            .map(|peer| (peer.clone(), !detector.is_available(peer, now)))
+            .collect();
+        for (peer, suspected) in neighbours {
+            self.set_suspected(node, &peer, suspected)?;
+            node.send_to(&peer, Payload::Heartbeat)
+                .with_context(|| format!("sending heartbeat to {peer}"))?;
+        }
+        Ok(())
+    }
+
+    /// Records that the neighbour replied, so it is available and trusted again
+    fn alive(&mut self, node: &mut Node<Command>, peer: &str) -> anyhow::Result<()> {
+        let Some(detector) = self.detector.as_mut() else {
+            return Ok(());
+        };
+        detector.heartbeat(peer, Instant::now());
+        self.set_suspected(node, peer, false)
+    }
+
+    /// Marks the neighbour as suspected or trusted again, in plumtree mode routes around suspected neighbours
+    pub fn set_suspected(
+        &mut self,
+        node: &mut Node<Command>,
+        peer: &str,
+        suspected: bool,
+    ) -> anyhow::Result<()> {
+        if !self.gossip.set_suspected(peer, suspected) {
+            return Ok(());
+        }
+        let state = if suspected {
+            "suspected"
+        } else {
+            "trusted again"
+        };
+        eprintln!("INFO: neighbour {peer} is {state}");
+
+        if self.mode == Mode::Plumtree {
+            self.tree
+                .route_around(node, &mut self.gossip, |messages| Payload::Graft {
+                    messages,
+                })?;
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if adding the messages would exceed [`MAX_MESSAGES`]
+    fn exceeds_limit(&self, messages: &RangeSet<isize>) -> bool {
+        let new = messages
+            .delta(self.gossip.state())
+            .map_or(0, |new| new.len());
+        self.gossip.state().len().saturating_add(new) > MAX_MESSAGES
+    }
+
+    /// Logs new messages to the storage before they are added to the state
+    fn persist(&mut self, new: &RangeSet<isize>) -> anyhow::Result<()> {
+        let Some(storage) = self.storage.as_mut() else {
+            return Ok(());
+        };
+        if new.delta(self.gossip.state()).is_none() {
+            return Ok(());
+        }
+
+        storage.append(new)?;
+        if storage.snapshot_due() {
+            // the snapshot must also contain the messages we are about to add
+            let mut snapshot = self.gossip.state().clone();
+            snapshot.merge(new);
+            storage.snapshot(&snapshot)?;
+        }
+
+        Ok(())
+    }
+}
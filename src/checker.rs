@@ -0,0 +1,884 @@
+//! History checkers
+//!
+//! Offline verification of the histories recorded by `gossipy-run` (see [`history`](crate::history)),
+//! a lightweight counterpart of the Maelstrom checkers:
+//! - `echo` - every reply echoes the request
+//! - `unique-ids` - generated IDs are unique
+//! - `broadcast` - every acknowledged message is eventually read by every node
+//! - `g-counter` - final reads are within the bounds given by the acknowledged and indefinite adds
+//! - `kafka` - offsets are assigned consistently, polls are monotonic and skip no acknowledged send
+//! - `txn-rw-register` - G0, G1a, G1b and G1c anomalies, checked against read-uncommitted
+//!   or read-committed consistency model
+//! - `lin-kv` - linearizability of the registers, see [`linearizability`](crate::linearizability)
+//!
+//! The results are rendered as EDN by [`to_edn`], in the shape of Maelstrom's `results.edn`.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    str::FromStr,
+};
+
+use anyhow::bail;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::{
+    history::{self, Op, OpType},
+    linearizability,
+};
+
+/// Maximum number of examples of every kind of error in the results
+const MAX_EXAMPLES: usize = 8;
+
+/// Consistency model checked by the txn-rw-register checker
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsistencyModel {
+    ReadUncommitted,
+    ReadCommitted,
+}
+
+impl FromStr for ConsistencyModel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read-uncommitted" => Ok(ConsistencyModel::ReadUncommitted),
+            "read-committed" => Ok(ConsistencyModel::ReadCommitted),
+            _ => {
+                bail!("unknown consistency model {s}, expected read-uncommitted or read-committed")
+            }
+        }
+    }
+}
+
+impl ConsistencyModel {
+    fn name(&self) -> &'static str {
+        match self {
+            ConsistencyModel::ReadUncommitted => "read-uncommitted",
+            ConsistencyModel::ReadCommitted => "read-committed",
+        }
+    }
+
+    /// Returns anomalies prohibited by the model
+    fn prohibited(&self) -> &'static [&'static str] {
+        match self {
+            ConsistencyModel::ReadUncommitted => &["G0"],
+            ConsistencyModel::ReadCommitted => &["G0", "G1a", "G1b", "G1c"],
+        }
+    }
+}
+
+/// Results of all checkers
+#[derive(Debug, Clone, Serialize)]
+pub struct Results {
+    #[serde(rename = "valid?")]
+    pub valid: bool,
+    /// Results of the workload checker
+    pub workload: Value,
+    pub stats: Stats,
+    /// Network statistics of the run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub net: Option<Value>,
+}
+
+/// Checks the history of the workload
+pub fn check(workload: &str, history: &[Op], model: ConsistencyModel) -> anyhow::Result<Results> {
+    let workload = match workload {
+        "echo" => serde_json::to_value(echo(history))?,
+        "unique-ids" => serde_json::to_value(unique_ids(history))?,
+        "broadcast" => serde_json::to_value(broadcast(history))?,
+        "g-counter" => serde_json::to_value(g_counter(history))?,
+        "kafka" => serde_json::to_value(kafka(history))?,
+        "txn-rw-register" => serde_json::to_value(txn_rw_register(history, model))?,
+        "lin-kv" => serde_json::to_value(linearizability::check(history))?,
+        _ => bail!("no checker for workload {workload}"),
+    };
+    let stats = stats(history);
+    Ok(Results {
+        valid: workload["valid?"] == json!(true) && stats.valid,
+        workload,
+        stats,
+        net: None,
+    })
+}
+
+/// Operation counts
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Stats {
+    /// Every function has at least one successful operation
+    #[serde(rename = "valid?")]
+    pub valid: bool,
+    pub count: usize,
+    pub ok_count: usize,
+    pub fail_count: usize,
+    pub info_count: usize,
+    pub by_f: BTreeMap<String, FStats>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct FStats {
+    #[serde(rename = "valid?")]
+    pub valid: bool,
+    pub count: usize,
+    pub ok_count: usize,
+    pub fail_count: usize,
+    pub info_count: usize,
+}
+
+/// Counts completed operations
+pub fn stats(history: &[Op]) -> Stats {
+    let mut stats = Stats::default();
+    for op in history.iter().filter(|op| op.ty != OpType::Invoke) {
+        let f = stats.by_f.entry(op.f.clone()).or_default();
+        stats.count += 1;
+        f.count += 1;
+        match op.ty {
+            OpType::Ok => {
+                stats.ok_count += 1;
+                f.ok_count += 1;
+            }
+            OpType::Fail => {
+                stats.fail_count += 1;
+                f.fail_count += 1;
+            }
+            _ => {
+                stats.info_count += 1;
+                f.info_count += 1;
+            }
+        }
+    }
+    for f in stats.by_f.values_mut() {
+        f.valid = f.ok_count > 0;
+    }
+    stats.valid = stats.ok_count > 0 && stats.by_f.values().all(|f| f.valid);
+    stats
+}
+
+/// Returns successfully completed operations with the function, `(invocation, completion)`
+fn ok_ops<'a>(history: &'a [Op], f: &'a str) -> impl Iterator<Item = (&'a Op, &'a Op)> + 'a {
+    history::pairs(history)
+        .into_iter()
+        .filter_map(move |(invoke, completion)| {
+            let completion = completion.filter(|op| op.ty == OpType::Ok)?;
+            (invoke.f == f).then_some((invoke, completion))
+        })
+}
+
+fn examples<T>(items: impl IntoIterator<Item = T>) -> Vec<T> {
+    items.into_iter().take(MAX_EXAMPLES).collect()
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct EchoResult {
+    #[serde(rename = "valid?")]
+    pub valid: bool,
+    pub ok_count: usize,
+    /// Replies different from the request
+    pub errors: Vec<Value>,
+}
+
+/// Checks that every reply echoes the request
+pub fn echo(history: &[Op]) -> EchoResult {
+    let mut ok_count = 0;
+    let mut errors = Vec::new();
+    for (invoke, ok) in ok_ops(history, "echo") {
+        ok_count += 1;
+        if invoke.value != ok.value {
+            errors.push(json!({"expected": invoke.value, "received": ok.value}));
+        }
+    }
+    EchoResult {
+        valid: errors.is_empty(),
+        ok_count,
+        errors: examples(errors),
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct UniqueIdsResult {
+    #[serde(rename = "valid?")]
+    pub valid: bool,
+    pub attempted_count: usize,
+    pub acknowledged_count: usize,
+    pub duplicated_count: usize,
+    /// Examples of IDs generated more than once with their counts
+    pub duplicated: Vec<(Value, usize)>,
+}
+
+/// Checks that generated IDs are unique
+pub fn unique_ids(history: &[Op]) -> UniqueIdsResult {
+    let attempted_count = history
+        .iter()
+        .filter(|op| op.ty == OpType::Invoke && op.f == "generate")
+        .count();
+    let mut ids: HashMap<String, (Value, usize)> = HashMap::new();
+    let mut acknowledged_count = 0;
+    for (_, ok) in ok_ops(history, "generate") {
+        acknowledged_count += 1;
+        ids.entry(ok.value.to_string())
+            .or_insert_with(|| (ok.value.clone(), 0))
+            .1 += 1;
+    }
+
+    let mut duplicated: Vec<(Value, usize)> = ids.into_values().filter(|(_, n)| *n > 1).collect();
+    duplicated.sort_by_key(|(_, n)| std::cmp::Reverse(*n));
+    UniqueIdsResult {
+        valid: duplicated.is_empty(),
+        attempted_count,
+        acknowledged_count,
+        duplicated_count: duplicated.len(),
+        duplicated: examples(duplicated),
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct BroadcastResult {
+    #[serde(rename = "valid?")]
+    pub valid: bool,
+    pub attempt_count: usize,
+    pub acknowledged_count: usize,
+    /// Acknowledged messages missing in the final read of a node
+    pub lost_count: usize,
+    pub lost: Vec<Value>,
+    /// Read messages that were never broadcast
+    pub unexpected_count: usize,
+    pub unexpected: Vec<Value>,
+    /// Nodes without successful read
+    pub unread_nodes: Vec<String>,
+}
+
+/// Checks that every acknowledged message is in the final (last successful) read of every node
+pub fn broadcast(history: &[Op]) -> BroadcastResult {
+    let attempted: HashSet<String> = history
+        .iter()
+        .filter(|op| op.ty == OpType::Invoke && op.f == "broadcast")
+        .map(|op| op.value.to_string())
+        .collect();
+    let acknowledged: BTreeMap<String, Value> = ok_ops(history, "broadcast")
+        .map(|(_, ok)| (ok.value.to_string(), ok.value.clone()))
+        .collect();
+
+    let mut final_reads: BTreeMap<&str, &Value> = BTreeMap::new();
+    let mut unexpected: BTreeMap<String, Value> = BTreeMap::new();
+    for (_, ok) in ok_ops(history, "read") {
+        final_reads.insert(&ok.node, &ok.value);
+        for message in ok.value.as_array().into_iter().flatten() {
+            if !attempted.contains(&message.to_string()) {
+                unexpected.insert(message.to_string(), message.clone());
+            }
+        }
+    }
+
+    let nodes: BTreeSet<&str> = history.iter().map(|op| op.node.as_str()).collect();
+    let unread_nodes: Vec<String> = nodes
+        .iter()
+        .filter(|node| !final_reads.contains_key(*node))
+        .map(|node| node.to_string())
+        .collect();
+
+    let mut lost: BTreeMap<String, Value> = BTreeMap::new();
+    for read in final_reads.values() {
+        let read: HashSet<String> = read
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(Value::to_string)
+            .collect();
+        for (key, message) in acknowledged.iter() {
+            if !read.contains(key) {
+                lost.insert(key.clone(), message.clone());
+            }
+        }
+    }
+
+    BroadcastResult {
+        valid: lost.is_empty() && unexpected.is_empty() && unread_nodes.is_empty(),
+        attempt_count: attempted.len(),
+        acknowledged_count: acknowledged.len(),
+        lost_count: lost.len(),
+        lost: examples(lost.into_values()),
+        unexpected_count: unexpected.len(),
+        unexpected: examples(unexpected.into_values()),
+        unread_nodes,
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct GCounterResult {
+    #[serde(rename = "valid?")]
+    pub valid: bool,
+    pub read_count: usize,
+    /// Sum of acknowledged adds, lower bound of the final reads
+    pub acknowledged_total: u64,
+    /// Sum of acknowledged and indefinite adds, upper bound of the final reads
+    pub attempted_total: u64,
+    /// Final reads out of bounds
+    pub errors: Vec<Value>,
+    /// Node => value of its last read
+    pub final_reads: BTreeMap<String, Value>,
+}
+
+/// Checks that the final (last successful) read of every node is at least the sum of acknowledged adds
+/// and at most the sum of acknowledged and indefinite adds
+///
+/// The counter is only eventually consistent, so earlier reads may be stale and they are not checked.
+pub fn g_counter(history: &[Op]) -> GCounterResult {
+    let mut acknowledged_total = 0;
+    let mut attempted_total = 0;
+    for (invoke, completion) in history::pairs(history) {
+        if invoke.f != "add" {
+            continue;
+        }
+        let delta = invoke.value.as_u64().unwrap_or(0);
+        match completion.map(|op| op.ty) {
+            Some(OpType::Ok) => {
+                acknowledged_total += delta;
+                attempted_total += delta;
+            }
+            Some(OpType::Fail) => {}
+            _ => attempted_total += delta,
+        }
+    }
+
+    let mut read_count = 0;
+    let mut final_reads = BTreeMap::new();
+    for (_, ok) in ok_ops(history, "read") {
+        read_count += 1;
+        final_reads.insert(ok.node.clone(), ok.value.clone());
+    }
+    let errors: Vec<Value> = final_reads
+        .iter()
+        .filter(|(_, value)| {
+            !value
+                .as_u64()
+                .is_some_and(|value| acknowledged_total <= value && value <= attempted_total)
+        })
+        .map(|(node, value)| json!({"node": node, "value": value}))
+        .collect();
+
+    GCounterResult {
+        valid: errors.is_empty(),
+        read_count,
+        acknowledged_total,
+        attempted_total,
+        errors,
+        final_reads,
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct KafkaResult {
+    #[serde(rename = "valid?")]
+    pub valid: bool,
+    pub send_count: usize,
+    pub poll_count: usize,
+    /// Offsets of a key with more than one message
+    pub inconsistent_offsets: Vec<Value>,
+    /// Messages of a key at more than one offset
+    pub duplicates: Vec<Value>,
+    /// Polls with messages out of order or before the requested offset
+    pub nonmonotonic_polls: Vec<Value>,
+    /// Acknowledged sends skipped by a later poll
+    pub lost_writes: Vec<Value>,
+    pub lost_write_count: usize,
+    /// Acknowledged sends never polled
+    pub unseen_count: usize,
+}
+
+/// Message of the poll, `(key, offset, msg)`
+fn polled(poll: &Op) -> impl Iterator<Item = (&str, u64, &Value)> {
+    poll.value["msgs"]
+        .as_object()
+        .into_iter()
+        .flatten()
+        .flat_map(|(key, msgs)| {
+            msgs.as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(move |msg| Some((key.as_str(), msg[0].as_u64()?, &msg[1])))
+        })
+}
+
+/// Checks consistency of the offsets and that no acknowledged send was skipped by a poll
+/// invoked after the send completed
+pub fn kafka(history: &[Op]) -> KafkaResult {
+    let sends: Vec<(&Op, &Op)> = ok_ops(history, "send").collect();
+    let polls: Vec<(&Op, &Op)> = ok_ops(history, "poll").collect();
+
+    // (key, offset) => messages and (key, message) => offsets
+    let mut by_offset: BTreeMap<(&str, u64), BTreeSet<String>> = BTreeMap::new();
+    let mut by_msg: BTreeMap<(&str, String), BTreeSet<u64>> = BTreeMap::new();
+    let mut observe = |key, offset, msg: &Value| {
+        by_offset
+            .entry((key, offset))
+            .or_default()
+            .insert(msg.to_string());
+        by_msg
+            .entry((key, msg.to_string()))
+            .or_default()
+            .insert(offset);
+    };
+    for (_, ok) in sends.iter() {
+        if let (Some(key), Some(offset)) = (ok.value["key"].as_str(), ok.value["offset"].as_u64()) {
+            observe(key, offset, &ok.value["msg"]);
+        }
+    }
+
+    let mut nonmonotonic_polls = Vec::new();
+    // key => polled offsets
+    let mut seen: HashMap<&str, HashSet<u64>> = HashMap::new();
+    for (invoke, ok) in polls.iter() {
+        let mut last: HashMap<&str, u64> = HashMap::new();
+        for (key, offset, msg) in polled(ok) {
+            observe(key, offset, msg);
+            seen.entry(key).or_default().insert(offset);
+
+            let start = invoke.value["offsets"][key].as_u64().unwrap_or(0);
+            let previous = last.insert(key, offset);
+            if offset < start || previous.is_some_and(|previous| previous >= offset) {
+                nonmonotonic_polls.push(json!({
+                    "key": key,
+                    "offset": offset,
+                    "previous": previous,
+                    "requested": start,
+                    "node": ok.node,
+                }));
+            }
+        }
+    }
+
+    let inconsistent_offsets: Vec<Value> = by_offset
+        .iter()
+        .filter(|(_, msgs)| msgs.len() > 1)
+        .map(|((key, offset), msgs)| json!({"key": key, "offset": offset, "msgs": msgs}))
+        .collect();
+    let duplicates: Vec<Value> = by_msg
+        .iter()
+        .filter(|(_, offsets)| offsets.len() > 1)
+        .map(|((key, msg), offsets)| json!({"key": key, "msg": msg, "offsets": offsets}))
+        .collect();
+
+    let mut lost_writes = Vec::new();
+    let mut unseen_count = 0;
+    for (_, sent) in sends.iter() {
+        let (Some(key), Some(offset)) = (sent.value["key"].as_str(), sent.value["offset"].as_u64())
+        else {
+            continue;
+        };
+        if !seen.get(key).is_some_and(|seen| seen.contains(&offset)) {
+            unseen_count += 1;
+        }
+
+        let skipped_by = polls.iter().find(|(invoke, ok)| {
+            let start = invoke.value["offsets"][key].as_u64().unwrap_or(0);
+            let polled: Vec<u64> = polled(ok)
+                .filter(|(k, _, _)| *k == key)
+                .map(|(_, offset, _)| offset)
+                .collect();
+            invoke.time > sent.time
+                && start <= offset
+                && polled.iter().any(|o| *o > offset)
+                && !polled.contains(&offset)
+        });
+        if let Some((_, poll)) = skipped_by {
+            lost_writes.push(json!({
+                "key": key,
+                "offset": offset,
+                "msg": sent.value["msg"],
+                "poll-node": poll.node,
+                "poll-time": poll.time,
+            }));
+        }
+    }
+
+    KafkaResult {
+        valid: inconsistent_offsets.is_empty()
+            && duplicates.is_empty()
+            && nonmonotonic_polls.is_empty()
+            && lost_writes.is_empty(),
+        send_count: sends.len(),
+        poll_count: polls.len(),
+        inconsistent_offsets: examples(inconsistent_offsets),
+        duplicates: examples(duplicates),
+        nonmonotonic_polls: examples(nonmonotonic_polls),
+        lost_write_count: lost_writes.len(),
+        lost_writes: examples(lost_writes),
+        unseen_count,
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TxnResult {
+    #[serde(rename = "valid?")]
+    pub valid: bool,
+    pub consistency_model: &'static str,
+    pub txn_count: usize,
+    /// Types of all found anomalies
+    pub anomaly_types: Vec<&'static str>,
+    /// Anomaly type => examples
+    pub anomalies: BTreeMap<&'static str, Vec<Value>>,
+    /// Consistency models ruled out by the anomalies
+    pub not: Vec<&'static str>,
+}
+
+/// Micro-operation of a transaction: `["r", key, value]` or `["w", key, value]`
+fn micro_ops(txn: &Value) -> impl Iterator<Item = (&str, String, &Value)> {
+    txn.as_array().into_iter().flatten().filter_map(|op| {
+        let f = op[0].as_str()?;
+        Some((f, op[1].to_string(), &op[2]))
+    })
+}
+
+/// Transaction in the dependency graph
+///
+/// Indefinite transactions count as committed writers, a read of their write means they committed.
+/// Their reads are unknown, so no dependency leads to them and they are never part of a cycle.
+struct Txn<'a> {
+    op: &'a Op,
+    /// Writes of uncommitted transactions are aborted reads when observed
+    committed: bool,
+}
+
+/// Checks transactions for G0 (write cycle), G1a (aborted read), G1b (intermediate read)
+/// and G1c (cycle of write and read dependencies)
+///
+/// Written values must be unique per key. Version order of the writes is inferred from transactions
+/// that read a value and then overwrite it.
+pub fn txn_rw_register(history: &[Op], model: ConsistencyModel) -> TxnResult {
+    let mut txns: Vec<Txn> = Vec::new();
+    for (invoke, completion) in history::pairs(history) {
+        if invoke.f != "txn" {
+            continue;
+        }
+        match completion {
+            Some(op) if op.ty == OpType::Ok => txns.push(Txn {
+                op,
+                committed: true,
+            }),
+            // writes of failed transactions are kept for G1a
+            Some(op) if op.ty == OpType::Fail => txns.push(Txn {
+                op: invoke,
+                committed: false,
+            }),
+            // writes of indefinite transactions may be visible, their reads are unknown (see `Txn`)
+            _ => txns.push(Txn {
+                op: invoke,
+                committed: true,
+            }),
+        }
+    }
+
+    // (key, value) => (writer, final write of the key in the writer)
+    let mut writers: HashMap<(String, String), (usize, bool)> = HashMap::new();
+    for (i, txn) in txns.iter().enumerate() {
+        let writes: Vec<_> = micro_ops(&txn.op.value)
+            .filter(|(f, _, _)| *f == "w")
+            .collect();
+        for (j, (_, key, value)) in writes.iter().enumerate() {
+            let last = !writes[j + 1..].iter().any(|(_, k, _)| k == key);
+            writers.insert((key.clone(), value.to_string()), (i, last));
+        }
+    }
+
+    let mut anomalies: BTreeMap<&'static str, Vec<Value>> = BTreeMap::new();
+    // edges of the dependency graph between committed transactions
+    let mut ww: Vec<BTreeSet<usize>> = vec![BTreeSet::new(); txns.len()];
+    let mut wr: Vec<BTreeSet<usize>> = vec![BTreeSet::new(); txns.len()];
+    for (i, txn) in txns.iter().enumerate() {
+        // only reads of completed transactions are known, the other ones get no incoming edges
+        if txn.op.ty != OpType::Ok {
+            continue;
+        }
+        // key => value read from another transaction before our own write
+        let mut external: HashMap<String, String> = HashMap::new();
+        let mut written: HashSet<String> = HashSet::new();
+        for (f, key, value) in micro_ops(&txn.op.value) {
+            if f == "w" {
+                if let Some(read) = external.get(&key) {
+                    // we overwrote the value we read: its writer precedes us in the version order
+                    if let Some(&(writer, _)) = writers.get(&(key.clone(), read.clone())) {
+                        if writer != i && txns[writer].committed {
+                            ww[writer].insert(i);
+                        }
+                    }
+                }
+                written.insert(key);
+                continue;
+            }
+            if value.is_null() || written.contains(&key) {
+                continue;
+            }
+            external.entry(key.clone()).or_insert(value.to_string());
+
+            let Some(&(writer, last)) = writers.get(&(key.clone(), value.to_string())) else {
+                continue;
+            };
+            if writer == i {
+                continue;
+            }
+            let example = || json!({"op": txn.op.value, "node": txn.op.node, "writer": txns[writer].op.value, "key": key, "value": value});
+            if !txns[writer].committed {
+                anomalies.entry("G1a").or_default().push(example());
+            } else if !last {
+                anomalies.entry("G1b").or_default().push(example());
+            } else {
+                wr[writer].insert(i);
+            }
+        }
+    }
+
+    let ww_components = strongly_connected(&ww);
+    for component in ww_components.iter().filter(|c| c.len() > 1) {
+        let Some(cycle) = cycle_in(&ww, component) else {
+            continue;
+        };
+        let cycle: Vec<&Value> = cycle.iter().map(|i| &txns[*i].op.value).collect();
+        anomalies.entry("G0").or_default().push(json!(cycle));
+    }
+
+    // cycle with a read dependency exists iff a wr edge has both ends in the same component
+    let both: Vec<BTreeSet<usize>> = ww
+        .iter()
+        .zip(wr.iter())
+        .map(|(ww, wr)| ww.union(wr).copied().collect())
+        .collect();
+    // cycles consisting of write dependencies only are reported as G0
+    let in_g0: HashSet<usize> = ww_components
+        .iter()
+        .filter(|c| c.len() > 1)
+        .flatten()
+        .copied()
+        .collect();
+    for component in strongly_connected(&both).iter().filter(|c| c.len() > 1) {
+        if component.iter().any(|v| in_g0.contains(v)) {
+            continue;
+        }
+        let members: HashSet<usize> = component.iter().copied().collect();
+        let edge = component.iter().find_map(|a| {
+            wr[*a]
+                .iter()
+                .find(|b| members.contains(b))
+                .map(|b| (*a, *b))
+        });
+        if let Some((a, b)) = edge {
+            // wr edge a -> b closed by a path b -> a
+            let Some(mut cycle) = path_in(&both, &members, b, a) else {
+                continue;
+            };
+            cycle.rotate_right(1);
+            let cycle: Vec<&Value> = cycle.iter().map(|i| &txns[*i].op.value).collect();
+            anomalies.entry("G1c").or_default().push(json!(cycle));
+        }
+    }
+
+    let anomaly_types: Vec<&'static str> = anomalies.keys().copied().collect();
+    let mut not = Vec::new();
+    for model in [
+        ConsistencyModel::ReadUncommitted,
+        ConsistencyModel::ReadCommitted,
+    ] {
+        if model
+            .prohibited()
+            .iter()
+            .any(|anomaly| anomalies.contains_key(anomaly))
+        {
+            not.push(model.name());
+        }
+    }
+    for examples in anomalies.values_mut() {
+        examples.truncate(MAX_EXAMPLES);
+    }
+
+    TxnResult {
+        valid: !not.contains(&model.name()),
+        consistency_model: model.name(),
+        txn_count: txns.len(),
+        anomaly_types,
+        anomalies,
+        not,
+    }
+}
+
+/// Returns strongly connected components of the graph (Tarjan's algorithm)
+fn strongly_connected(graph: &[BTreeSet<usize>]) -> Vec<Vec<usize>> {
+    struct State {
+        index: Vec<Option<usize>>,
+        low: Vec<usize>,
+        on_stack: Vec<bool>,
+        stack: Vec<usize>,
+        next: usize,
+        components: Vec<Vec<usize>>,
+    }
+
+    let n = graph.len();
+    let mut state = State {
+        index: vec![None; n],
+        low: vec![0; n],
+        on_stack: vec![false; n],
+        stack: Vec::new(),
+        next: 0,
+        components: Vec::new(),
+    };
+
+    for root in 0..n {
+        if state.index[root].is_some() {
+            continue;
+        }
+        // iterative DFS, frame is (vertex, successors still to visit)
+        let mut frames: Vec<(usize, Vec<usize>)> = Vec::new();
+        let visit = |state: &mut State, v: usize, frames: &mut Vec<(usize, Vec<usize>)>| {
+            state.index[v] = Some(state.next);
+            state.low[v] = state.next;
+            state.next += 1;
+            state.stack.push(v);
+            state.on_stack[v] = true;
+            frames.push((v, graph[v].iter().rev().copied().collect()));
+        };
+        visit(&mut state, root, &mut frames);
+
+        while let Some((v, successors)) = frames.last_mut() {
+            let v = *v;
+            if let Some(w) = successors.pop() {
+                match state.index[w] {
+                    None => visit(&mut state, w, &mut frames),
+                    Some(index) if state.on_stack[w] => state.low[v] = state.low[v].min(index),
+                    Some(_) => {}
+                }
+                continue;
+            }
+
+            frames.pop();
+            if let Some((parent, _)) = frames.last() {
+                state.low[*parent] = state.low[*parent].min(state.low[v]);
+            }
+            if Some(state.low[v]) == state.index[v] {
+                let mut component = Vec::new();
+                loop {
+                    let w = state.stack.pop().expect("component on stack");
+                    state.on_stack[w] = false;
+                    component.push(w);
+                    if w == v {
+                        break;
+                    }
+                }
+                state.components.push(component);
+            }
+        }
+    }
+
+    state.components
+}
+
+/// Returns a cycle within the strongly connected component, `None` if the component has no cycle
+fn cycle_in(graph: &[BTreeSet<usize>], component: &[usize]) -> Option<Vec<usize>> {
+    let members: HashSet<usize> = component.iter().copied().collect();
+    let start = component[0];
+    let next = *graph[start].iter().find(|v| members.contains(v))?;
+    let mut cycle = path_in(graph, &members, next, start)?;
+    cycle.insert(0, start);
+    cycle.pop();
+    Some(cycle)
+}
+
+/// Returns the shortest path from `from` to `to` through the members (breadth-first search),
+/// `None` if `to` is not reachable
+fn path_in(
+    graph: &[BTreeSet<usize>],
+    members: &HashSet<usize>,
+    from: usize,
+    to: usize,
+) -> Option<Vec<usize>> {
+    let mut previous: HashMap<usize, usize> = HashMap::new();
+    let mut queue = std::collections::VecDeque::from([from]);
+    let mut visited = HashSet::from([from]);
+    while let Some(v) = queue.pop_front() {
+        if v == to {
+            break;
+        }
+        for &w in graph[v].iter().filter(|w| members.contains(w)) {
+            if visited.insert(w) {
+                previous.insert(w, v);
+                queue.push_back(w);
+            }
+        }
+    }
+
+    let mut path = vec![to];
+    let mut v = to;
+    while v != from {
+        v = *previous.get(&v)?;
+        path.push(v);
+    }
+    path.reverse();
+    Some(path)
+}
+
+/// Renders JSON value as EDN, object keys become keywords
+///
+/// Entries of the top-level map are put on separate lines.
+pub fn to_edn(value: &Value) -> String {
+    match value {
+        Value::Object(map) => {
+            let entries: Vec<String> = map
+                .iter()
+                .map(|(key, value)| format!("{} {}", edn_key(key), edn(value)))
+                .collect();
+            format!("{{{}}}\n", entries.join(",\n "))
+        }
+        _ => edn(value),
+    }
+}
+
+fn edn(value: &Value) -> String {
+    match value {
+        Value::Null => "nil".to_string(),
+        Value::Bool(_) | Value::Number(_) | Value::String(_) => value.to_string(),
+        Value::Array(items) => {
+            let items: Vec<String> = items.iter().map(edn).collect();
+            format!("[{}]", items.join(" "))
+        }
+        Value::Object(map) => {
+            let entries: Vec<String> = map
+                .iter()
+                .map(|(key, value)| format!("{} {}", edn_key(key), edn(value)))
+                .collect();
+            format!("{{{}}}", entries.join(", "))
+        }
+    }
+}
+
+/// Keyword if the key is a valid keyword, string otherwise
+fn edn_key(key: &str) -> String {
+    let keyword = key.chars().next().is_some_and(|c| c.is_ascii_alphabetic())
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_?!*".contains(c));
+    if keyword {
+        format!(":{key}")
+    } else {
+        Value::from(key).to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn path_to_unreachable_member_is_none() {
+        // 0 -> 1, 2 -> 0
+        let graph = vec![BTreeSet::from([1]), BTreeSet::new(), BTreeSet::from([0])];
+        let members = HashSet::from([0, 1, 2]);
+        assert_eq!(path_in(&graph, &members, 2, 1), Some(vec![2, 0, 1]));
+        assert_eq!(path_in(&graph, &members, 0, 2), None);
+        assert_eq!(cycle_in(&graph, &[0, 1, 2]), None);
+    }
+}
@@ -0,0 +1,215 @@
+//! Logical clocks
+//!
+//! Clocks used to order events across the nodes of the cluster. Every clock can be
+//! embedded in a message payload and merged with the local clock when the message is received.
+
+use std::{
+    cmp::Ordering,
+    collections::BTreeMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Lamport clock
+///
+/// Provides total order of events that is consistent with causality
+/// (if `a` happened before `b`, then `a`'s time is lower than `b`'s time).
+/// The time saturates at `u64::MAX`, a peer sending that time stops the clock.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct LamportClock(u64);
+
+impl LamportClock {
+    /// Creates new clock starting at time 0
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns current time
+    pub fn time(&self) -> u64 {
+        self.0
+    }
+
+    /// Advances the clock for a local event (eg. sending a message) and returns new time
+    pub fn tick(&mut self) -> u64 {
+        self.0 = self.0.saturating_add(1);
+        self.0
+    }
+
+    /// Merges time received in a message and returns new time
+    pub fn merge(&mut self, received: u64) -> u64 {
+        self.0 = self.0.max(received).saturating_add(1);
+        self.0
+    }
+}
+
+/// Version vector keyed by node ID
+///
+/// Unlike Lamport clock it is able to detect concurrent events.
+/// Comparison of two vectors returns `None` when neither of them happened before the other.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct VersionVector(BTreeMap<String, u64>);
+
+impl VersionVector {
+    /// Creates new empty version vector
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns counter of the node (0 if the node is not present)
+    pub fn get(&self, node_id: &str) -> u64 {
+        self.0.get(node_id).copied().unwrap_or(0)
+    }
+
+    /// Increments counter of the node for a local event and returns its new value
+    pub fn increment(&mut self, node_id: &str) -> u64 {
+        let counter = self.0.entry(node_id.to_string()).or_insert(0);
+        *counter += 1;
+        *counter
+    }
+
+    /// Merges received version vector (pointwise maximum)
+    pub fn merge(&mut self, other: &VersionVector) {
+        for (node_id, &counter) in other.0.iter() {
+            let local = self.0.entry(node_id.clone()).or_insert(0);
+            *local = (*local).max(counter);
+        }
+    }
+
+    /// Returns `true` if this vector happened before `other`
+    pub fn happened_before(&self, other: &VersionVector) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Less)
+    }
+
+    /// Returns `true` if neither of the vectors happened before the other
+    pub fn concurrent_with(&self, other: &VersionVector) -> bool {
+        self.partial_cmp(other).is_none()
+    }
+
+    /// Returns iterator over (node ID, counter) pairs
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.0.iter().map(|(id, &c)| (id.as_str(), c))
+    }
+}
+
+impl PartialOrd for VersionVector {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        let mut less = false;
+        let mut greater = false;
+
+        for node_id in self.0.keys().chain(other.0.keys()) {
+            match self.get(node_id).cmp(&other.get(node_id)) {
+                Ordering::Less => less = true,
+                Ordering::Greater => greater = true,
+                Ordering::Equal => {}
+            }
+        }
+
+        match (less, greater) {
+            (false, false) => Some(Ordering::Equal),
+            (true, false) => Some(Ordering::Less),
+            (false, true) => Some(Ordering::Greater),
+            (true, true) => None, // concurrent
+        }
+    }
+}
+
+/// Timestamp produced by [`HybridClock`]
+///
+/// Ordered by wall time first and by logical counter second.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct HlcTimestamp {
+    /// Physical time in milliseconds since UNIX epoch
+    pub wall: u64,
+    /// Logical counter distinguishing events within the same millisecond
+    pub logical: u32,
+}
+
+/// Hybrid logical clock (HLC)
+///
+/// Combines physical time with a logical counter, so the timestamps stay close to the wall clock
+/// while still respecting causality even if the physical clocks of the nodes drift apart.
+/// Timestamps saturate at the maximum wall time and logical counter.
+/// See <https://cse.buffalo.edu/tech-reports/2014-04.pdf>
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct HybridClock {
+    last: HlcTimestamp,
+}
+
+impl HybridClock {
+    /// Creates new clock
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns last issued timestamp
+    pub fn last(&self) -> HlcTimestamp {
+        self.last
+    }
+
+    /// Returns new timestamp for a local event (eg. sending a message)
+    pub fn now(&mut self) -> HlcTimestamp {
+        self.tick_at(physical_time())
+    }
+
+    /// Merges timestamp received in a message and returns new timestamp
+    pub fn merge(&mut self, received: HlcTimestamp) -> HlcTimestamp {
+        self.merge_at(received, physical_time())
+    }
+
+    /// Same as [`HybridClock::now`] with explicitly provided physical time (in milliseconds)
+    pub fn tick_at(&mut self, physical: u64) -> HlcTimestamp {
+        self.last = if physical > self.last.wall {
+            HlcTimestamp {
+                wall: physical,
+                logical: 0,
+            }
+        } else {
+            successor(self.last.wall, self.last.logical)
+        };
+        self.last
+    }
+
+    /// Same as [`HybridClock::merge`] with explicitly provided physical time (in milliseconds)
+    pub fn merge_at(&mut self, received: HlcTimestamp, physical: u64) -> HlcTimestamp {
+        let wall = physical.max(self.last.wall).max(received.wall);
+
+        self.last = if wall == self.last.wall && wall == received.wall {
+            successor(wall, self.last.logical.max(received.logical))
+        } else if wall == self.last.wall {
+            successor(wall, self.last.logical)
+        } else if wall == received.wall {
+            successor(wall, received.logical)
+        } else {
+            HlcTimestamp { wall, logical: 0 }
+        };
+        self.last
+    }
+}
+
+/// Returns the timestamp following `logical` within the millisecond, the next millisecond
+/// when the logical counter is exhausted, the same timestamp when both are at their maximum
+fn successor(wall: u64, logical: u32) -> HlcTimestamp {
+    match logical.checked_add(1) {
+        Some(logical) => HlcTimestamp { wall, logical },
+        None => match wall.checked_add(1) {
+            Some(wall) => HlcTimestamp { wall, logical: 0 },
+            None => HlcTimestamp { wall, logical },
+        },
+    }
+}
+
+/// Returns current physical time in milliseconds since UNIX epoch
+fn physical_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time is after UNIX epoch")
+        .as_millis() as u64
+}
@@ -0,0 +1,88 @@
+//! Encoding and decoding of Maelstrom messages
+//!
+//! Hot path of the runtime, every received line is decoded and every sent message is encoded here.
+//! - the line is inspected for the runtime message types before it is deserialized,
+//!   so regular messages are deserialized only once
+//! - batch envelopes are unpacked without building `serde_json::Value`s, inner messages are borrowed
+//!   from the line as raw JSON and deserialized directly into the handler's payload
+//! - only the envelope borrows from the line, decoded messages own their data (`Payload: DeserializeOwned`)
+//!   because they outlive the line in the event queues of the shard threads
+//! - messages are encoded into a reusable per-thread buffer and written to STDOUT with one call
+
+use std::{cell::RefCell, io::Write};
+
+use anyhow::Context;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::value::RawValue;
+
+use crate::{
+    admin::{self, AdminPayload},
+    batch::{self, Envelope},
+    Event, Message,
+};
+
+/// Common prefix of the types of the runtime messages ([`batch::BATCH_MSG_TYPE`], [`admin::STATE_MSG_TYPE`])
+const RUNTIME_MSG_TYPE_PREFIX: &str = "__gossipy_";
+
+thread_local! {
+    /// Buffer reused for encoding of the messages sent by the thread
+    static ENCODE_BUF: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Decodes line received from STDIN either to an admin or to a regular message event,
+/// batch of messages is unpacked to multiple events
+pub fn decode_line<Payload, Command>(line: &str) -> anyhow::Result<Vec<Event<Payload, Command>>>
+where
+    Payload: DeserializeOwned,
+{
+    // regular messages are scanned only once,
+    // but the line may also be a regular message that merely contains the strings
+    if line.contains(RUNTIME_MSG_TYPE_PREFIX) {
+        if line.contains(batch::BATCH_MSG_TYPE) {
+            if let Ok(envelope) = serde_json::from_str::<Envelope>(line) {
+                if envelope.body.ty == batch::BATCH_MSG_TYPE {
+                    return envelope
+                        .body
+                        .msgs
+                        .iter()
+                        .map(|msg| Ok(Event::Message(serde_json::from_str(msg.get())?)))
+                        .collect();
+                }
+            }
+        }
+        if line.contains(admin::STATE_MSG_TYPE) {
+            if let Ok(msg) = serde_json::from_str::<Message<AdminPayload>>(line) {
+                return Ok(vec![Event::Admin(msg)]);
+            }
+        }
+    }
+
+    let msg = serde_json::from_str(line)?;
+    Ok(vec![Event::Message(msg)])
+}
+
+/// Encodes message as one line (terminated by new line) into the buffer, previous content is cleared
+pub fn encode_line<T: Serialize>(msg: &T, buf: &mut Vec<u8>) -> anyhow::Result<()> {
+    buf.clear();
+    serde_json::to_writer(&mut *buf, msg).context("serializing message")?;
+    buf.push(b'\n');
+    Ok(())
+}
+
+/// Encodes message to raw JSON kept for sending later
+pub(crate) fn encode_raw<T: Serialize>(msg: &T) -> anyhow::Result<Box<RawValue>> {
+    serde_json::value::to_raw_value(msg).context("serializing message")
+}
+
+/// Writes message as one line to STDOUT
+///
+/// The whole line is written at once, so the messages sent from multiple threads are not interleaved.
+pub(crate) fn write_message<T: Serialize>(msg: &T) -> anyhow::Result<()> {
+    ENCODE_BUF.with_borrow_mut(|buf| {
+        encode_line(msg, buf)?;
+        std::io::stdout()
+            .lock()
+            .write_all(buf)
+            .context("writing message to STDOUT")
+    })
+}
@@ -0,0 +1,779 @@
+//! Conflict-free Replicated Data Types (CRDTs)
+//!
+//! State-based CRDTs that can be updated independently on every node and gossiped between them.
+//! Merging is commutative, associative and idempotent, so the replicas converge to the same state
+//! no matter in which order (or how many times) the states are received.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::clock::{HlcTimestamp, VersionVector};
+
+/// Common interface of all CRDTs
+pub trait Crdt: Clone + Default {
+    /// Merges state of other replica into this one
+    fn merge(&mut self, other: &Self);
+
+    /// Returns the part of the state that is not covered by `known` state
+    /// (eg. state acknowledged by other replica), or `None` if there is nothing new.
+    ///
+    /// Merging the delta into `known` gives the same result as merging the whole state.
+    fn delta(&self, known: &Self) -> Option<Self>;
+}
+
+/// Grow-only counter
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct GCounter(BTreeMap<String, u64>);
+
+impl GCounter {
+    /// Creates new counter with value 0
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `delta` to the counter on behalf of the node
+    pub fn increment(&mut self, node_id: &str, delta: u64) {
+        *self.0.entry(node_id.to_string()).or_insert(0) += delta;
+    }
+
+    /// Returns value of the counter
+    pub fn value(&self) -> u64 {
+        self.0.values().sum()
+    }
+}
+
+impl Crdt for GCounter {
+    fn merge(&mut self, other: &Self) {
+        for (node_id, &count) in other.0.iter() {
+            let local = self.0.entry(node_id.clone()).or_insert(0);
+            *local = (*local).max(count);
+        }
+    }
+
+    fn delta(&self, known: &Self) -> Option<Self> {
+        let delta: BTreeMap<_, _> = self
+            .0
+            .iter()
+            .filter(|(node_id, &count)| known.0.get(*node_id).is_none_or(|&k| k < count))
+            .map(|(node_id, &count)| (node_id.clone(), count))
+            .collect();
+
+        (!delta.is_empty()).then_some(Self(delta))
+    }
+}
+
+/// Counter supporting both increments and decrements
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PNCounter {
+    p: GCounter,
+    n: GCounter,
+}
+
+impl PNCounter {
+    /// Creates new counter with value 0
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `delta` to the counter on behalf of the node
+    pub fn increment(&mut self, node_id: &str, delta: u64) {
+        self.p.increment(node_id, delta);
+    }
+
+    /// Subtracts `delta` from the counter on behalf of the node
+    pub fn decrement(&mut self, node_id: &str, delta: u64) {
+        self.n.increment(node_id, delta);
+    }
+
+    /// Returns value of the counter
+    pub fn value(&self) -> i64 {
+        self.p.value() as i64 - self.n.value() as i64
+    }
+}
+
+impl Crdt for PNCounter {
+    fn merge(&mut self, other: &Self) {
+        self.p.merge(&other.p);
+        self.n.merge(&other.n);
+    }
+
+    fn delta(&self, known: &Self) -> Option<Self> {
+        let p = self.p.delta(&known.p);
+        let n = self.n.delta(&known.n);
+        if p.is_none() && n.is_none() {
+            return None;
+        }
+
+        Some(Self {
+            p: p.unwrap_or_default(),
+            n: n.unwrap_or_default(),
+        })
+    }
+}
+
+/// Grow-only set
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+#[serde(bound(serialize = "T: Serialize", deserialize = "T: DeserializeOwned + Ord"))]
+pub struct GSet<T>(BTreeSet<T>);
+
+impl<T: Ord> Default for GSet<T> {
+    fn default() -> Self {
+        Self(BTreeSet::new())
+    }
+}
+
+impl<T: Ord + Clone> GSet<T> {
+    /// Creates new empty set
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds element to the set, returns `true` if it was not present
+    pub fn insert(&mut self, value: T) -> bool {
+        self.0.insert(value)
+    }
+
+    /// Returns `true` if the set contains the element
+    pub fn contains(&self, value: &T) -> bool {
+        self.0.contains(value)
+    }
+
+    /// Returns number of elements in the set
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns `true` if the set contains no elements
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns iterator over the elements in ascending order
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.0.iter()
+    }
+}
+
+impl<T: Ord + Clone> Crdt for GSet<T> {
+    fn merge(&mut self, other: &Self) {
+        self.0.extend(other.0.iter().cloned());
+    }
+
+    fn delta(&self, known: &Self) -> Option<Self> {
+        let delta: BTreeSet<_> = self.0.difference(&known.0).cloned().collect();
+        (!delta.is_empty()).then_some(Self(delta))
+    }
+}
+
+impl<T: Ord> FromIterator<T> for GSet<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+impl<T: Ord> Extend<T> for GSet<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        self.0.extend(iter)
+    }
+}
+
+/// Integer type that can be stored in [`RangeSet`]
+pub trait Discrete: Copy + Ord {
+    /// The smallest value
+    const MIN: Self;
+    /// The largest value
+    const MAX: Self;
+
+    /// Returns the next value, `None` for the maximum
+    fn successor(self) -> Option<Self>;
+
+    /// Returns the previous value, `None` for the minimum
+    fn predecessor(self) -> Option<Self>;
+
+    /// Returns number of values from `start` to `end` inclusive, saturated at `usize::MAX`
+    fn count(start: Self, end: Self) -> usize;
+
+    /// Returns the value widened to `i128`, so equal values of different types are encoded the same
+    fn to_i128(self) -> i128;
+}
+
+macro_rules! impl_discrete {
+    ($($t:ty),*) => {
+        $(impl Discrete for $t {
+            const MIN: Self = <$t>::MIN;
+            const MAX: Self = <$t>::MAX;
+
+            fn successor(self) -> Option<Self> {
+                self.checked_add(1)
+            }
+
+            fn predecessor(self) -> Option<Self> {
+                self.checked_sub(1)
+            }
+
+            fn count(start: Self, end: Self) -> usize {
+                let count = end as i128 - start as i128 + 1;
+                usize::try_from(count.max(0)).unwrap_or(usize::MAX)
+            }
+
+            fn to_i128(self) -> i128 {
+                self as i128
+            }
+        })*
+    };
+}
+
+impl_discrete!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+/// Grow-only set of integers stored as ranges of consecutive values
+///
+/// Serialized as a list of inclusive ranges and single elements (eg. `[[1,500],502,503,[505,900]]`),
+/// so dense sets stay small no matter how many elements they contain, and sparse ones are not
+/// larger than a plain list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RangeSet<T: Discrete>(
+    /// start => end of disjoint ranges, adjacent ranges are always joined
+    BTreeMap<T, T>,
+);
+
+impl<T: Discrete> Default for RangeSet<T> {
+    fn default() -> Self {
+        Self(BTreeMap::new())
+    }
+}
+
+impl<T: Discrete> RangeSet<T> {
+    /// Creates new empty set
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds element to the set, returns `true` if it was not present
+    pub fn insert(&mut self, value: T) -> bool {
+        self.insert_range(value, value)
+    }
+
+    /// Adds elements from `start` to `end` inclusive, returns `true` if any of them was not present
+    pub fn insert_range(&mut self, start: T, end: T) -> bool {
+        if start > end || self.range_containing(start).is_some_and(|(_, e)| e >= end) {
+            return false;
+        }
+
+        // ranges overlapping or adjacent to the new one, ends of disjoint ranges are ordered as their starts
+        let touching: Vec<(T, T)> = self
+            .0
+            .range(..=end.successor().unwrap_or(end))
+            .rev()
+            .take_while(|(_, e)| e.successor().is_none_or(|next| next >= start))
+            .map(|(s, e)| (*s, *e))
+            .collect();
+
+        let (mut start, mut end) = (start, end);
+        for (s, e) in touching {
+            self.0.remove(&s);
+            start = start.min(s);
+            end = end.max(e);
+        }
+        self.0.insert(start, end);
+        true
+    }
+
+    /// Returns `true` if the set contains the element
+    pub fn contains(&self, value: &T) -> bool {
+        self.range_containing(*value).is_some()
+    }
+
+    /// Returns number of elements in the set, saturated at `usize::MAX`
+    pub fn len(&self) -> usize {
+        self.ranges().fold(0, |len, (start, end)| {
+            len.saturating_add(T::count(start, end))
+        })
+    }
+
+    /// Returns `true` if the set contains no elements
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns iterator over the inclusive ranges of elements in ascending order
+    pub fn ranges(&self) -> impl Iterator<Item = (T, T)> + '_ {
+        self.0.iter().map(|(start, end)| (*start, *end))
+    }
+
+    /// Returns iterator over the elements in ascending order
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        self.ranges().flat_map(|(start, end)| {
+            std::iter::successors(Some(start), move |v| {
+                v.successor().filter(|next| *next <= end)
+            })
+        })
+    }
+
+    /// Returns elements from `start` to `end` inclusive
+    pub fn range(&self, start: T, end: T) -> Self {
+        if start > end {
+            return Self::new();
+        }
+        let first = self.range_containing(start).map_or(start, |(s, _)| s);
+        let ranges = self.0.range(first..=end);
+        Self(
+            ranges
+                .map(|(s, e)| (*s.max(&start), *e.min(&end)))
+                .collect(),
+        )
+    }
+
+    /// Returns elements present in both sets
+    pub fn intersection(&self, other: &Self) -> Self {
+        match self.delta(other) {
+            // removing what is not in the other set leaves the common part
+            Some(only_self) => self.delta(&only_self).unwrap_or_default(),
+            None => self.clone(),
+        }
+    }
+
+    /// Returns the range containing the value
+    fn range_containing(&self, value: T) -> Option<(T, T)> {
+        self.0
+            .range(..=value)
+            .next_back()
+            .filter(|(_, end)| **end >= value)
+            .map(|(start, end)| (*start, *end))
+    }
+}
+
+impl<T: Discrete> Crdt for RangeSet<T> {
+    fn merge(&mut self, other: &Self) {
+        for (start, end) in other.ranges() {
+            self.insert_range(start, end);
+        }
+    }
+
+    fn delta(&self, known: &Self) -> Option<Self> {
+        let mut delta = BTreeMap::new();
+        for (start, end) in self.ranges() {
+            // first value of the range not covered by the known ranges processed so far
+            let mut next = Some(start);
+            let first = known.range_containing(start).map_or(start, |(s, _)| s);
+            for (&known_start, &known_end) in known.0.range(first..=end) {
+                let Some(from) = next else { break };
+                if known_start > from {
+                    let to = known_start.predecessor().expect("above start of the range");
+                    delta.insert(from, to);
+                }
+                next = known_end.successor().filter(|n| *n <= end);
+            }
+            if let Some(from) = next {
+                delta.insert(from, end);
+            }
+        }
+        // gaps are separated by known values, so they are neither overlapping nor adjacent
+        (!delta.is_empty()).then_some(Self(delta))
+    }
+}
+
+impl<T: Discrete> FromIterator<T> for RangeSet<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut set = Self::new();
+        set.extend(iter);
+        set
+    }
+}
+
+impl<T: Discrete> Extend<T> for RangeSet<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for value in iter {
+            self.insert(value);
+        }
+    }
+}
+
+/// Serialized form of a range in [`RangeSet`]
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum EncodedRange<T> {
+    Single(T),
+    Range(T, T),
+}
+
+impl<T: Discrete + Serialize> Serialize for RangeSet<T> {
+    fn serialize<Ser: serde::Serializer>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error> {
+        // ranges of one or two elements are shorter as single elements
+        let encoded = self
+            .ranges()
+            .flat_map(|(start, end)| match T::count(start, end) {
+                1 => vec![EncodedRange::Single(start)],
+                2 => vec![EncodedRange::Single(start), EncodedRange::Single(end)],
+                _ => vec![EncodedRange::Range(start, end)],
+            });
+        serializer.collect_seq(encoded)
+    }
+}
+
+impl<'de, T: Discrete + DeserializeOwned> Deserialize<'de> for RangeSet<T> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut set = Self::new();
+        for range in Vec::<EncodedRange<T>>::deserialize(deserializer)? {
+            let (start, end) = match range {
+                EncodedRange::Single(value) => (value, value),
+                EncodedRange::Range(start, end) => (start, end),
+            };
+            if start > end {
+                return Err(serde::de::Error::custom("range starts after its end"));
+            }
+            set.insert_range(start, end);
+        }
+        Ok(set)
+    }
+}
+
+/// Two-phase set
+///
+/// Element can be added and removed, but once removed it can never be added again.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(bound(serialize = "T: Serialize", deserialize = "T: DeserializeOwned + Ord"))]
+pub struct TwoPSet<T> {
+    added: GSet<T>,
+    removed: GSet<T>,
+}
+
+impl<T: Ord> Default for TwoPSet<T> {
+    fn default() -> Self {
+        Self {
+            added: GSet::default(),
+            removed: GSet::default(),
+        }
+    }
+}
+
+impl<T: Ord + Clone> TwoPSet<T> {
+    /// Creates new empty set
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds element to the set, returns `false` if it was already present or removed
+    pub fn insert(&mut self, value: T) -> bool {
+        !self.removed.contains(&value) && self.added.insert(value)
+    }
+
+    /// Removes element from the set, returns `true` if it was present
+    pub fn remove(&mut self, value: &T) -> bool {
+        self.contains(value) && self.removed.insert(value.clone())
+    }
+
+    /// Returns `true` if the set contains the element
+    pub fn contains(&self, value: &T) -> bool {
+        self.added.contains(value) && !self.removed.contains(value)
+    }
+
+    /// Returns iterator over the elements in ascending order
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.added.iter().filter(|v| !self.removed.contains(v))
+    }
+}
+
+impl<T: Ord + Clone> Crdt for TwoPSet<T> {
+    fn merge(&mut self, other: &Self) {
+        self.added.merge(&other.added);
+        self.removed.merge(&other.removed);
+    }
+
+    fn delta(&self, known: &Self) -> Option<Self> {
+        let added = self.added.delta(&known.added);
+        let removed = self.removed.delta(&known.removed);
+        if added.is_none() && removed.is_none() {
+            return None;
+        }
+
+        Some(Self {
+            added: added.unwrap_or_default(),
+            removed: removed.unwrap_or_default(),
+        })
+    }
+}
+
+/// Unique tag of an element added to [`ORSet`]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Dot {
+    pub node: String,
+    pub counter: u64,
+}
+
+/// Observed-remove set
+///
+/// Element can be added and removed repeatedly. Removal affects only those additions
+/// the replica has observed, so concurrent add wins over remove.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(bound(serialize = "T: Serialize", deserialize = "T: DeserializeOwned + Ord"))]
+pub struct ORSet<T> {
+    /// (element, tag of the addition)
+    added: BTreeSet<(T, Dot)>,
+    /// tags of removed additions
+    removed: BTreeSet<Dot>,
+    /// Counters used to produce unique tags
+    clock: VersionVector,
+}
+
+impl<T: Ord> Default for ORSet<T> {
+    fn default() -> Self {
+        Self {
+            added: BTreeSet::new(),
+            removed: BTreeSet::new(),
+            clock: VersionVector::new(),
+        }
+    }
+}
+
+impl<T: Ord + Clone> ORSet<T> {
+    /// Creates new empty set
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds element to the set on behalf of the node
+    pub fn insert(&mut self, node_id: &str, value: T) {
+        let dot = Dot {
+            node: node_id.to_string(),
+            counter: self.clock.increment(node_id),
+        };
+        self.added.insert((value, dot));
+    }
+
+    /// Removes all observed additions of the element, returns `true` if it was present
+    pub fn remove(&mut self, value: &T) -> bool {
+        let dots: Vec<_> = self.live_dots(value).cloned().collect();
+        let present = !dots.is_empty();
+        self.removed.extend(dots);
+        present
+    }
+
+    /// Returns `true` if the set contains the element
+    pub fn contains(&self, value: &T) -> bool {
+        self.live_dots(value).next().is_some()
+    }
+
+    /// Returns iterator over the elements in ascending order
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        let mut last = None;
+        self.added
+            .iter()
+            .filter(|(_, dot)| !self.removed.contains(dot))
+            .map(|(value, _)| value)
+            .filter(move |value| {
+                // additions of the same element are sorted next to each other
+                let new = last != Some(*value);
+                last = Some(*value);
+                new
+            })
+    }
+
+    fn live_dots<'a>(&'a self, value: &'a T) -> impl Iterator<Item = &'a Dot> {
+        self.added
+            .iter()
+            .filter(move |(v, dot)| v == value && !self.removed.contains(dot))
+            .map(|(_, dot)| dot)
+    }
+}
+
+impl<T: Ord + Clone> Crdt for ORSet<T> {
+    fn merge(&mut self, other: &Self) {
+        self.added.extend(other.added.iter().cloned());
+        self.removed.extend(other.removed.iter().cloned());
+        self.clock.merge(&other.clock);
+    }
+
+    fn delta(&self, known: &Self) -> Option<Self> {
+        let added: BTreeSet<_> = self.added.difference(&known.added).cloned().collect();
+        let removed: BTreeSet<_> = self.removed.difference(&known.removed).cloned().collect();
+        if added.is_empty() && removed.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            added,
+            removed,
+            clock: self.clock.clone(),
+        })
+    }
+}
+
+/// Last-writer-wins register
+///
+/// Concurrent writes are resolved by timestamp, ties are broken by node ID and value.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LWWRegister<T> {
+    value: Option<T>,
+    timestamp: HlcTimestamp,
+    node: String,
+}
+
+impl<T> Default for LWWRegister<T> {
+    fn default() -> Self {
+        Self {
+            value: None,
+            timestamp: HlcTimestamp::default(),
+            node: String::new(),
+        }
+    }
+}
+
+impl<T: Ord + Clone> LWWRegister<T> {
+    /// Creates new empty register
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Writes the value if the write is newer than the current one
+    pub fn set(&mut self, value: T, timestamp: HlcTimestamp, node_id: &str) {
+        self.write(Some(value), timestamp, node_id);
+    }
+
+    /// Clears the value if the write is newer than the current one
+    pub fn clear(&mut self, timestamp: HlcTimestamp, node_id: &str) {
+        self.write(None, timestamp, node_id);
+    }
+
+    /// Returns current value
+    pub fn get(&self) -> Option<&T> {
+        self.value.as_ref()
+    }
+
+    /// Returns timestamp of the current value
+    pub fn timestamp(&self) -> HlcTimestamp {
+        self.timestamp
+    }
+
+    fn write(&mut self, value: Option<T>, timestamp: HlcTimestamp, node_id: &str) {
+        let other = Self {
+            value,
+            timestamp,
+            node: node_id.to_string(),
+        };
+        self.merge(&other);
+    }
+
+    /// Returns `true` if this write wins over the other one
+    fn wins_over(&self, other: &Self) -> bool {
+        (&self.timestamp, &self.node, &self.value) > (&other.timestamp, &other.node, &other.value)
+    }
+}
+
+impl<T: Ord + Clone> Crdt for LWWRegister<T> {
+    fn merge(&mut self, other: &Self) {
+        if other.wins_over(self) {
+            *self = other.clone();
+        }
+    }
+
+    fn delta(&self, known: &Self) -> Option<Self> {
+        self.wins_over(known).then(|| self.clone())
+    }
+}
+
+/// Last-writer-wins map
+///
+/// Every key is an independent [`LWWRegister`], removed keys are kept as tombstones.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(bound(
+    serialize = "K: Serialize, V: Serialize",
+    deserialize = "K: DeserializeOwned + Ord, V: DeserializeOwned"
+))]
+pub struct LWWMap<K, V> {
+    /// serialized as a list of pairs, so the keys need not to be strings
+    #[serde(with = "pairs")]
+    entries: BTreeMap<K, LWWRegister<V>>,
+}
+
+impl<K: Ord, V> Default for LWWMap<K, V> {
+    fn default() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+        }
+    }
+}
+
+impl<K: Ord + Clone, V: Ord + Clone> LWWMap<K, V> {
+    /// Creates new empty map
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Writes the value for the key if the write is newer than the current one
+    pub fn insert(&mut self, key: K, value: V, timestamp: HlcTimestamp, node_id: &str) {
+        self.entries
+            .entry(key)
+            .or_default()
+            .set(value, timestamp, node_id);
+    }
+
+    /// Removes the key if the removal is newer than the current value
+    pub fn remove(&mut self, key: K, timestamp: HlcTimestamp, node_id: &str) {
+        self.entries
+            .entry(key)
+            .or_default()
+            .clear(timestamp, node_id);
+    }
+
+    /// Returns value for the key
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.entries.get(key).and_then(|r| r.get())
+    }
+
+    /// Returns iterator over (key, value) pairs of present keys
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.entries
+            .iter()
+            .filter_map(|(k, r)| r.get().map(|v| (k, v)))
+    }
+}
+
+impl<K: Ord + Clone, V: Ord + Clone> Crdt for LWWMap<K, V> {
+    fn merge(&mut self, other: &Self) {
+        for (key, register) in other.entries.iter() {
+            self.entries.entry(key.clone()).or_default().merge(register);
+        }
+    }
+
+    fn delta(&self, known: &Self) -> Option<Self> {
+        let entries: BTreeMap<_, _> = self
+            .entries
+            .iter()
+            .filter_map(|(key, register)| match known.entries.get(key) {
+                Some(known) => register.delta(known).map(|r| (key.clone(), r)),
+                None => Some((key.clone(), register.clone())),
+            })
+            .collect();
+
+        (!entries.is_empty()).then_some(Self { entries })
+    }
+}
+
+/// (De)serializes map as a sequence of (key, value) pairs
+mod pairs {
+    use std::collections::BTreeMap;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<K, V, S>(map: &BTreeMap<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        K: Serialize,
+        V: Serialize,
+        S: Serializer,
+    {
+        serializer.collect_seq(map.iter())
+    }
+
+    pub fn deserialize<'de, K, V, D>(deserializer: D) -> Result<BTreeMap<K, V>, D::Error>
+    where
+        K: Deserialize<'de> + Ord,
+        V: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        let pairs = Vec::<(K, V)>::deserialize(deserializer)?;
+        Ok(pairs.into_iter().collect())
+    }
+}
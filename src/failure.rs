@@ -0,0 +1,144 @@
+//! Phi accrual failure detector
+//!
+//! Instead of declaring a peer dead after a fixed timeout, the detector computes suspicion level `phi`
+//! of every monitored peer from the time since its last heartbeat and the distribution of the previous
+//! intervals between its heartbeats: `phi = -log10(P(heartbeat arrives even later))`, so eg. phi 3 means
+//! the heartbeat would be this late with probability 0.001. Peers with phi above the threshold are suspected.
+//! The detector adapts to the network, heartbeats delayed by a slow link raise phi less than on a fast one.
+//!
+//! Heartbeat is any regular sign of life of the peer (eg. reply to a periodic ping), the detector is fed
+//! by [`FailureDetector::heartbeat`] with its arrival time. Peers that never sent a heartbeat are not suspected.
+//!
+//! See [The φ Accrual Failure Detector](https://doi.org/10.1109/RELDIS.2004.1353004).
+
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+
+/// Number of the last intervals between heartbeats the distribution is estimated from
+const WINDOW: usize = 100;
+
+/// Failure detection configuration
+#[derive(Debug, Clone, Copy)]
+pub struct DetectorConfig {
+    /// How often peers send heartbeats
+    pub interval: Duration,
+    /// Peers with phi above the threshold are suspected
+    pub threshold: f64,
+}
+
+impl DetectorConfig {
+    /// Creates configuration with default threshold
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            threshold: 8.0,
+        }
+    }
+
+    /// Reads configuration from environment variables,
+    /// returns `None` if the failure detection is not enabled
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Ok(interval) = std::env::var("GOSSIPY_HEARTBEAT_MS") else {
+            return Ok(None);
+        };
+        let interval: u64 = interval.parse().context("parsing GOSSIPY_HEARTBEAT_MS")?;
+        if interval == 0 {
+            return Ok(None);
+        }
+
+        let mut config = Self::new(Duration::from_millis(interval));
+        if let Ok(threshold) = std::env::var("GOSSIPY_PHI_THRESHOLD") {
+            config.threshold = threshold.parse().context("parsing GOSSIPY_PHI_THRESHOLD")?;
+        }
+
+        Ok(Some(config))
+    }
+}
+
+/// Arrival times of heartbeats of a peer
+struct Arrivals {
+    last: Instant,
+    /// Intervals between the last heartbeats in milliseconds
+    intervals: VecDeque<f64>,
+}
+
+/// Failure detector of the peers
+pub struct FailureDetector {
+    config: DetectorConfig,
+    arrivals: HashMap<String, Arrivals>,
+}
+
+impl FailureDetector {
+    /// Creates detector with no heartbeats received yet
+    pub fn new(config: DetectorConfig) -> Self {
+        Self {
+            config,
+            arrivals: HashMap::new(),
+        }
+    }
+
+    /// Records heartbeat of the peer that arrived at `now`
+    pub fn heartbeat(&mut self, peer: &str, now: Instant) {
+        match self.arrivals.get_mut(peer) {
+            Some(arrivals) => {
+                let interval = now.saturating_duration_since(arrivals.last);
+                arrivals
+                    .intervals
+                    .push_back(interval.as_secs_f64() * 1000.0);
+                if arrivals.intervals.len() > WINDOW {
+                    arrivals.intervals.pop_front();
+                }
+                arrivals.last = arrivals.last.max(now);
+            }
+            None => {
+                // the expected interval is the first estimate
+                let arrivals = Arrivals {
+                    last: now,
+                    intervals: VecDeque::from([self.config.interval.as_secs_f64() * 1000.0]),
+                };
+                self.arrivals.insert(peer.to_string(), arrivals);
+            }
+        }
+    }
+
+    /// Returns suspicion level of the peer at `now`, 0 for peers that never sent a heartbeat
+    pub fn phi(&self, peer: &str, now: Instant) -> f64 {
+        let Some(arrivals) = self.arrivals.get(peer) else {
+            return 0.0;
+        };
+        let elapsed = now.saturating_duration_since(arrivals.last).as_secs_f64() * 1000.0;
+
+        let n = arrivals.intervals.len() as f64;
+        let mean = arrivals.intervals.iter().sum::<f64>() / n;
+        let variance = arrivals
+            .intervals
+            .iter()
+            .map(|i| (i - mean).powi(2))
+            .sum::<f64>()
+            / n;
+        // perfectly regular heartbeats would make any delay infinitely suspicious
+        let min_deviation = self.config.interval.as_secs_f64() * 1000.0 / 4.0;
+        phi(elapsed, mean, variance.sqrt().max(min_deviation))
+    }
+
+    /// Returns `true` if the peer is not suspected at `now`
+    pub fn is_available(&self, peer: &str, now: Instant) -> bool {
+        self.phi(peer, now) < self.config.threshold
+    }
+}
+
+/// Returns `-log10` of the probability that normally distributed value is larger than `elapsed`,
+/// using logistic approximation of the cumulative distribution function
+fn phi(elapsed: f64, mean: f64, deviation: f64) -> f64 {
+    let y = (elapsed - mean) / deviation;
+    let e = (-y * (1.5976 + 0.070566 * y * y)).exp();
+    if elapsed > mean {
+        -(e / (1.0 + e)).log10()
+    } else {
+        -(1.0 - 1.0 / (1.0 + e)).log10()
+    }
+}
@@ -0,0 +1,391 @@
+//! Anti-entropy gossip
+//!
+//! Generic gossip engine that disseminates any mergeable state ([`Crdt`]) across the cluster.
+//! The engine keeps track of what every peer acknowledged to know and sends it only the missing part
+//! (delta) of the state. Unacknowledged deltas are sent again in the next gossip round,
+//! so the state eventually converges even if messages get lost.
+//!
+//! Handler using the engine is expected to:
+//! - call [`Gossip::gossip`] periodically (eg. on a command produced by [`spawn_interval`] or [`spawn_paced`]),
+//!   or [`Gossip::start_round`] if it repairs the state by other messages
+//! - call [`Gossip::receive`] when a gossip message arrives and acknowledge it with a reply
+//! - call [`Gossip::ack`] when the acknowledgement arrives
+//!
+//! Optionally the handler can [`Gossip::push`] newly learned state to the peers right away (eager push),
+//! the periodic gossip then only repairs what got lost.
+//!
+//! Peers marked by [`Gossip::set_suspected`] (eg. by a [`FailureDetector`](crate::failure::FailureDetector))
+//! get no pushes and their gossip rounds back off exponentially until they are trusted again.
+
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::Sender,
+        Arc,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use serde::Serialize;
+
+use crate::{crdt::Crdt, rng::Rng, MsgId, Node};
+
+/// Number of gossip rounds after which unacknowledged gossip message is forgotten
+const PENDING_ROUNDS: usize = 10;
+/// Number of gossip rounds during which pushed delta is in flight and not sent again
+const PUSH_ROUNDS: usize = 2;
+/// Maximum number of gossip rounds between gossip messages to a suspected peer
+const MAX_BACKOFF_ROUNDS: usize = 16;
+
+/// Strategy for selecting peers to gossip with in every round
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerSelection {
+    /// Gossip with all peers
+    All,
+    /// Gossip with at most `n` randomly chosen peers
+    Random(usize),
+}
+
+/// Gossip message sent to a peer and waiting for acknowledgement
+struct Pending<S> {
+    peer: String,
+    delta: S,
+    round: usize,
+    /// Sent outside of gossip rounds, see [`Gossip::push`]
+    pushed: bool,
+    sent_at: Instant,
+}
+
+/// Gossip rounds of a suspected peer
+struct Backoff {
+    /// Next round the peer gets gossip in
+    next_round: usize,
+    /// Current number of rounds between gossip messages to the peer
+    rounds: usize,
+}
+
+/// Anti-entropy gossip engine
+pub struct Gossip<S> {
+    /// Local state
+    state: S,
+    /// Peers we gossip with
+    peers: Vec<String>,
+    selection: PeerSelection,
+    /// peer => state the peer is known to have
+    known: HashMap<String, S>,
+    /// msg_id => gossip message waiting for acknowledgement
+    pending: HashMap<MsgId, Pending<S>>,
+    /// peer => its gossip rounds while it is suspected
+    suspected: HashMap<String, Backoff>,
+    /// Gossip round counter
+    round: usize,
+    rng: Rng,
+}
+
+impl<S: Crdt> Gossip<S> {
+    /// Creates new engine with empty state and no peers
+    pub fn new(selection: PeerSelection) -> Self {
+        Self {
+            state: S::default(),
+            peers: Vec::new(),
+            selection,
+            known: HashMap::new(),
+            pending: HashMap::new(),
+            suspected: HashMap::new(),
+            round: 0,
+            rng: Rng::new(),
+        }
+    }
+
+    /// Sets peers to gossip with
+    pub fn set_peers(&mut self, peers: Vec<String>) {
+        self.peers = peers;
+    }
+
+    /// Returns peers we gossip with
+    pub fn peers(&self) -> &[String] {
+        &self.peers
+    }
+
+    /// Sets strategy for selecting peers in gossip rounds, eg. fanout chosen by a [`Pacer`](crate::pacing::Pacer)
+    pub fn set_selection(&mut self, selection: PeerSelection) {
+        self.selection = selection;
+    }
+
+    /// Returns local state
+    pub fn state(&self) -> &S {
+        &self.state
+    }
+
+    /// Applies local change to the state
+    pub fn update<R>(&mut self, f: impl FnOnce(&mut S) -> R) -> R {
+        f(&mut self.state)
+    }
+
+    /// Marks the peer as suspected of failure or trusts it again, returns `true` if that changed
+    pub fn set_suspected(&mut self, peer: &str, suspected: bool) -> bool {
+        if !suspected {
+            return self.suspected.remove(peer).is_some();
+        }
+        if self.suspected.contains_key(peer) {
+            return false;
+        }
+        let backoff = Backoff {
+            next_round: self.round + 1,
+            rounds: 1,
+        };
+        self.suspected.insert(peer.to_string(), backoff);
+        true
+    }
+
+    /// Returns `true` if the peer is suspected of failure
+    pub fn is_suspected(&self, peer: &str) -> bool {
+        self.suspected.contains_key(peer)
+    }
+
+    /// Returns state the peer is known to have
+    pub fn known(&self, peer: &str) -> Option<&S> {
+        self.known.get(peer)
+    }
+
+    /// Returns the part of the state the peer is not known to have yet
+    pub fn delta_for(&self, peer: &str) -> Option<S> {
+        match self.known.get(peer) {
+            Some(known) => self.state.delta(known),
+            None => self.state.delta(&S::default()),
+        }
+    }
+
+    /// Merges delta received from the peer into the local state, returns the part that was new to us.
+    /// The peer obviously knows what it has sent, so it is not sent back to it.
+    pub fn receive(&mut self, peer: &str, delta: &S) -> Option<S> {
+        let new = delta.delta(&self.state);
+        self.state.merge(delta);
+        self.known.entry(peer.to_string()).or_default().merge(delta);
+        new
+    }
+
+    /// Handles acknowledgement of gossip message with ID `in_reply_to`, returns its round-trip time
+    /// or `None` if the message is not known (eg. the acknowledgement arrived too late)
+    pub fn ack(&mut self, in_reply_to: MsgId) -> Option<Duration> {
+        let Pending {
+            peer,
+            delta,
+            sent_at,
+            ..
+        } = self.pending.remove(&in_reply_to)?;
+        self.known.entry(peer).or_default().merge(&delta);
+        Some(sent_at.elapsed())
+    }
+
+    /// Sends deltas to selected peers, `to_payload` wraps the delta into a gossip message payload
+    pub fn gossip<P, C>(
+        &mut self,
+        node: &mut Node<C>,
+        to_payload: impl Fn(S) -> P,
+    ) -> anyhow::Result<()>
+    where
+        P: Serialize,
+        C: Clone,
+    {
+        self.expire();
+        let round = self.round;
+
+        let mut deltas: Vec<_> = self
+            .peers
+            .iter()
+            .filter(|peer| self.is_due(peer, round))
+            .filter_map(|peer| {
+                // recently pushed deltas are still in flight
+                let mut known = self.known.get(peer).cloned().unwrap_or_default();
+                self.pending
+                    .values()
+                    .filter(|pending| {
+                        pending.pushed
+                            && pending.round + PUSH_ROUNDS >= round
+                            && pending.peer == *peer
+                    })
+                    .for_each(|pending| known.merge(&pending.delta));
+                self.state.delta(&known).map(|delta| (peer.clone(), delta))
+            })
+            .collect();
+
+        self.select(&mut deltas);
+
+        for (peer, delta) in deltas {
+            self.back_off(&peer, round);
+            let msg_id = node
+                .send_to(&peer, to_payload(delta.clone()))
+                .with_context(|| format!("sending gossip to {peer}"))?;
+
+            let pending = Pending {
+                peer,
+                delta,
+                round,
+                pushed: false,
+                sent_at: Instant::now(),
+            };
+            self.pending.insert(msg_id, pending);
+        }
+
+        Ok(())
+    }
+
+    /// Starts new gossip round like [`Gossip::expire`] and returns the peers selected for it, suspected peers
+    /// back off like in [`Gossip::gossip`]. For handlers that send the peers other messages than deltas
+    /// (eg. digests of [`reconcile`](crate::reconcile)).
+    pub fn start_round(&mut self) -> Vec<String> {
+        self.expire();
+        let round = self.round;
+        let mut peers: Vec<String> = self
+            .peers
+            .iter()
+            .filter(|peer| self.is_due(peer, round))
+            .cloned()
+            .collect();
+        self.select(&mut peers);
+        for peer in &peers {
+            self.back_off(peer, round);
+        }
+        peers
+    }
+
+    /// Starts new gossip round without sending anything and forgets unacknowledged messages that are too old,
+    /// for handlers that repair the state by other means than [`Gossip::gossip`] (eg. [`reconcile`](crate::reconcile))
+    pub fn expire(&mut self) {
+        self.round += 1;
+        let round = self.round;
+        self.pending
+            .retain(|_, pending| pending.round + PENDING_ROUNDS > round);
+    }
+
+    /// Returns `true` if the peer gets gossip in the round, suspected peers only once in a few rounds
+    fn is_due(&self, peer: &str, round: usize) -> bool {
+        self.suspected
+            .get(peer)
+            .is_none_or(|backoff| backoff.next_round <= round)
+    }
+
+    /// Keeps only the candidates of the peers the selection strategy chooses
+    fn select<T>(&mut self, candidates: &mut Vec<T>) {
+        if let PeerSelection::Random(fanout) = self.selection {
+            self.rng.shuffle(candidates);
+            candidates.truncate(fanout);
+        }
+    }
+
+    /// Doubles the number of rounds until the next gossip to the peer if it is suspected
+    fn back_off(&mut self, peer: &str, round: usize) {
+        if let Some(backoff) = self.suspected.get_mut(peer) {
+            backoff.rounds = (backoff.rounds * 2).min(MAX_BACKOFF_ROUNDS);
+            backoff.next_round = round + backoff.rounds;
+        }
+    }
+
+    /// Sends `delta` right away to all peers not known to have it, except `from` (eg. the peer we got it from)
+    /// and suspected peers. Pushed deltas are acknowledged like gossip messages and not sent again in the next
+    /// two gossip rounds.
+    pub fn push<P, C>(
+        &mut self,
+        node: &mut Node<C>,
+        delta: &S,
+        from: Option<&str>,
+        to_payload: impl Fn(S) -> P,
+    ) -> anyhow::Result<()>
+    where
+        P: Serialize,
+        C: Clone,
+    {
+        let peers: Vec<_> = self
+            .peers
+            .iter()
+            .filter(|peer| Some(peer.as_str()) != from && !self.is_suspected(peer))
+            .cloned()
+            .collect();
+        for peer in peers {
+            let delta = match self.known.get(&peer) {
+                Some(known) => delta.delta(known),
+                None => Some(delta.clone()),
+            };
+            if let Some(delta) = delta {
+                self.send(node, &peer, delta, &to_payload)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sends `delta` to the peer right away, eg. in reply to its request. Like pushed deltas it is acknowledged
+    /// and not sent again in the next two gossip rounds. The peer is not required to be one of our peers.
+    pub fn send<P, C>(
+        &mut self,
+        node: &mut Node<C>,
+        peer: &str,
+        delta: S,
+        to_payload: impl Fn(S) -> P,
+    ) -> anyhow::Result<MsgId>
+    where
+        P: Serialize,
+        C: Clone,
+    {
+        let msg_id = node
+            .send_to(peer, to_payload(delta.clone()))
+            .with_context(|| format!("pushing gossip to {peer}"))?;
+
+        let pending = Pending {
+            peer: peer.to_string(),
+            delta,
+            round: self.round,
+            pushed: true,
+            sent_at: Instant::now(),
+        };
+        self.pending.insert(msg_id, pending);
+        Ok(msg_id)
+    }
+}
+
+/// Spawns a thread that sends `cmd` to the command channel every `interval`
+///
+/// Thread finishes when the command channel is closed, ie. the node stopped.
+pub fn spawn_interval<C>(
+    interval: Duration,
+    tx: Sender<C>,
+    cmd: C,
+) -> JoinHandle<anyhow::Result<()>>
+where
+    C: Clone + Debug + Send + Sync + 'static,
+{
+    std::thread::spawn(move || loop {
+        std::thread::sleep(interval);
+        if tx.send(cmd.clone()).is_err() {
+            // the node stopped and dropped the receiver
+            return Ok(());
+        }
+    })
+}
+
+/// Spawns a thread that sends `cmd` to the command channel every `interval` milliseconds,
+/// the interval is read before every wait, so it can be changed while the thread runs (eg. by a
+/// [`Pacer`](crate::pacing::Pacer))
+///
+/// Thread finishes when the command channel is closed, ie. the node stopped.
+pub fn spawn_paced<C>(
+    interval: Arc<AtomicU64>,
+    tx: Sender<C>,
+    cmd: C,
+) -> JoinHandle<anyhow::Result<()>>
+where
+    C: Clone + Debug + Send + Sync + 'static,
+{
+    std::thread::spawn(move || loop {
+        std::thread::sleep(Duration::from_millis(interval.load(Ordering::Relaxed)));
+        if tx.send(cmd.clone()).is_err() {
+            // the node stopped and dropped the receiver
+            return Ok(());
+        }
+    })
+}
@@ -0,0 +1,48 @@
+//! Stable hashing
+//!
+//! Output of [`DefaultHasher`](std::hash::DefaultHasher) may change between Rust releases, so hashes that are
+//! persisted or sent to other nodes use 64-bit FNV-1a, which is fully specified by its constants.
+//! Only the bytes passed to [`Fnv1a::write`] are hashed: callers encode their values explicitly
+//! (eg. by `to_le_bytes`) instead of relying on `Hash` implementations of the standard library.
+//!
+//! See <http://www.isthe.com/chongo/tech/comp/fnv/>.
+
+const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Incremental 64-bit FNV-1a hash
+#[derive(Debug, Clone)]
+pub struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Fnv1a {
+    /// Creates hash of no bytes
+    pub fn new() -> Self {
+        Self(OFFSET_BASIS)
+    }
+
+    /// Adds the bytes to the hash
+    pub fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= u64::from(byte);
+            self.0 = self.0.wrapping_mul(PRIME);
+        }
+    }
+
+    /// Returns hash of the bytes written so far
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Returns FNV-1a hash of the bytes
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash = Fnv1a::new();
+    hash.write(bytes);
+    hash.finish()
+}
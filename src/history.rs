@@ -0,0 +1,95 @@
+//! Operation history
+//!
+//! History of the client operations recorded by `gossipy-run`, in the spirit of Jepsen histories:
+//! every operation is recorded twice, once when a client invokes it and once when it completes.
+//! The completion is either `ok`, `fail` (the operation certainly did not happen) or `info`
+//! (the outcome is unknown, eg. the request timed out). The history is stored as JSON lines.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Type of the history entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OpType {
+    Invoke,
+    Ok,
+    Fail,
+    Info,
+}
+
+/// Invocation or completion of an operation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Op {
+    /// Client process, process performs one operation at a time
+    pub process: usize,
+    #[serde(rename = "type")]
+    pub ty: OpType,
+    /// Function, eg. `read` or `broadcast`
+    pub f: String,
+    /// Argument of the invocation, result of the completion
+    pub value: Value,
+    /// Nanoseconds since the start of the test
+    pub time: u64,
+    /// Node the operation was sent to
+    pub node: String,
+    /// Error reply of failed operation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<Value>,
+}
+
+/// Writes history as JSON lines
+pub fn write(path: &Path, history: &[Op]) -> anyhow::Result<()> {
+    let file = File::create(path).with_context(|| format!("creating {}", path.display()))?;
+    let mut writer = BufWriter::new(file);
+    for op in history {
+        serde_json::to_writer(&mut writer, op).context("serializing history")?;
+        writer.write_all(b"\n").context("writing history")?;
+    }
+    writer.flush().context("writing history")
+}
+
+/// Reads history written by [`write`]
+pub fn read(path: &Path) -> anyhow::Result<Vec<Op>> {
+    let file = File::open(path).with_context(|| format!("opening {}", path.display()))?;
+    let mut history = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line.context("reading history")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        history.push(serde_json::from_str(&line).context("deserializing history")?);
+    }
+    Ok(history)
+}
+
+/// Pairs every invocation with its completion, returns `(invocation, completion)` in the order of invocations
+///
+/// Completion is `None` if the operation did not complete before the end of the history.
+pub fn pairs(history: &[Op]) -> Vec<(&Op, Option<&Op>)> {
+    let mut pairs: Vec<(&Op, Option<&Op>)> = Vec::new();
+    // process => index of its pending invocation
+    let mut pending = HashMap::new();
+    for op in history {
+        match op.ty {
+            OpType::Invoke => {
+                pending.insert(op.process, pairs.len());
+                pairs.push((op, None));
+            }
+            _ => {
+                if let Some(i) = pending.remove(&op.process) {
+                    pairs[i].1 = Some(op);
+                }
+            }
+        }
+    }
+    pairs
+}
@@ -0,0 +1,378 @@
+//! Key-value store services
+//!
+//! IDs of the Maelstrom key-value services and their local stand-in implementations
+//! speaking the same wire protocol (`read`, `write` and `cas` requests), so that handlers using them
+//! can be run without Maelstrom (eg. by `gossipy-run`). The implementations follow the consistency
+//! models of the Maelstrom services:
+//! - [`LinKv`] - linearizable, every request sees the latest state
+//! - [`SeqKv`] - sequentially consistent, reads may return stale state, but never older than
+//!   the state the client already observed (by its reads or writes)
+//! - [`LwwKv`] - eventually consistent, requests go to random replicas which converge by last-write-wins,
+//!   so concurrent updates (including successful `cas`) may get lost
+//!
+//! See <https://github.com/jepsen-io/maelstrom/blob/main/doc/services.md>
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    clock::HybridClock,
+    crdt::{Crdt, LWWMap},
+    error,
+    rng::Rng,
+    Body, Message, MsgId,
+};
+
+pub const SEQ_KV_SERVICE_ID: &str = "seq-kv";
+pub const LIN_KV_SERVICE_ID: &str = "lin-kv";
+pub const LWW_KV_SERVICE_ID: &str = "lww-kv";
+
+pub const ERROR_KEY_DOES_NOT_EXIST: u16 = error::KEY_DOES_NOT_EXIST;
+pub const ERROR_PRECONDITION_FAILED: u16 = error::PRECONDITION_FAILED;
+
+/// Number of writes to [`SeqKv`] between prunings of the versions nobody can read
+const PRUNE_EVERY: u64 = 1024;
+
+/// Number of replicas of [`LwwKv`]
+const LWW_REPLICAS: usize = 3;
+
+/// Request sent to the key-value service, keys and values are arbitrary JSON values
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum KvRequest {
+    Read {
+        key: Value,
+    },
+    Write {
+        key: Value,
+        value: Value,
+    },
+    /// Compare And Swap
+    Cas {
+        key: Value,
+        from: Value,
+        to: Value,
+        #[serde(default)]
+        create_if_not_exists: bool,
+    },
+}
+
+/// Reply of the key-value service
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum KvResponse {
+    ReadOk { value: Value },
+    WriteOk,
+    CasOk,
+    Error { code: u16, text: String },
+}
+
+impl KvResponse {
+    fn key_does_not_exist() -> Self {
+        KvResponse::Error {
+            code: ERROR_KEY_DOES_NOT_EXIST,
+            text: "key does not exist".to_string(),
+        }
+    }
+
+    fn precondition_failed(current: &Value, from: &Value) -> Self {
+        KvResponse::Error {
+            code: ERROR_PRECONDITION_FAILED,
+            text: format!("current value {current} is not {from}"),
+        }
+    }
+}
+
+/// Key-value service
+pub trait KvService: Send {
+    /// Handles request sent by `client` (node ID)
+    fn handle(&mut self, client: &str, request: KvRequest) -> KvResponse;
+    /// Called periodically by the simulation, eg. to replicate state between replicas
+    fn tick(&mut self) {}
+}
+
+/// Returns service with the ID, `None` if there is no such service
+pub fn service(id: &str, rng: Rng) -> Option<Box<dyn KvService>> {
+    match id {
+        LIN_KV_SERVICE_ID => Some(Box::new(LinKv::default())),
+        SEQ_KV_SERVICE_ID => Some(Box::new(SeqKv::new(rng))),
+        LWW_KV_SERVICE_ID => Some(Box::new(LwwKv::new(rng))),
+        _ => None,
+    }
+}
+
+/// Applies request to the current state of the map, returns new value of the key if it changed
+fn apply(current: Option<&Value>, request: KvRequest) -> (KvResponse, Option<Value>) {
+    match request {
+        KvRequest::Read { .. } => match current {
+            Some(value) => (
+                KvResponse::ReadOk {
+                    value: value.clone(),
+                },
+                None,
+            ),
+            None => (KvResponse::key_does_not_exist(), None),
+        },
+        KvRequest::Write { value, .. } => (KvResponse::WriteOk, Some(value)),
+        KvRequest::Cas {
+            from,
+            to,
+            create_if_not_exists,
+            ..
+        } => match current {
+            Some(current) if *current == from => (KvResponse::CasOk, Some(to)),
+            Some(current) => (KvResponse::precondition_failed(current, &from), None),
+            None if create_if_not_exists => (KvResponse::CasOk, Some(to)),
+            None => (KvResponse::key_does_not_exist(), None),
+        },
+    }
+}
+
+/// Returns key of the request encoded as JSON, keys are compared by their JSON representation
+fn key_of(request: &KvRequest) -> String {
+    match request {
+        KvRequest::Read { key } | KvRequest::Write { key, .. } | KvRequest::Cas { key, .. } => {
+            key.to_string()
+        }
+    }
+}
+
+/// Linearizable key-value store
+#[derive(Debug, Default)]
+pub struct LinKv {
+    map: HashMap<String, Value>,
+}
+
+impl KvService for LinKv {
+    fn handle(&mut self, _client: &str, request: KvRequest) -> KvResponse {
+        let key = key_of(&request);
+        let (response, new_value) = apply(self.map.get(&key), request);
+        if let Some(value) = new_value {
+            self.map.insert(key, value);
+        }
+        response
+    }
+}
+
+/// Sequentially consistent key-value store
+///
+/// All versions of the state are kept, a read returns a random version between the last version
+/// observed by the client and the latest one. Writes are applied to the latest version.
+#[derive(Debug)]
+pub struct SeqKv {
+    /// key => [(version, value)] ordered by version
+    history: HashMap<String, Vec<(u64, Value)>>,
+    /// Latest version
+    version: u64,
+    /// client => last observed version
+    observed: HashMap<String, u64>,
+    /// Versions older than this one were pruned
+    pruned: u64,
+    rng: Rng,
+}
+
+impl SeqKv {
+    pub fn new(rng: Rng) -> Self {
+        Self {
+            history: HashMap::new(),
+            version: 0,
+            observed: HashMap::new(),
+            pruned: 0,
+            rng,
+        }
+    }
+
+    /// Returns value of the key in the version
+    fn value_at(&self, key: &str, version: u64) -> Option<&Value> {
+        let values = self.history.get(key)?;
+        let i = values.partition_point(|(v, _)| *v <= version);
+        i.checked_sub(1).map(|i| &values[i].1)
+    }
+
+    /// Drops versions that no client can read anymore
+    fn prune(&mut self) {
+        let oldest = self
+            .observed
+            .values()
+            .copied()
+            .min()
+            .unwrap_or(self.version);
+        for values in self.history.values_mut() {
+            // keep the last value visible in the oldest readable version
+            let i = values.partition_point(|(v, _)| *v <= oldest);
+            values.drain(..i.saturating_sub(1));
+        }
+        self.pruned = oldest;
+    }
+}
+
+impl KvService for SeqKv {
+    fn handle(&mut self, client: &str, request: KvRequest) -> KvResponse {
+        let key = key_of(&request);
+        let observed = self.observed.get(client).copied().unwrap_or(self.pruned);
+
+        let version = match request {
+            // possibly stale read, but not older than anything the client saw before
+            KvRequest::Read { .. } => self.rng.between(observed, self.version),
+            KvRequest::Write { .. } | KvRequest::Cas { .. } => self.version,
+        };
+
+        let (response, new_value) = apply(self.value_at(&key, version), request);
+        let new_value_written = new_value.is_some();
+        let version = match new_value {
+            Some(value) => {
+                self.version += 1;
+                self.history
+                    .entry(key)
+                    .or_default()
+                    .push((self.version, value));
+                self.version
+            }
+            None => version,
+        };
+        self.observed.insert(client.to_string(), version);
+
+        if new_value_written && self.version.is_multiple_of(PRUNE_EVERY) {
+            self.prune();
+        }
+
+        response
+    }
+}
+
+/// Eventually consistent key-value store
+///
+/// Every request is handled by a random replica, replicas merge their states on [`KvService::tick`].
+/// Writes are stamped with the number of handled requests instead of the wall clock,
+/// so the lost updates are reproducible from the seed.
+#[derive(Debug)]
+pub struct LwwKv {
+    replicas: Vec<Replica>,
+    /// Logical time, number of handled requests
+    time: u64,
+    rng: Rng,
+}
+
+#[derive(Debug, Default)]
+struct Replica {
+    /// key => value, both encoded as JSON
+    map: LWWMap<String, String>,
+    clock: HybridClock,
+}
+
+impl LwwKv {
+    pub fn new(rng: Rng) -> Self {
+        Self {
+            replicas: (0..LWW_REPLICAS).map(|_| Replica::default()).collect(),
+            time: 0,
+            rng,
+        }
+    }
+}
+
+impl KvService for LwwKv {
+    fn handle(&mut self, _client: &str, request: KvRequest) -> KvResponse {
+        self.time += 1;
+        let key = key_of(&request);
+        let i = self.rng.below(self.replicas.len() as u64) as usize;
+        let replica = &mut self.replicas[i];
+
+        let current = replica
+            .map
+            .get(&key)
+            .map(|value| serde_json::from_str(value).expect("stored value is valid JSON"));
+        let (response, new_value) = apply(current.as_ref(), request);
+        if let Some(value) = new_value {
+            let timestamp = replica.clock.tick_at(self.time);
+            replica
+                .map
+                .insert(key, value.to_string(), timestamp, &format!("r{i}"));
+        }
+        response
+    }
+
+    fn tick(&mut self) {
+        let mut merged = LWWMap::new();
+        for replica in self.replicas.iter() {
+            merged.merge(&replica.map);
+        }
+        for replica in self.replicas.iter_mut() {
+            replica.map = merged.clone();
+        }
+    }
+}
+
+/// Key-value service answering Maelstrom messages
+pub struct KvServer {
+    id: String,
+    service: Box<dyn KvService>,
+    msg_id: usize,
+}
+
+impl KvServer {
+    pub fn new(id: &str, service: Box<dyn KvService>) -> Self {
+        Self {
+            id: id.to_string(),
+            service,
+            msg_id: 0,
+        }
+    }
+
+    /// Returns service ID (eg. `lin-kv`)
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Handles request message and returns the reply
+    pub fn handle(&mut self, msg: Message<KvRequest>) -> Message<KvResponse> {
+        let payload = self.service.handle(&msg.src, msg.body.payload);
+        self.reply(msg.src, msg.body.id, payload)
+    }
+
+    /// Handles request received as JSON line, malformed and unsupported requests
+    /// are answered by an error. Returns `None` if the line is not a message.
+    pub fn handle_line(&mut self, line: &str) -> Option<Message<KvResponse>> {
+        if let Ok(msg) = serde_json::from_str::<Message<KvRequest>>(line) {
+            return Some(self.handle(msg));
+        }
+
+        let msg: Message<Value> = serde_json::from_str(line).ok()?;
+        let ty = msg.body.payload.get("type").and_then(Value::as_str);
+        let payload = match ty {
+            Some("read" | "write" | "cas") => KvResponse::Error {
+                code: error::MALFORMED_REQUEST,
+                text: format!("malformed request: {}", msg.body.payload),
+            },
+            _ => KvResponse::Error {
+                code: error::NOT_SUPPORTED,
+                text: format!("unsupported request: {}", msg.body.payload),
+            },
+        };
+        Some(self.reply(msg.src, msg.body.id, payload))
+    }
+
+    /// Called periodically by the simulation
+    pub fn tick(&mut self) {
+        self.service.tick();
+    }
+
+    fn reply(
+        &mut self,
+        dst: String,
+        in_reply_to: Option<MsgId>,
+        payload: KvResponse,
+    ) -> Message<KvResponse> {
+        self.msg_id += 1;
+        Message {
+            src: self.id.clone(),
+            dst,
+            body: Body {
+                id: Some(MsgId(self.msg_id)),
+                in_reply_to,
+                payload,
+            },
+        }
+    }
+}
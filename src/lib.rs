@@ -1,12 +1,57 @@
+pub mod batch;
+pub mod broadcast;
+pub mod checker;
+pub mod clock;
+pub mod codec;
+pub mod crdt;
+pub mod failure;
+pub mod gossip;
+pub mod hash;
+pub mod history;
+pub mod kv_store;
+pub mod linearizability;
+pub mod metrics;
+pub mod pacing;
+pub mod persist;
+pub mod pipeline;
+pub mod plumtree;
+pub mod queue;
+pub mod reconcile;
+pub mod rng;
+pub mod runner;
+pub mod shard;
+pub mod workload;
+
 use std::{
-    io::Write,
-    sync::{Arc, Mutex},
+    collections::HashMap,
+    fmt,
+    io::BufRead,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::RecvTimeoutError,
+        Arc, Mutex,
+    },
     thread::JoinHandle,
+    time::{Duration, Instant},
 };
 
 use anyhow::{anyhow, bail, Context};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
+use admin::{AdminPayload, InFlightRpc};
+use batch::{BatchConfig, Batcher};
+use metrics::Metrics;
+use pipeline::{Pipeline, PipelineConfig};
+use queue::{EventClass, EventQueue, Push, QueueConfig};
+use shard::{Outstanding, ShardKey, ShardedHandler};
+
+/// Requests older than this are dropped from the table of in-flight requests
+const RPC_TIMEOUT: Duration = Duration::from_secs(30);
+/// Size of the table of in-flight requests that triggers removal of timed out requests
+const RPC_TABLE_PRUNE_SIZE: usize = 1024;
+/// How often the command thread checks whether the node is shutting down
+const COMMAND_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message<P> {
     pub src: String,
@@ -32,16 +77,80 @@ impl<P> Message<P> {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Body<P> {
     #[serde(rename = "msg_id")]
-    pub id: Option<usize>,
-    pub in_reply_to: Option<usize>,
+    pub id: Option<MsgId>,
+    pub in_reply_to: Option<MsgId>,
     #[serde(flatten)]
     pub payload: P,
 }
 
+/// Message ID, unique among the messages sent by one node
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct MsgId(pub usize);
+
+impl fmt::Display for MsgId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Globally unique identification of a request: its source and message ID
+///
+/// Used as a key of maps correlating received requests with the requests we sent on their behalf.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct RequestKey(pub String, pub MsgId);
+
+impl RequestKey {
+    /// Returns key of the request, `None` if the message has no ID
+    pub fn of<P>(msg: &Message<P>) -> Option<Self> {
+        msg.body.id.map(|id| Self(msg.src.clone(), id))
+    }
+}
+
+impl fmt::Display for RequestKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.0, self.1)
+    }
+}
+
+/// Lock-free allocator of increasing message IDs
+#[derive(Debug)]
+struct MsgIdAllocator(AtomicUsize);
+
+impl MsgIdAllocator {
+    fn new() -> Self {
+        Self(AtomicUsize::new(1))
+    }
+
+    fn next(&self) -> MsgId {
+        MsgId(self.0.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum Event<Payload, Command> {
     Message(Message<Payload>),
     Command(Command),
+    /// Admin message handled by the runtime itself
+    Admin(Message<AdminPayload>),
+}
+
+/// Returns class of the event used by the event queue
+fn event_class<Payload, Command>(event: &Event<Payload, Command>) -> EventClass {
+    match event {
+        Event::Message(msg) if msg.body.in_reply_to.is_some() => EventClass::Reply,
+        Event::Message(msg) if is_client(&msg.src) => EventClass::ClientRequest,
+        Event::Message(_) => EventClass::Peer,
+        Event::Command(_) => EventClass::Command,
+        Event::Admin(_) => EventClass::Control,
+    }
+}
+
+/// Returns `true` if the ID belongs to Maelstrom client (`c1`, `c2`, ...)
+pub fn is_client(node_id: &str) -> bool {
+    node_id
+        .strip_prefix('c')
+        .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
 }
 
 /// Message handler
@@ -54,6 +163,11 @@ pub trait Handler<Payload, Command = ()> {
     fn handle_command(&mut self, _cmd: Command, _node: Node<Command>) -> anyhow::Result<()> {
         unimplemented!("Node handler using commands must implement this method!!!");
     }
+    /// Returns snapshot of the handler's state for debugging purposes.
+    /// It is returned as a reply to the admin [`STATE_MSG_TYPE`](admin::STATE_MSG_TYPE) message.
+    fn debug_state(&self) -> Option<serde_json::Value> {
+        None
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -69,17 +183,41 @@ enum InitPayload {
 /// Common node functionality
 #[derive(Clone)]
 pub struct Node<Command = ()> {
+    /// Node ID and cluster membership, immutable after the `init` message
+    info: Arc<NodeInfo>,
+    msg_ids: Arc<MsgIdAllocator>,
     inner: Arc<Mutex<Inner>>,
     command_rx: Option<Arc<Mutex<std::sync::mpsc::Receiver<Command>>>>,
+    metrics: Arc<Metrics>,
+    queue_config: QueueConfig,
+    /// Index of the shard this node handle belongs to (see [`Node::run_sharded`])
+    shard: usize,
+    /// Coalesces messages to other nodes if the batching is enabled
+    batcher: Option<Arc<Batcher>>,
+    /// Limits requests in flight per destination if the pipelining is enabled
+    pipeline: Option<Arc<Mutex<Pipeline>>>,
+    /// Messages sent by the node if the output is captured (see [`Node::capture_output`])
+    captured: Option<Arc<Mutex<Vec<String>>>>,
 }
 
-pub struct Inner {
+struct NodeInfo {
     /// Node ID
-    pub id: String,
+    id: Arc<str>,
     /// List of all nodes in the cluster, including the recipient
-    pub node_ids: Vec<String>,
-    /// Message ID counter
-    msg_id: usize,
+    node_ids: Arc<[Arc<str>]>,
+}
+
+struct Inner {
+    /// Requests sent by us waiting for a reply
+    rpcs: HashMap<MsgId, Rpc>,
+}
+
+/// Request sent by us waiting for a reply
+struct Rpc {
+    dst: String,
+    sent_at: Instant,
+    /// Shard that sent the request and handles the reply
+    shard: usize,
 }
 
 impl<Command> Node<Command>
@@ -88,30 +226,20 @@ where
 {
     /// Creates new [`Node`] instance initialized by Maelstrom `init` message
     pub fn new() -> anyhow::Result<Self> {
-        let mut node = Self {
-            inner: Arc::new(Mutex::new(Inner {
-                id: String::new(),
-                node_ids: Vec::new(),
-                msg_id: 1,
-            })),
-            command_rx: None,
-        };
-
         let msg: Message<InitPayload> = serde_json::Deserializer::from_reader(std::io::stdin())
             .into_iter()
             .next()
             .ok_or(anyhow!("failed to read Init message from STDIN"))?
             .context("deserializing Init message from STDIN")?;
 
-        let reply_payload = match msg.body.payload {
-            InitPayload::Init { node_id, node_ids } => {
-                let mut node = node.inner.lock().expect("lock");
-                node.id = node_id;
-                node.node_ids = node_ids;
-                InitPayload::InitOk {}
-            }
+        let mut node = match msg.body.payload {
+            InitPayload::Init {
+                ref node_id,
+                ref node_ids,
+            } => Self::init(node_id, node_ids)?,
             InitPayload::InitOk {} => bail!("Unexpected message received: {:?}", msg),
         };
+        let reply_payload = InitPayload::InitOk {};
 
         let body = Body {
             id: Some(node.new_msg_id()),
@@ -120,16 +248,85 @@ where
         };
 
         let reply = Message {
-            src: node.id(),
+            src: node.id().to_string(),
             dst: msg.src,
             body,
         };
 
         node.send(reply)?;
 
+        node.set_batch_config(BatchConfig::from_env()?);
+
         Ok(node)
     }
 
+    /// Creates new [`Node`] instance with the given ID and cluster membership without the `init` handshake,
+    /// eg. to drive a handler by [`Node::handle_line`] in tests or fuzz targets
+    pub fn with_ids(node_id: &str, node_ids: &[String]) -> anyhow::Result<Self> {
+        let mut node = Self::init(node_id, node_ids)?;
+        node.set_batch_config(BatchConfig::from_env()?);
+        Ok(node)
+    }
+
+    fn init(node_id: &str, node_ids: &[String]) -> anyhow::Result<Self> {
+        let info = NodeInfo {
+            id: node_id.into(),
+            node_ids: node_ids.iter().map(|id| id.as_str().into()).collect(),
+        };
+
+        Ok(Self {
+            info: Arc::new(info),
+            msg_ids: Arc::new(MsgIdAllocator::new()),
+            inner: Arc::new(Mutex::new(Inner {
+                rpcs: HashMap::new(),
+            })),
+            command_rx: None,
+            metrics: Arc::new(Metrics::default()),
+            queue_config: QueueConfig::from_env()?,
+            shard: 0,
+            batcher: None,
+            pipeline: PipelineConfig::from_env()?.map(|c| Arc::new(Mutex::new(Pipeline::new(c)))),
+            captured: None,
+        })
+    }
+
+    /// Captures messages sent by the node instead of writing them to STDOUT, eg. to simulate a cluster in tests.
+    /// Captured messages are returned by [`Node::take_output`].
+    pub fn capture_output(&mut self) {
+        self.captured = Some(Arc::default());
+    }
+
+    /// Returns messages captured since the last call, one JSON line each
+    pub fn take_output(&self) -> Vec<String> {
+        self.captured
+            .as_ref()
+            .map(|captured| std::mem::take(&mut *captured.lock().expect("lock")))
+            .unwrap_or_default()
+    }
+
+    /// Sets capacity and overload policy of the event queue (by default read from environment variables)
+    pub fn set_queue_config(&mut self, config: QueueConfig) {
+        self.queue_config = config;
+    }
+
+    /// Enables or disables coalescing of messages to other nodes (by default read from environment variables),
+    /// see [`batch`]
+    pub fn set_batch_config(&mut self, config: Option<BatchConfig>) {
+        self.batcher = config.map(|config| {
+            Arc::new(Batcher::new(
+                config,
+                self.id().to_string(),
+                self.metrics.clone(),
+            ))
+        });
+    }
+
+    /// Sets or removes the limit of requests in flight per destination
+    /// (by default read from environment variables), see [`pipeline`]
+    pub fn set_pipeline_config(&mut self, config: Option<PipelineConfig>) {
+        self.pipeline = config.map(|config| Arc::new(Mutex::new(Pipeline::new(config))));
+    }
+
     /// Registers Receiver part of the channel to receive commands
     pub fn register_command_receiver(&mut self, command_rx: std::sync::mpsc::Receiver<Command>)
     where
@@ -139,65 +336,211 @@ where
     }
 
     /// Starts main loop that processes incoming messages
-    pub fn run<H, Payload>(&mut self, mut handler: H) -> anyhow::Result<()>
+    pub fn run<H, Payload>(&mut self, handler: H) -> anyhow::Result<()>
+    where
+        H: Handler<Payload, Command> + Send + 'static,
+        Payload: Serialize + DeserializeOwned + Send + 'static + Sync,
+        Command: Send + 'static + Sync,
+    {
+        self.run_shards(std::io::stdin().lock(), vec![handler], |_| ShardKey::Key(0))
+    }
+
+    /// Starts main loop that processes incoming messages by `shards` instances of the handler
+    /// created by `new_handler` (called with the shard index) in parallel, see [`shard`]
+    pub fn run_sharded<H, Payload>(
+        &mut self,
+        shards: usize,
+        new_handler: impl FnMut(usize) -> H,
+    ) -> anyhow::Result<()>
+    where
+        H: ShardedHandler<Payload, Command> + Send + 'static,
+        Payload: Serialize + DeserializeOwned + Send + 'static + Sync,
+        Command: Send + 'static + Sync,
+    {
+        let handlers = (0..shards.max(1)).map(new_handler).collect();
+        self.run_shards(std::io::stdin().lock(), handlers, H::shard_key)
+    }
+
+    /// Runs the handlers on messages read from the input until it ends
+    fn run_shards<H, Payload>(
+        &mut self,
+        mut input: impl BufRead,
+        handlers: Vec<H>,
+        shard_key: fn(&Message<Payload>) -> ShardKey,
+    ) -> anyhow::Result<()>
     where
         H: Handler<Payload, Command> + Send + 'static,
         Payload: Serialize + DeserializeOwned + Send + 'static + Sync,
         Command: Send + 'static + Sync,
     {
-        let (event_tx, event_rx) = std::sync::mpsc::channel::<Event<Payload, Command>>();
-        let event_tx_clone = event_tx.clone();
+        self.queue_config
+            .validate()
+            .context("invalid event queue configuration")?;
+        let shards = handlers.len();
+        let queues: Arc<Vec<EventQueue<Event<Payload, Command>>>> = Arc::new(
+            (0..shards)
+                .map(|_| EventQueue::new(self.queue_config, self.metrics.clone()))
+                .collect(),
+        );
+        let outstanding = Arc::new(Outstanding::default());
 
         let mut cmd_jh: Option<JoinHandle<Result<_, anyhow::Error>>> = None;
         if let Some(command_rx) = self.command_rx.clone() {
             // if the node has command receiver registered,
-            // use it to create events of type Command and send it to the event channel
+            // use it to create events of type Command and send it to the event queue of every shard
+            let queues = queues.clone();
+            let outstanding = outstanding.clone();
             let jh = std::thread::spawn(move || loop {
-                let cmd = command_rx
+                let received = command_rx
                     .lock()
                     .expect("lock")
-                    .recv()
-                    .context("receive from command channel")?;
-
-                event_tx_clone
-                    .send(Event::Command(cmd))
-                    .context("sending received command to the event channel")?;
+                    .recv_timeout(COMMAND_POLL_INTERVAL);
+                let cmd = match received {
+                    Ok(cmd) => cmd,
+                    // the input ended, stop even if the handler still holds the sender
+                    Err(RecvTimeoutError::Timeout) if queues[0].is_closed() => return Ok(()),
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    // no more commands will come
+                    Err(RecvTimeoutError::Disconnected) => return Ok(()),
+                };
+
+                for queue in queues.iter() {
+                    outstanding.add();
+                    if let Push::Closed(_) =
+                        queue.push(Event::Command(cmd.clone()), EventClass::Command)
+                    {
+                        outstanding.done();
+                        return Ok(());
+                    }
+                }
             });
             cmd_jh = Some(jh);
         }
 
-        // listen for events from the event channel and handle either message or command
-        let node = self.clone();
-        let event_jh: JoinHandle<Result<_, anyhow::Error>> = std::thread::spawn(move || loop {
-            let event = event_rx.recv().context("receive from event channel")?;
-            match event {
-                Event::Message(msg) => {
-                    handler
-                        .handle(msg, node.clone())
-                        .context("handling message from the event channel")?;
-                }
-                Event::Command(cmd) => {
-                    handler
-                        .handle_command(cmd, node.clone())
-                        .context("handling command from the event channel")?;
-                }
-            }
+        // every shard listens for events from its event queue and handles either message or command
+        let mut event_jhs: Vec<JoinHandle<Result<_, anyhow::Error>>> = Vec::new();
+        for (shard, mut handler) in handlers.into_iter().enumerate() {
+            let mut node = self.clone();
+            node.shard = shard;
+            let queues = queues.clone();
+            let outstanding = outstanding.clone();
+            let jh = std::thread::spawn(move || {
+                let result = Self::handle_events(&queues[shard], &outstanding, &mut handler, node);
+                // unblock the reader if the handler failed
+                outstanding.abort();
+                queues.iter().for_each(EventQueue::close);
+                result
+            });
+            event_jhs.push(jh);
+        }
+
+        // periodically send batches of messages to other nodes
+        let batch_jh = self.batcher.clone().map(|batcher| {
+            std::thread::spawn(move || batcher.run().context("sending batches of messages"))
         });
 
-        // send incoming messages to event channel as a Message events
-        let incoming_messages = self.messages::<Message<Payload>>();
+        // periodically free pipeline slots of timed out requests, also when no input arrives
+        let (expiry_stop, expiry_rx) = std::sync::mpsc::channel::<()>();
+        let expiry_jh = self.pipeline.as_ref().map(|pipeline| {
+            let interval = pipeline.lock().expect("lock").expiry_interval();
+            let node = self.clone();
+            std::thread::spawn(move || loop {
+                match expiry_rx.recv_timeout(interval) {
+                    Err(RecvTimeoutError::Timeout) => node
+                        .release_expired()
+                        .context("sending pipelined requests")?,
+                    // the input ended
+                    _ => return Ok::<_, anyhow::Error>(()),
+                }
+            })
+        });
 
+        // send incoming messages to event queues as a Message (or Admin) events
         let mut error: Option<anyhow::Error> = None;
-        for msg in incoming_messages {
-            let msg = msg.context("deserializing Maelstrom message from STDIN failed")?;
-            if let Err(e) = event_tx
-                .send(Event::Message(msg))
-                .context("sending message from stdin to the event channel")
+        // reused for all lines
+        let mut line = String::new();
+        'reader: loop {
+            line.clear();
+            if input
+                .read_line(&mut line)
+                .context("reading line from STDIN")?
+                == 0
             {
-                error = Some(e);
                 break;
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let events = match codec::decode_line(&line) {
+                Ok(events) => events,
+                Err(err) => {
+                    self.reject_malformed(&line, &err)
+                        .context("rejecting malformed message")?;
+                    continue;
+                }
             };
+            for event in events {
+                self.metrics.message_received();
+                let class = event_class(&event);
+                if let Event::Message(Message {
+                    body:
+                        Body {
+                            in_reply_to: Some(in_reply_to),
+                            ..
+                        },
+                    ..
+                }) = &event
+                {
+                    self.reply_received(*in_reply_to)
+                        .context("sending pipelined requests")?;
+                }
+
+                let (shard, barrier) = match &event {
+                    Event::Message(msg) => {
+                        match msg.body.in_reply_to.and_then(|id| self.rpc_shard(id)) {
+                            Some(shard) => (shard, false),
+                            None => match shard_key(msg) {
+                                ShardKey::Key(hash) => ((hash % shards as u64) as usize, false),
+                                ShardKey::Barrier => (0, shards > 1),
+                            },
+                        }
+                    }
+                    Event::Admin(_) => (0, shards > 1),
+                    Event::Command(_) => unreachable!("commands are not received from STDIN"),
+                };
+
+                if barrier {
+                    outstanding.wait_idle();
+                }
+                outstanding.add();
+                let pushed = queues[shard].push(event, class);
+                if !matches!(pushed, Push::Queued) {
+                    outstanding.done();
+                }
+                match pushed {
+                    Push::Queued => {}
+                    Push::Shed(event) => {
+                        if let Event::Message(msg) = event {
+                            self.reply_error(
+                                msg,
+                                error::TEMPORARILY_UNAVAILABLE,
+                                "node is overloaded",
+                            )
+                            .context("rejecting client request")?;
+                        }
+                    }
+                    Push::Closed(_) => {
+                        error = Some(anyhow!("event queue was closed"));
+                        break 'reader;
+                    }
+                }
+                if barrier {
+                    outstanding.wait_idle();
+                }
+            }
         }
+        queues.iter().for_each(EventQueue::close);
 
         if let Some(cmd_jh) = cmd_jh {
             cmd_jh
@@ -205,10 +548,26 @@ where
                 .expect("could not join command thread")
                 .context("command thread errored")?;
         }
-        event_jh
-            .join()
-            .expect("could not join event thread")
-            .context("event thread errored")?;
+        for event_jh in event_jhs {
+            event_jh
+                .join()
+                .expect("could not join event thread")
+                .context("event thread errored")?;
+        }
+
+        drop(expiry_stop);
+        if let Some(expiry_jh) = expiry_jh {
+            expiry_jh.join().expect("could not join expiry thread")?;
+        }
+
+        if let (Some(batcher), Some(batch_jh)) = (&self.batcher, batch_jh) {
+            // send messages remaining in batches
+            batcher.stop();
+            batch_jh.join().expect("could not join batch thread")?;
+        }
+
+        // drop the receiver, so threads producing commands see the node has stopped
+        self.command_rx = None;
 
         if let Some(err) = error {
             // log input loop error only after threads finished
@@ -218,6 +577,142 @@ where
         Ok(())
     }
 
+    /// Decodes the line and handles its events right away, without the event queue and the runtime threads,
+    /// eg. to drive a handler in fuzz targets. Malformed lines are rejected and replies free pipeline slots
+    /// the same way as by the main loop.
+    pub fn handle_line<H, Payload>(&self, handler: &mut H, line: &str) -> anyhow::Result<()>
+    where
+        H: Handler<Payload, Command>,
+        Payload: Serialize + DeserializeOwned,
+    {
+        let events = match codec::decode_line(line) {
+            Ok(events) => events,
+            Err(err) => return self.reject_malformed(line, &err),
+        };
+        for event in events {
+            self.metrics.message_received();
+            if let Event::Message(Message {
+                body:
+                    Body {
+                        in_reply_to: Some(in_reply_to),
+                        ..
+                    },
+                ..
+            }) = &event
+            {
+                self.reply_received(*in_reply_to)
+                    .context("sending pipelined requests")?;
+            }
+            Self::handle_event(event, handler, self)?;
+        }
+
+        Ok(())
+    }
+
+    /// Answers request that could not be decoded with [`MALFORMED_REQUEST`](error::MALFORMED_REQUEST) error,
+    /// other malformed lines are only logged
+    fn reject_malformed(&self, line: &str, err: &anyhow::Error) -> anyhow::Result<()> {
+        match serde_json::from_str::<Message<serde_json::Value>>(line) {
+            Ok(msg) if msg.body.id.is_some() && msg.body.in_reply_to.is_none() => {
+                let text = format!("malformed request: {err}");
+                self.clone()
+                    .reply_error(msg, error::MALFORMED_REQUEST, &text)
+            }
+            _ => {
+                eprintln!(
+                    "WARN: ignoring malformed message: {err}: {}",
+                    line.trim_end()
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Handles events from the event queue until the queue is closed
+    fn handle_events<H, Payload>(
+        queue: &EventQueue<Event<Payload, Command>>,
+        outstanding: &Outstanding,
+        handler: &mut H,
+        node: Self,
+    ) -> anyhow::Result<()>
+    where
+        H: Handler<Payload, Command>,
+        Payload: Serialize,
+    {
+        while let Some(event) = queue.pop() {
+            Self::handle_event(event, handler, &node)?;
+            outstanding.done();
+        }
+
+        Ok(())
+    }
+
+    fn handle_event<H, Payload>(
+        event: Event<Payload, Command>,
+        handler: &mut H,
+        node: &Self,
+    ) -> anyhow::Result<()>
+    where
+        H: Handler<Payload, Command>,
+        Payload: Serialize,
+    {
+        match event {
+            Event::Message(msg) => {
+                if let Some(in_reply_to) = msg.body.in_reply_to {
+                    node.complete_rpc(in_reply_to);
+                }
+                handler
+                    .handle(msg, node.clone())
+                    .context("handling message from the event channel")?;
+                node.metrics.message_handled();
+            }
+            Event::Command(cmd) => {
+                handler
+                    .handle_command(cmd, node.clone())
+                    .context("handling command from the event channel")?;
+                node.metrics.command_handled();
+            }
+            Event::Admin(msg) => {
+                let state = AdminPayload::StateOk {
+                    node_id: node.id().to_string(),
+                    handler: handler.debug_state(),
+                    metrics: Box::new(node.metrics.snapshot()),
+                    in_flight: node.in_flight_rpcs(),
+                };
+                node.clone()
+                    .reply(msg, state)
+                    .context("replying to admin message")?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Replies to the incoming message with Maelstrom error
+    pub fn reply_error<P>(
+        &mut self,
+        incoming_msg: Message<P>,
+        code: u16,
+        text: &str,
+    ) -> anyhow::Result<()> {
+        let body = Body {
+            id: Some(self.new_msg_id()),
+            in_reply_to: incoming_msg.body.id,
+            payload: error::ErrorPayload {
+                code,
+                text: text.to_string(),
+            },
+        };
+
+        let reply = Message {
+            src: self.id().to_string(),
+            dst: incoming_msg.src,
+            body,
+        };
+
+        self.send(reply)
+    }
+
     /// Replies to the incoming message with a reply with specified new payload
     pub fn reply<P>(&mut self, incoming_msg: Message<P>, new_payload: P) -> anyhow::Result<()>
     where
@@ -230,7 +725,7 @@ where
         };
 
         let reply = Message {
-            src: self.id(),
+            src: self.id().to_string(),
             dst: incoming_msg.src,
             body,
         };
@@ -239,11 +734,16 @@ where
     }
 
     /// Sends new message with `payload` to `dst` and returns message id
-    pub fn send_to<P>(&mut self, dst: &str, payload: P) -> anyhow::Result<usize>
+    ///
+    /// The message is recorded in the table of in-flight requests until a reply to it arrives.
+    /// If the pipelining is enabled and `dst` has too many requests in flight, the message is queued
+    /// and sent later.
+    pub fn send_to<P>(&mut self, dst: &str, payload: P) -> anyhow::Result<MsgId>
     where
         P: Serialize,
     {
         let msg_id = self.new_msg_id();
+        self.start_rpc(msg_id, dst);
 
         let body = Body {
             id: Some(msg_id),
@@ -252,60 +752,247 @@ where
         };
 
         let reply = Message {
-            src: self.id(),
+            src: self.id().to_string(),
             dst: dst.to_owned(),
             body,
         };
 
-        self.send(reply)?;
+        if let Some(pipeline) = &self.pipeline {
+            let msg = codec::encode_raw(&reply)?;
+            let admitted = pipeline.lock().expect("lock").admit(dst, msg_id, msg);
+            match admitted {
+                Some(msg) => self.write(dst, &msg)?,
+                None => self.metrics.request_queued(),
+            }
+        } else {
+            self.send(reply)?;
+        }
 
         Ok(msg_id)
     }
 
+    /// Sends one-way message with `payload` to `dst`, ie. a message the receiver does not reply to
+    ///
+    /// Unlike [`Node::send_to`] the message is not recorded as an in-flight request
+    /// and the pipelining never holds it back.
+    pub fn notify<P>(&mut self, dst: &str, payload: P) -> anyhow::Result<()>
+    where
+        P: Serialize,
+    {
+        let msg = Message {
+            src: self.id().to_string(),
+            dst: dst.to_owned(),
+            body: Body {
+                id: Some(self.new_msg_id()),
+                in_reply_to: None,
+                payload,
+            },
+        };
+
+        self.send(msg)
+    }
+
     /// Sends provided message
     fn send<P>(&mut self, msg: Message<P>) -> anyhow::Result<()>
     where
         P: Serialize,
     {
-        let mut stdout = std::io::stdout();
+        self.write(&msg.dst, &msg)
+    }
+
+    /// Writes message to `dst`, messages to other nodes may be coalesced by the batcher
+    fn write<T: Serialize>(&self, dst: &str, msg: &T) -> anyhow::Result<()> {
+        if let Some(captured) = &self.captured {
+            let line = serde_json::to_string(msg).context("serializing message")?;
+            captured.lock().expect("lock").push(line);
+        } else {
+            match &self.batcher {
+                Some(batcher) if self.is_peer(dst) => batcher.push(dst, msg)?,
+                _ => codec::write_message(msg)?,
+            }
+        }
+        self.metrics.message_sent(self.is_peer(dst));
 
-        serde_json::to_writer(&mut stdout, &msg).context("writing message to STDOUT")?;
-        stdout.write_all(b"\n").context("write new line")?;
+        Ok(())
+    }
+
+    /// Frees the pipeline slot of the request when the reply to it arrives and sends queued requests
+    fn reply_received(&self, msg_id: MsgId) -> anyhow::Result<()> {
+        let Some(pipeline) = &self.pipeline else {
+            return Ok(());
+        };
+        let Some(dst) = self.rpc_dst(msg_id) else {
+            return Ok(());
+        };
+
+        let released = pipeline.lock().expect("lock").complete(&dst, msg_id);
+        self.send_released(released)
+    }
+
+    /// Frees the pipeline slots of timed out requests and sends queued requests
+    fn release_expired(&self) -> anyhow::Result<()> {
+        let Some(pipeline) = &self.pipeline else {
+            return Ok(());
+        };
+
+        let released = pipeline.lock().expect("lock").expire();
+        self.send_released(released)
+    }
+
+    fn send_released(&self, released: Vec<pipeline::Released>) -> anyhow::Result<()> {
+        for request in released {
+            self.metrics.request_released(request.delay);
+            self.write(&request.dst, &request.msg)?;
+        }
 
         Ok(())
     }
 
+    /// Returns `true` if the ID belongs to other node of the cluster
+    fn is_peer(&self, node_id: &str) -> bool {
+        *self.info.id != *node_id && self.info.node_ids.iter().any(|id| **id == *node_id)
+    }
+
     /// Returns node's ID
-    pub fn id(&self) -> String {
-        self.inner.lock().expect("lock").id.clone()
+    pub fn id(&self) -> Arc<str> {
+        self.info.id.clone()
     }
 
     /// Returns the list of all nodes in the cluster
-    pub fn node_ids(&self) -> Vec<String> {
-        self.inner.lock().expect("lock").node_ids.clone()
+    pub fn node_ids(&self) -> Arc<[Arc<str>]> {
+        self.info.node_ids.clone()
     }
 
-    /// Returns iterator over deserialized incoming messages
-    fn messages<T>(&mut self) -> impl Iterator<Item = Result<T, serde_json::Error>>
-    where
-        T: for<'a> Deserialize<'a>,
-    {
-        serde_json::Deserializer::from_reader(std::io::stdin()).into_iter::<T>()
+    /// Returns runtime metrics
+    pub fn metrics(&self) -> metrics::MetricsSnapshot {
+        self.metrics.snapshot()
     }
 
-    /// Produces new message ID
-    fn new_msg_id(&mut self) -> usize {
+    /// Returns requests sent by us that are still waiting for a reply
+    pub fn in_flight_rpcs(&self) -> Vec<InFlightRpc> {
+        let mut rpcs: Vec<_> = self
+            .inner
+            .lock()
+            .expect("lock")
+            .rpcs
+            .iter()
+            .map(|(&msg_id, rpc)| InFlightRpc {
+                msg_id,
+                dest: rpc.dst.clone(),
+                age_ms: rpc.sent_at.elapsed().as_millis() as u64,
+            })
+            .collect();
+        rpcs.sort_by_key(|rpc| rpc.msg_id);
+        rpcs
+    }
+
+    /// Records request sent to `dst` in the table of in-flight requests
+    fn start_rpc(&self, msg_id: MsgId, dst: &str) {
         let mut node = self.inner.lock().expect("lock");
-        let msg_id = node.msg_id;
-        node.msg_id += 1;
-        msg_id
+        if node.rpcs.len() >= RPC_TABLE_PRUNE_SIZE {
+            // requests that never got a reply (eg. lost in a network partition)
+            node.rpcs
+                .retain(|_, rpc| rpc.sent_at.elapsed() < RPC_TIMEOUT);
+        }
+        let rpc = Rpc {
+            dst: dst.to_string(),
+            sent_at: Instant::now(),
+            shard: self.shard,
+        };
+        node.rpcs.insert(msg_id, rpc);
+    }
+
+    /// Returns shard that sent the request with the ID
+    fn rpc_shard(&self, msg_id: MsgId) -> Option<usize> {
+        let node = self.inner.lock().expect("lock");
+        node.rpcs.get(&msg_id).map(|rpc| rpc.shard)
+    }
+
+    /// Returns destination of the request with the ID
+    fn rpc_dst(&self, msg_id: MsgId) -> Option<String> {
+        let node = self.inner.lock().expect("lock");
+        node.rpcs.get(&msg_id).map(|rpc| rpc.dst.clone())
+    }
+
+    /// Removes request from the table of in-flight requests when the reply arrives
+    fn complete_rpc(&self, msg_id: MsgId) {
+        self.inner.lock().expect("lock").rpcs.remove(&msg_id);
+    }
+
+    /// Produces new message ID
+    fn new_msg_id(&self) -> MsgId {
+        self.msg_ids.next()
+    }
+}
+
+pub mod admin {
+    //! Admin messages handled by the node runtime instead of the handler
+    //!
+    //! Send `{"src":"c0","dest":"n1","body":{"type":"__gossipy_state","msg_id":1}}` to the node
+    //! (eg. manually over STDIN) to get the handler's state, runtime metrics
+    //! and the table of in-flight requests.
+
+    use serde::{Deserialize, Serialize};
+
+    use crate::{metrics::MetricsSnapshot, MsgId};
+
+    /// Type of the admin message asking the node for its state
+    pub const STATE_MSG_TYPE: &str = "__gossipy_state";
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(tag = "type")]
+    pub enum AdminPayload {
+        #[serde(rename = "__gossipy_state")]
+        State,
+        #[serde(rename = "__gossipy_state_ok")]
+        StateOk {
+            node_id: String,
+            /// State returned by [`Handler::debug_state`](crate::Handler::debug_state)
+            handler: Option<serde_json::Value>,
+            metrics: Box<MetricsSnapshot>,
+            in_flight: Vec<InFlightRpc>,
+        },
+    }
+
+    /// Request sent by the node that is still waiting for a reply
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct InFlightRpc {
+        pub msg_id: MsgId,
+        pub dest: String,
+        pub age_ms: u64,
     }
 }
 
-pub mod kv_store {
-    pub const SEQ_KV_SERVICE_ID: &str = "seq-kv";
-    pub const LIN_KV_SERVICE_ID: &str = "lin-kv";
+pub mod error {
+    //! Maelstrom error codes
+    //!
+    //! See <https://github.com/jepsen-io/maelstrom/blob/main/doc/protocol.md#errors>
+
+    use serde::{Deserialize, Serialize};
+
+    pub const TIMEOUT: u16 = 0;
+    pub const NODE_NOT_FOUND: u16 = 1;
+    pub const NOT_SUPPORTED: u16 = 10;
+    pub const TEMPORARILY_UNAVAILABLE: u16 = 11;
+    pub const MALFORMED_REQUEST: u16 = 12;
+    pub const CRASH: u16 = 13;
+    pub const ABORT: u16 = 14;
+    pub const KEY_DOES_NOT_EXIST: u16 = 20;
+    pub const KEY_ALREADY_EXISTS: u16 = 21;
+    pub const PRECONDITION_FAILED: u16 = 22;
+    pub const TXN_CONFLICT: u16 = 30;
+
+    /// Returns `true` if the error means that the request certainly did not take effect,
+    /// after other errors (eg. timeout) the request may or may not have taken effect
+    pub fn is_definite(code: u16) -> bool {
+        !matches!(code, TIMEOUT | CRASH) && code < 1000
+    }
 
-    pub const ERROR_KEY_DOES_NOT_EXIST: u16 = 20;
-    pub const ERROR_PRECONDITION_FAILED: u16 = 22;
+    /// Payload of Maelstrom error message
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(tag = "type", rename = "error")]
+    pub struct ErrorPayload {
+        pub code: u16,
+        pub text: String,
+    }
 }
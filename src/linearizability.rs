@@ -0,0 +1,359 @@
+//! Linearizability checker of key-value histories
+//!
+//! Checks histories of `read`, `write` and `cas` operations on registers (the Maelstrom `lin-kv` workload)
+//! the way Knossos and Porcupine do: it searches for an order of the operations consistent with their
+//! real-time order in which every operation observes the register state left by the previous ones.
+//! - operation values are `[key, value]` for `read` and `write` and `[key, [from, to]]` for `cas`,
+//!   a read of a missing key returns `null`
+//! - registers are independent, so the history is partitioned by key and every key is checked alone
+//! - failed operations did not happen, indefinite (`info`) operations may have taken effect
+//!   at any time after their invocation or not at all
+//! - the search is the just-in-time linearization of Lowe with memoization of visited
+//!   `(linearized operations, state)` pairs
+//!
+//! When a key is not linearizable, its history is shrunk to a minimal counterexample: a subset of
+//! the operations that is still not linearizable, but becomes linearizable after removing any
+//! of its operations.
+
+use std::collections::{BTreeMap, HashSet};
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::history::{self, Op, OpType};
+
+/// Operation on a register
+#[derive(Debug, Clone, PartialEq)]
+enum Call {
+    /// Read returning the value, `None` if the register is empty
+    Read(Option<String>),
+    Write(String),
+    Cas {
+        from: String,
+        to: String,
+    },
+}
+
+/// Operation of the checked history
+#[derive(Debug, Clone)]
+struct Operation<'a> {
+    call: Call,
+    invoked_at: u64,
+    /// `None` if the operation is indefinite
+    completed_at: Option<u64>,
+    /// Invocation and completion
+    ops: (&'a Op, Option<&'a Op>),
+}
+
+impl Operation<'_> {
+    /// Applies operation to the state, returns `None` if the operation is not possible in the state
+    fn step(&self, state: &Option<String>) -> Option<Option<String>> {
+        match &self.call {
+            Call::Read(value) => (value == state).then(|| state.clone()),
+            Call::Write(value) => Some(Some(value.clone())),
+            Call::Cas { from, to } => (state.as_ref() == Some(from)).then(|| Some(to.clone())),
+        }
+    }
+
+    /// Returns value written by the operation
+    fn writes(&self) -> Option<&str> {
+        match &self.call {
+            Call::Read(_) => None,
+            Call::Write(value) | Call::Cas { to: value, .. } => Some(value),
+        }
+    }
+
+    /// Returns value the operation requires in the register
+    fn observes(&self) -> Option<&str> {
+        match &self.call {
+            Call::Read(value) => value.as_deref(),
+            Call::Write(_) => None,
+            Call::Cas { from, .. } => Some(from),
+        }
+    }
+
+    fn describe(&self) -> Value {
+        let (invoke, completion) = self.ops;
+        json!({
+            "process": invoke.process,
+            "f": invoke.f,
+            "value": completion.map_or(&invoke.value, |op| &op.value),
+            "type": completion.map_or(OpType::Info, |op| op.ty),
+            "invoke-time": self.invoked_at,
+            "complete-time": self.completed_at,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LinearizabilityResult {
+    #[serde(rename = "valid?")]
+    pub valid: bool,
+    pub key_count: usize,
+    /// Number of checked operations
+    pub op_count: usize,
+    /// Keys that are not linearizable
+    pub failures: Vec<KeyFailure>,
+}
+
+/// Minimal non-linearizable history of the key
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct KeyFailure {
+    pub key: Value,
+    /// Operations of the key in the history
+    pub op_count: usize,
+    /// Operations ordered by invocation time
+    pub counterexample: Vec<Value>,
+}
+
+/// Checks linearizability of the key-value history
+pub fn check(history: &[Op]) -> LinearizabilityResult {
+    let mut keys: BTreeMap<String, (Value, Vec<Operation>)> = BTreeMap::new();
+    for (invoke, completion) in history::pairs(history) {
+        let Some((key, operation)) = operation(invoke, completion) else {
+            continue;
+        };
+        keys.entry(key.to_string())
+            .or_insert_with(|| (key, Vec::new()))
+            .1
+            .push(operation);
+    }
+
+    let mut op_count = 0;
+    let mut failures = Vec::new();
+    for (key, operations) in keys.values() {
+        op_count += operations.len();
+        if linearizable(operations) {
+            continue;
+        }
+        let counterexample = shrink(operations.clone());
+        failures.push(KeyFailure {
+            key: key.clone(),
+            op_count: operations.len(),
+            counterexample: counterexample.iter().map(Operation::describe).collect(),
+        });
+    }
+
+    LinearizabilityResult {
+        valid: failures.is_empty(),
+        key_count: keys.len(),
+        op_count,
+        failures,
+    }
+}
+
+/// Returns key and operation to check, `None` if the operation did not happen or says nothing
+/// about the state (eg. indefinite read)
+fn operation<'a>(invoke: &'a Op, completion: Option<&'a Op>) -> Option<(Value, Operation<'a>)> {
+    let ty = completion.map_or(OpType::Info, |op| op.ty);
+    if ty == OpType::Fail {
+        return None;
+    }
+    let value = completion
+        .filter(|op| op.ty == OpType::Ok)
+        .map_or(&invoke.value, |op| &op.value);
+    let key = value.get(0)?.clone();
+    let argument = value.get(1)?;
+
+    let call = match invoke.f.as_str() {
+        "read" if ty == OpType::Ok => Call::Read(
+            Some(argument)
+                .filter(|v| !v.is_null())
+                .map(Value::to_string),
+        ),
+        "write" => Call::Write(argument.to_string()),
+        "cas" => Call::Cas {
+            from: argument.get(0)?.to_string(),
+            to: argument.get(1)?.to_string(),
+        },
+        _ => return None,
+    };
+    let completed_at = completion
+        .filter(|op| op.ty == OpType::Ok)
+        .map(|op| op.time);
+    Some((
+        key,
+        Operation {
+            call,
+            invoked_at: invoke.time,
+            completed_at,
+            ops: (invoke, completion),
+        },
+    ))
+}
+
+/// Invocation or completion of an operation in the linked list of events
+#[derive(Debug, Clone, Copy)]
+struct Event {
+    op: usize,
+    is_call: bool,
+    /// Completion of the invoked operation
+    completion: Option<usize>,
+    prev: usize,
+    next: Option<usize>,
+}
+
+/// Set of linearized operations
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct BitSet(Vec<u64>);
+
+impl BitSet {
+    fn new(n: usize) -> Self {
+        Self(vec![0; n.div_ceil(64)])
+    }
+
+    fn set(&mut self, i: usize) {
+        self.0[i / 64] |= 1 << (i % 64);
+    }
+
+    fn clear(&mut self, i: usize) {
+        self.0[i / 64] &= !(1 << (i % 64));
+    }
+}
+
+/// Returns `true` if the operations of one register are linearizable
+fn linearizable(operations: &[Operation]) -> bool {
+    // events sorted by time, index 0 is the head of the list
+    let mut times: Vec<(u64, bool, usize)> = Vec::new();
+    for (i, op) in operations.iter().enumerate() {
+        // invocations first, so operations completed and invoked at the same time are concurrent
+        times.push((op.invoked_at, false, i));
+        if let Some(completed_at) = op.completed_at {
+            times.push((completed_at, true, i));
+        }
+    }
+    times.sort();
+
+    let mut events = vec![Event {
+        op: usize::MAX,
+        is_call: false,
+        completion: None,
+        prev: 0,
+        next: None,
+    }];
+    let mut calls = vec![0; operations.len()];
+    for (n, (_, is_return, op)) in times.iter().enumerate() {
+        let i = n + 1;
+        events[i - 1].next = Some(i);
+        events.push(Event {
+            op: *op,
+            is_call: !is_return,
+            completion: None,
+            prev: i - 1,
+            next: None,
+        });
+        if *is_return {
+            events[calls[*op]].completion = Some(i);
+        } else {
+            calls[*op] = i;
+        }
+    }
+
+    // removes the event from the list
+    fn lift(events: &mut [Event], i: usize) {
+        let Event { prev, next, .. } = events[i];
+        events[prev].next = next;
+        if let Some(next) = next {
+            events[next].prev = prev;
+        }
+    }
+    // puts the removed event back to the list
+    fn unlift(events: &mut [Event], i: usize) {
+        let Event { prev, next, .. } = events[i];
+        events[prev].next = Some(i);
+        if let Some(next) = next {
+            events[next].prev = i;
+        }
+    }
+
+    let mut state: Option<String> = None;
+    let mut linearized = BitSet::new(operations.len());
+    let mut cache: HashSet<(BitSet, Option<String>)> = HashSet::new();
+    // (linearized call, state before it)
+    let mut stack: Vec<(usize, Option<String>)> = Vec::new();
+    let mut current = events[0].next;
+    loop {
+        let Some(i) = current else {
+            // only indefinite operations are left, they need not take effect
+            return true;
+        };
+        let event = events[i];
+        if event.is_call {
+            let op = &operations[event.op];
+            if let Some(next_state) = op.step(&state) {
+                let mut next_linearized = linearized.clone();
+                next_linearized.set(event.op);
+                if cache.insert((next_linearized.clone(), next_state.clone())) {
+                    stack.push((i, std::mem::replace(&mut state, next_state)));
+                    linearized = next_linearized;
+                    // the return is lifted first, so the call is restored last
+                    if let Some(completion) = event.completion {
+                        lift(&mut events, completion);
+                    }
+                    lift(&mut events, i);
+                    current = events[0].next;
+                    continue;
+                }
+            }
+            current = event.next;
+        } else {
+            // completion of an operation that could not be linearized before it, backtrack
+            let Some((call, previous)) = stack.pop() else {
+                return false;
+            };
+            state = previous;
+            linearized.clear(events[call].op);
+            unlift(&mut events, call);
+            if let Some(completion) = events[call].completion {
+                unlift(&mut events, completion);
+            }
+            current = events[call].next;
+        }
+    }
+}
+
+/// Removes operations from non-linearizable history while it stays non-linearizable
+///
+/// Writes of the values observed by the remaining operations are kept, so the counterexample does not
+/// degenerate to a read of a value that was written by a removed operation.
+fn shrink(mut operations: Vec<Operation>) -> Vec<Operation> {
+    let written: HashSet<String> = operations
+        .iter()
+        .filter_map(Operation::writes)
+        .map(str::to_string)
+        .collect();
+    let explained = |candidate: &[Operation]| {
+        let writes: HashSet<&str> = candidate.iter().filter_map(Operation::writes).collect();
+        candidate
+            .iter()
+            .filter_map(Operation::observes)
+            .all(|value| !written.contains(value) || writes.contains(value))
+    };
+
+    let mut chunk = operations.len().div_ceil(2).max(1);
+    loop {
+        let mut removed = false;
+        let mut start = 0;
+        while start < operations.len() {
+            let end = (start + chunk).min(operations.len());
+            let mut candidate = operations.clone();
+            candidate.drain(start..end);
+            if !candidate.is_empty() && explained(&candidate) && !linearizable(&candidate) {
+                operations = candidate;
+                removed = true;
+            } else {
+                start = end;
+            }
+        }
+        if chunk == 1 && !removed {
+            break;
+        }
+        if !removed {
+            chunk = chunk.div_ceil(2);
+        }
+    }
+    operations.sort_by_key(|op| op.invoked_at);
+    operations
+}
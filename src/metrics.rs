@@ -0,0 +1,183 @@
+//! Runtime metrics
+//!
+//! Counters maintained by the node runtime, they can be inspected with the admin
+//! [`STATE_MSG_TYPE`](crate::admin::STATE_MSG_TYPE) message.
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::queue::{Priority, PRIORITIES};
+
+/// Runtime counters shared by all clones of the node
+#[derive(Debug)]
+pub struct Metrics {
+    started: Instant,
+    messages_received: AtomicU64,
+    messages_sent: AtomicU64,
+    messages_sent_to_nodes: AtomicU64,
+    messages_handled: AtomicU64,
+    commands_handled: AtomicU64,
+    queue_depth: AtomicU64,
+    queue_max_depth: AtomicU64,
+    queue_depth_by_priority: [AtomicU64; PRIORITIES],
+    events_shed: AtomicU64,
+    reader_blocked: AtomicU64,
+    batches_sent: AtomicU64,
+    messages_batched: AtomicU64,
+    requests_queued: AtomicU64,
+    requests_waiting: AtomicU64,
+    request_delay_total_us: AtomicU64,
+    request_delay_max_us: AtomicU64,
+}
+
+/// Point-in-time copy of the runtime counters
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetricsSnapshot {
+    pub uptime_ms: u64,
+    pub messages_received: u64,
+    pub messages_sent: u64,
+    /// Number of the sent messages that went to other nodes of the cluster
+    pub messages_sent_to_nodes: u64,
+    pub messages_handled: u64,
+    pub commands_handled: u64,
+    /// Number of events waiting in the event queue
+    pub queue_depth: u64,
+    /// Maximum observed number of events waiting in the event queue
+    pub queue_max_depth: u64,
+    /// Number of events waiting in the event queue for every [`Priority`] class
+    pub queue_depth_by_priority: [u64; PRIORITIES],
+    /// Number of client requests rejected because the event queue was full
+    pub events_shed: u64,
+    /// Number of times the reader had to wait for a free space in the event queue
+    pub reader_blocked: u64,
+    /// Number of envelopes carrying multiple messages sent to other nodes
+    pub batches_sent: u64,
+    /// Number of messages sent in the envelopes
+    pub messages_batched: u64,
+    /// Number of requests that had to wait because their destination had too many requests in flight
+    pub requests_queued: u64,
+    /// Number of requests currently waiting for a free slot
+    pub requests_waiting: u64,
+    /// Average time spent by the queued requests waiting for a free slot
+    pub request_delay_avg_ms: f64,
+    /// Maximum time spent by a request waiting for a free slot
+    pub request_delay_max_ms: f64,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            messages_received: AtomicU64::new(0),
+            messages_sent: AtomicU64::new(0),
+            messages_sent_to_nodes: AtomicU64::new(0),
+            messages_handled: AtomicU64::new(0),
+            commands_handled: AtomicU64::new(0),
+            queue_depth: AtomicU64::new(0),
+            queue_max_depth: AtomicU64::new(0),
+            queue_depth_by_priority: Default::default(),
+            events_shed: AtomicU64::new(0),
+            reader_blocked: AtomicU64::new(0),
+            batches_sent: AtomicU64::new(0),
+            messages_batched: AtomicU64::new(0),
+            requests_queued: AtomicU64::new(0),
+            requests_waiting: AtomicU64::new(0),
+            request_delay_total_us: AtomicU64::new(0),
+            request_delay_max_us: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Metrics {
+    pub(crate) fn message_received(&self) {
+        self.messages_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn message_sent(&self, to_node: bool) {
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+        if to_node {
+            self.messages_sent_to_nodes.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn message_handled(&self) {
+        self.messages_handled.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn command_handled(&self) {
+        self.commands_handled.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn queue_depth(&self, depth: usize, priority: Priority, priority_depth: usize) {
+        self.queue_depth_by_priority[priority as usize]
+            .store(priority_depth as u64, Ordering::Relaxed);
+        self.queue_depth.store(depth as u64, Ordering::Relaxed);
+        self.queue_max_depth
+            .fetch_max(depth as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn event_shed(&self) {
+        self.events_shed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn reader_blocked(&self) {
+        self.reader_blocked.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn batch_sent(&self, messages: usize) {
+        self.batches_sent.fetch_add(1, Ordering::Relaxed);
+        self.messages_batched
+            .fetch_add(messages as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn request_queued(&self) {
+        self.requests_queued.fetch_add(1, Ordering::Relaxed);
+        self.requests_waiting.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn request_released(&self, delay: Duration) {
+        let delay = delay.as_micros() as u64;
+        self.requests_waiting.fetch_sub(1, Ordering::Relaxed);
+        self.request_delay_total_us
+            .fetch_add(delay, Ordering::Relaxed);
+        self.request_delay_max_us
+            .fetch_max(delay, Ordering::Relaxed);
+    }
+
+    /// Returns current values of the counters
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let requests_queued = self.requests_queued.load(Ordering::Relaxed);
+        let requests_waiting = self.requests_waiting.load(Ordering::Relaxed);
+        let requests_released = requests_queued - requests_waiting;
+        let delay_total_us = self.request_delay_total_us.load(Ordering::Relaxed);
+
+        MetricsSnapshot {
+            uptime_ms: self.started.elapsed().as_millis() as u64,
+            messages_received: self.messages_received.load(Ordering::Relaxed),
+            messages_sent: self.messages_sent.load(Ordering::Relaxed),
+            messages_sent_to_nodes: self.messages_sent_to_nodes.load(Ordering::Relaxed),
+            messages_handled: self.messages_handled.load(Ordering::Relaxed),
+            commands_handled: self.commands_handled.load(Ordering::Relaxed),
+            queue_depth: self.queue_depth.load(Ordering::Relaxed),
+            queue_max_depth: self.queue_max_depth.load(Ordering::Relaxed),
+            queue_depth_by_priority: std::array::from_fn(|p| {
+                self.queue_depth_by_priority[p].load(Ordering::Relaxed)
+            }),
+            events_shed: self.events_shed.load(Ordering::Relaxed),
+            reader_blocked: self.reader_blocked.load(Ordering::Relaxed),
+            batches_sent: self.batches_sent.load(Ordering::Relaxed),
+            messages_batched: self.messages_batched.load(Ordering::Relaxed),
+            requests_queued,
+            requests_waiting,
+            request_delay_avg_ms: match requests_released {
+                0 => 0.0,
+                n => delay_total_us as f64 / n as f64 / 1000.0,
+            },
+            request_delay_max_ms: self.request_delay_max_us.load(Ordering::Relaxed) as f64 / 1000.0,
+        }
+    }
+}
@@ -0,0 +1,210 @@
+//! Adaptive gossip pacing
+//!
+//! Picks the gossip interval and fanout from what the node observes instead of a hand tuned interval.
+//! The operator sets a budget of messages between nodes per broadcast value (`msgs_per_op`), the pacer
+//! measures how many values the node learns per second and how many messages a gossip round costs,
+//! and gossips as often as the budget allows:
+//! - the cost of a round (gossip messages and their acknowledgements, at full fanout) divided by the budget
+//!   per node gives the interval
+//! - the interval is never shorter than the round-trip time of gossip acknowledgements, unacknowledged
+//!   values would be sent again in the next round
+//! - the interval is never longer than `max_interval` to bound latency, if the budget requires longer rounds,
+//!   they go to a random subset of the peers instead
+//!
+//! Rounds without unacknowledged values (backlog) and without new values cost nothing and say nothing about
+//! the cost of gossip, so they do not change the pace.
+//!
+//! The pacer is fed by [`Pacer::acked`] with round-trip times of acknowledgements and by [`Pacer::round`]
+//! in every gossip round, which returns the [`Pace`] of the next rounds.
+//!
+//! Configuration is read from environment variables:
+//! - `GOSSIPY_MSGS_PER_OP` - budget of messages per value (default 25)
+//! - `GOSSIPY_MIN_GOSSIP_INTERVAL_MS` - shortest interval (default 50)
+//! - `GOSSIPY_MAX_GOSSIP_INTERVAL_MS` - longest interval (default 500)
+
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+
+/// Time over which the rate of learned values is averaged
+const RATE_WINDOW: Duration = Duration::from_secs(2);
+/// Weight of the last sample in the averages of round costs and round-trip times
+const SAMPLE_WEIGHT: f64 = 0.2;
+
+/// Pacing configuration
+#[derive(Debug, Clone, Copy)]
+pub struct PacingConfig {
+    /// Target number of messages between nodes per value
+    pub msgs_per_op: f64,
+    /// Shortest gossip interval
+    pub min_interval: Duration,
+    /// Longest gossip interval
+    pub max_interval: Duration,
+}
+
+impl PacingConfig {
+    /// Creates configuration with default interval bounds
+    pub fn new(msgs_per_op: f64) -> Self {
+        Self {
+            msgs_per_op,
+            min_interval: Duration::from_millis(50),
+            max_interval: Duration::from_millis(500),
+        }
+    }
+
+    /// Reads configuration from environment variables, unset ones have default values
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut config = Self::new(25.0);
+        if let Ok(budget) = std::env::var("GOSSIPY_MSGS_PER_OP") {
+            config.msgs_per_op = budget.parse().context("parsing GOSSIPY_MSGS_PER_OP")?;
+        }
+        if let Ok(min) = std::env::var("GOSSIPY_MIN_GOSSIP_INTERVAL_MS") {
+            let min = min
+                .parse()
+                .context("parsing GOSSIPY_MIN_GOSSIP_INTERVAL_MS")?;
+            config.min_interval = Duration::from_millis(min);
+        }
+        if let Ok(max) = std::env::var("GOSSIPY_MAX_GOSSIP_INTERVAL_MS") {
+            let max = max
+                .parse()
+                .context("parsing GOSSIPY_MAX_GOSSIP_INTERVAL_MS")?;
+            config.max_interval = Duration::from_millis(max);
+        }
+        anyhow::ensure!(
+            config.msgs_per_op > 0.0,
+            "GOSSIPY_MSGS_PER_OP must be positive"
+        );
+        anyhow::ensure!(
+            config.min_interval <= config.max_interval,
+            "GOSSIPY_MIN_GOSSIP_INTERVAL_MS is above GOSSIPY_MAX_GOSSIP_INTERVAL_MS"
+        );
+
+        Ok(config)
+    }
+}
+
+/// Gossip interval and fanout
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pace {
+    pub interval: Duration,
+    /// Number of peers to gossip with in a round, `None` for all of them
+    pub fanout: Option<usize>,
+}
+
+/// What the node observed until a gossip round
+#[derive(Debug, Clone, Copy)]
+pub struct Observation {
+    /// Number of values the node has
+    pub values: usize,
+    /// Number of messages sent to the peers so far, acknowledgements included
+    pub messages: usize,
+    /// Number of values the peers are not known to have
+    pub backlog: usize,
+    /// Number of peers the node gossips with
+    pub peers: usize,
+    /// Number of nodes in the cluster
+    pub nodes: usize,
+}
+
+/// Adapts gossip pace to the observed load
+pub struct Pacer {
+    config: PacingConfig,
+    pace: Pace,
+    /// Observation of the previous round and its time
+    last: Option<(Observation, Instant)>,
+    /// Values learned per second
+    rate: f64,
+    /// Messages per round at full fanout
+    cost: Option<f64>,
+    /// Round-trip time of acknowledgements in seconds
+    rtt: Option<f64>,
+}
+
+impl Pacer {
+    /// Creates pacer gossiping with all peers every `interval` until it learns more
+    pub fn new(config: PacingConfig, interval: Duration) -> Self {
+        let interval = interval.clamp(config.min_interval, config.max_interval);
+        Self {
+            config,
+            pace: Pace {
+                interval,
+                fanout: None,
+            },
+            last: None,
+            rate: 0.0,
+            cost: None,
+            rtt: None,
+        }
+    }
+
+    /// Returns the current pace
+    pub fn pace(&self) -> Pace {
+        self.pace
+    }
+
+    /// Records round-trip time of an acknowledged gossip message
+    pub fn acked(&mut self, rtt: Duration) {
+        let rtt = rtt.as_secs_f64();
+        self.rtt = Some(self.rtt.map_or(rtt, |avg| average(avg, rtt)));
+    }
+
+    /// Updates the pace from what the node observed since the previous round at `now`
+    pub fn round(&mut self, observed: Observation, now: Instant) -> Pace {
+        let Some((last, since)) = self.last.replace((observed, now)) else {
+            return self.pace;
+        };
+        let learned = observed.values.saturating_sub(last.values);
+        if observed.backlog == 0 && learned == 0 {
+            return self.pace;
+        }
+
+        let elapsed = now.saturating_duration_since(since).as_secs_f64();
+        if elapsed > 0.0 {
+            let weight = 1.0 - (-elapsed / RATE_WINDOW.as_secs_f64()).exp();
+            self.rate += weight * (learned as f64 / elapsed - self.rate);
+        }
+        // rounds with reduced fanout would cost this much with all peers
+        let messages = observed.messages.saturating_sub(last.messages) as f64;
+        let cost = match self.pace.fanout {
+            Some(fanout) if fanout > 0 => messages * observed.peers as f64 / fanout as f64,
+            _ => messages,
+        };
+        self.cost = Some(self.cost.map_or(cost, |avg| average(avg, cost)));
+
+        self.pace = self.next_pace(observed.peers, observed.nodes);
+        self.pace
+    }
+
+    /// Returns the pace that keeps the cost of gossip within the budget
+    fn next_pace(&self, peers: usize, nodes: usize) -> Pace {
+        let min = self
+            .config
+            .min_interval
+            .as_secs_f64()
+            .max(self.rtt.unwrap_or(0.0));
+        let max = self.config.max_interval.as_secs_f64().max(min);
+        // every value learned by one node is learned by all of them
+        let budget = self.config.msgs_per_op * self.rate / nodes.max(1) as f64;
+        let wanted = match self.cost {
+            Some(cost) if budget > 0.0 => cost / budget,
+            _ => return self.pace,
+        };
+
+        if wanted <= max {
+            return Pace {
+                interval: Duration::from_secs_f64(wanted.max(min)),
+                fanout: None,
+            };
+        }
+        let fanout = (peers as f64 * max / wanted).ceil() as usize;
+        Pace {
+            interval: Duration::from_secs_f64(max),
+            fanout: (fanout < peers).then_some(fanout.max(1)),
+        }
+    }
+}
+
+/// Returns exponentially weighted average with the new sample
+fn average(avg: f64, sample: f64) -> f64 {
+    avg + SAMPLE_WEIGHT * (sample - avg)
+}
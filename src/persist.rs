@@ -0,0 +1,333 @@
+//! Durable node state
+//!
+//! Optional persistence of handler state consisting of an append-only write-ahead log (WAL)
+//! and periodic snapshots. Every change of the state is first appended to the WAL and only then
+//! applied, so the state can be rebuilt on startup by loading the latest snapshot and replaying
+//! the WAL. After the snapshot is written the WAL is truncated.
+//!
+//! WAL records are numbered and the snapshot stores the number of the last record it contains,
+//! so records that are already in the snapshot are not replayed again if the node crashes
+//! after writing the snapshot but before truncating the WAL.
+//!
+//! Persistence is enabled by setting `GOSSIPY_DATA_DIR` environment variable,
+//! every node stores its files in its own subdirectory named by node ID.
+//! Other settings:
+//! - `GOSSIPY_SNAPSHOT_EVERY` - number of WAL records after which the snapshot is taken (default 1000)
+//! - `GOSSIPY_FSYNC` - set to `1` to flush every WAL record to disk (default `0`)
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::crdt::Crdt;
+
+const WAL_FILE: &str = "wal.jsonl";
+const SNAPSHOT_FILE: &str = "snapshot.json";
+const SHARDS_FILE: &str = "shards";
+
+/// State that can be persisted and recovered
+pub trait Durable {
+    /// Change of the state recorded in the WAL
+    type Record: Serialize + DeserializeOwned;
+    /// Copy of the whole state
+    type Snapshot: Serialize + DeserializeOwned;
+
+    /// Applies change to the state, both during normal operation and during recovery
+    fn apply(&mut self, record: Self::Record);
+    /// Returns copy of the whole state
+    fn snapshot(&self) -> Self::Snapshot;
+    /// Replaces the state with the snapshot during recovery
+    fn restore(&mut self, snapshot: Self::Snapshot);
+}
+
+/// Every CRDT is durable, its deltas are used as WAL records
+impl<S> Durable for S
+where
+    S: Crdt + Serialize + DeserializeOwned,
+{
+    type Record = S;
+    type Snapshot = S;
+
+    fn apply(&mut self, record: S) {
+        self.merge(&record);
+    }
+
+    fn snapshot(&self) -> S {
+        self.clone()
+    }
+
+    fn restore(&mut self, snapshot: S) {
+        *self = snapshot;
+    }
+}
+
+/// Line of the WAL
+#[derive(Serialize, Deserialize)]
+struct WalEntry<R> {
+    /// Number of the record, starting at 1
+    seq: u64,
+    record: R,
+}
+
+/// Content of the snapshot file
+#[derive(Serialize, Deserialize)]
+struct SnapshotFile<S> {
+    /// Number of the last WAL record contained in the snapshot
+    seq: u64,
+    state: S,
+}
+
+/// Storage configuration
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Directory containing the WAL and the snapshot
+    pub dir: PathBuf,
+    /// Number of WAL records after which the snapshot is taken
+    pub snapshot_every: usize,
+    /// Flush every WAL record to disk
+    pub fsync: bool,
+}
+
+impl Config {
+    /// Creates configuration with default settings
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            snapshot_every: 1000,
+            fsync: false,
+        }
+    }
+
+    /// Reads configuration from environment variables,
+    /// returns `None` if the persistence is not enabled
+    pub fn from_env(node_id: &str) -> anyhow::Result<Option<Self>> {
+        let Ok(dir) = std::env::var("GOSSIPY_DATA_DIR") else {
+            return Ok(None);
+        };
+
+        let mut config = Self::new(Path::new(&dir).join(node_id));
+        if let Ok(n) = std::env::var("GOSSIPY_SNAPSHOT_EVERY") {
+            config.snapshot_every = n.parse().context("parsing GOSSIPY_SNAPSHOT_EVERY")?;
+        }
+        if let Ok(fsync) = std::env::var("GOSSIPY_FSYNC") {
+            config.fsync = fsync == "1";
+        }
+
+        Ok(Some(config))
+    }
+}
+
+/// Write-ahead log and snapshot files of one node
+pub struct Storage {
+    config: Config,
+    wal: File,
+    /// Number of records in the WAL
+    records: usize,
+    /// Number of the last record appended to the WAL
+    seq: u64,
+}
+
+impl Storage {
+    /// Opens (or creates) the storage in the configured directory
+    pub fn open(config: Config) -> anyhow::Result<Self> {
+        fs::create_dir_all(&config.dir)
+            .with_context(|| format!("creating data directory {:?}", config.dir))?;
+
+        let wal = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(config.dir.join(WAL_FILE))
+            .context("opening WAL")?;
+
+        Ok(Self {
+            config,
+            wal,
+            records: 0,
+            seq: 0,
+        })
+    }
+
+    /// Rebuilds the state from the snapshot and the WAL, returns number of replayed WAL records.
+    /// Records already contained in the snapshot are skipped.
+    pub fn recover<D: Durable>(&mut self, state: &mut D) -> anyhow::Result<usize> {
+        let snapshot_path = self.config.dir.join(SNAPSHOT_FILE);
+        if snapshot_path.exists() {
+            let file = File::open(&snapshot_path).context("opening snapshot")?;
+            let snapshot: SnapshotFile<D::Snapshot> =
+                serde_json::from_reader(BufReader::new(file)).context("deserializing snapshot")?;
+            state.restore(snapshot.state);
+            self.seq = snapshot.seq;
+        }
+
+        let file = File::open(self.config.dir.join(WAL_FILE)).context("opening WAL")?;
+        let mut reader = BufReader::new(file);
+        let mut line = String::new();
+        let mut replayed = 0;
+        // length of the WAL containing only valid records
+        let mut valid_len = 0;
+        loop {
+            line.clear();
+            let n = reader.read_line(&mut line).context("reading WAL")?;
+            if n == 0 {
+                break;
+            }
+
+            match serde_json::from_str::<WalEntry<D::Record>>(&line) {
+                Ok(entry) => {
+                    // the node crashed before truncating the WAL after the snapshot
+                    if entry.seq > self.seq {
+                        state.apply(entry.record);
+                        self.seq = entry.seq;
+                        replayed += 1;
+                    }
+                    valid_len += n as u64;
+                }
+                Err(e) => {
+                    // the node crashed in the middle of writing the last record,
+                    // drop it, so the new records are not appended after it
+                    eprintln!("WARN: truncating corrupted WAL record: {e}");
+                    self.wal.set_len(valid_len).context("truncating WAL")?;
+                    break;
+                }
+            }
+        }
+        self.records = replayed;
+
+        Ok(replayed)
+    }
+
+    /// Appends record to the WAL
+    pub fn append<R: Serialize>(&mut self, record: &R) -> anyhow::Result<()> {
+        let entry = WalEntry {
+            seq: self.seq + 1,
+            record,
+        };
+        let mut line = serde_json::to_vec(&entry).context("serializing WAL record")?;
+        line.push(b'\n');
+        self.wal.write_all(&line).context("appending to WAL")?;
+        if self.config.fsync {
+            self.wal.sync_data().context("syncing WAL")?;
+        }
+        self.records += 1;
+        self.seq += 1;
+
+        Ok(())
+    }
+
+    /// Returns `true` if the WAL grew enough to take a snapshot
+    pub fn snapshot_due(&self) -> bool {
+        self.records >= self.config.snapshot_every
+    }
+
+    /// Atomically replaces the snapshot and truncates the WAL,
+    /// the snapshot must contain all records appended so far
+    pub fn snapshot<S: Serialize>(&mut self, snapshot: &S) -> anyhow::Result<()> {
+        let tmp_path = self.config.dir.join(format!("{SNAPSHOT_FILE}.tmp"));
+        let mut file = File::create(&tmp_path).context("creating snapshot")?;
+        let content = SnapshotFile {
+            seq: self.seq,
+            state: snapshot,
+        };
+        serde_json::to_writer(&mut file, &content).context("writing snapshot")?;
+        file.sync_all().context("syncing snapshot")?;
+        fs::rename(&tmp_path, self.config.dir.join(SNAPSHOT_FILE)).context("replacing snapshot")?;
+        // the rename must be durable before the WAL is gone
+        File::open(&self.config.dir)
+            .and_then(|dir| dir.sync_all())
+            .context("syncing data directory")?;
+
+        self.wal.set_len(0).context("truncating WAL")?;
+        self.records = 0;
+
+        Ok(())
+    }
+}
+
+/// Records the number of shards whose storages are kept in the directory, fails if the directory was used
+/// with a different number of shards, because keys would then be looked up in storages of other shards
+pub fn check_shards(dir: &Path, shards: usize) -> anyhow::Result<()> {
+    fs::create_dir_all(dir).with_context(|| format!("creating data directory {dir:?}"))?;
+    let path = dir.join(SHARDS_FILE);
+    if path.exists() {
+        let recorded: usize = fs::read_to_string(&path)
+            .context("reading number of shards")?
+            .trim()
+            .parse()
+            .context("parsing number of shards")?;
+        if recorded != shards {
+            bail!("data directory {dir:?} holds state of {recorded} shards, not {shards}");
+        }
+        return Ok(());
+    }
+
+    let mut file = File::create(&path).context("creating shards file")?;
+    writeln!(file, "{shards}").context("writing number of shards")?;
+    file.sync_all().context("syncing shards file")
+}
+
+/// State persisted in optional storage
+///
+/// All changes must go through [`Persisted::apply`], reads are possible through [`Deref`].
+pub struct Persisted<D> {
+    state: D,
+    storage: Option<Storage>,
+}
+
+impl<D: Durable> Persisted<D> {
+    /// Opens storage configured by environment variables and recovers the state from it.
+    /// If the persistence is not enabled, the state is kept only in memory.
+    pub fn open(node_id: &str, state: D) -> anyhow::Result<Self> {
+        match Config::from_env(node_id)? {
+            Some(config) => Self::with_storage(Storage::open(config)?, state),
+            None => Ok(Self::in_memory(state)),
+        }
+    }
+
+    /// Recovers the state from the storage
+    pub fn with_storage(mut storage: Storage, mut state: D) -> anyhow::Result<Self> {
+        let replayed = storage.recover(&mut state).context("recovering state")?;
+        eprintln!("INFO: state recovered, {replayed} WAL records replayed");
+
+        Ok(Self {
+            state,
+            storage: Some(storage),
+        })
+    }
+
+    /// Keeps the state only in memory
+    pub fn in_memory(state: D) -> Self {
+        Self {
+            state,
+            storage: None,
+        }
+    }
+
+    /// Logs the change to the WAL and applies it to the state
+    pub fn apply(&mut self, record: D::Record) -> anyhow::Result<()> {
+        if let Some(storage) = self.storage.as_mut() {
+            storage.append(&record)?;
+        }
+
+        self.state.apply(record);
+
+        if let Some(storage) = self.storage.as_mut().filter(|s| s.snapshot_due()) {
+            storage.snapshot(&self.state.snapshot())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<D> Deref for Persisted<D> {
+    type Target = D;
+
+    fn deref(&self) -> &D {
+        &self.state
+    }
+}
@@ -0,0 +1,349 @@
+//! Send-ahead pipelining with per-destination in-flight limits
+//!
+//! Opt-in limit of the number of requests (sent by [`Node::send_to`](crate::Node::send_to)) waiting
+//! for a reply from the same destination. Requests over the limit get their message ID immediately,
+//! but they are queued and sent in the order of sending when replies to the earlier requests arrive.
+//! A request that did not get a reply within the timeout frees its slot, so lost messages
+//! do not block the destination forever.
+//!
+//! The limit is set with [`Node::set_pipeline_config`](crate::Node::set_pipeline_config)
+//! or with environment variables:
+//! - `GOSSIPY_MAX_IN_FLIGHT` - maximum number of requests in flight per destination,
+//!   the limit is disabled if not set or `0`
+//! - `GOSSIPY_IN_FLIGHT_TIMEOUT_MS` - time after which unanswered request frees its slot (default 1000)
+
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use serde_json::value::RawValue;
+
+use crate::MsgId;
+
+/// Pipelining configuration
+#[derive(Debug, Clone, Copy)]
+pub struct PipelineConfig {
+    /// Maximum number of requests in flight per destination
+    pub max_in_flight: usize,
+    /// Time after which unanswered request frees its slot
+    pub timeout: Duration,
+}
+
+impl PipelineConfig {
+    /// Creates configuration with default timeout
+    pub fn new(max_in_flight: usize) -> Self {
+        Self {
+            max_in_flight,
+            timeout: Duration::from_secs(1),
+        }
+    }
+
+    /// Reads configuration from environment variables,
+    /// returns `None` if the limit is not enabled
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Ok(max) = std::env::var("GOSSIPY_MAX_IN_FLIGHT") else {
+            return Ok(None);
+        };
+        let max: usize = max.parse().context("parsing GOSSIPY_MAX_IN_FLIGHT")?;
+        if max == 0 {
+            return Ok(None);
+        }
+
+        let mut config = Self::new(max);
+        if let Ok(timeout) = std::env::var("GOSSIPY_IN_FLIGHT_TIMEOUT_MS") {
+            let timeout = timeout
+                .parse()
+                .context("parsing GOSSIPY_IN_FLIGHT_TIMEOUT_MS")?;
+            config.timeout = Duration::from_millis(timeout);
+        }
+
+        Ok(Some(config))
+    }
+}
+
+/// Request waiting for a free slot
+struct Waiting {
+    msg_id: MsgId,
+    msg: Box<RawValue>,
+    queued_at: Instant,
+}
+
+#[derive(Default)]
+struct Destination {
+    /// msg_id => sent at
+    in_flight: HashMap<MsgId, Instant>,
+    waiting: VecDeque<Waiting>,
+}
+
+/// Request released from the queue, it can be sent now
+pub(crate) struct Released {
+    pub(crate) dst: String,
+    pub(crate) msg: Box<RawValue>,
+    /// How long the request waited in the queue
+    pub(crate) delay: Duration,
+}
+
+/// Requests in flight and requests waiting for a free slot for every destination
+pub(crate) struct Pipeline {
+    config: PipelineConfig,
+    destinations: HashMap<String, Destination>,
+}
+
+impl Pipeline {
+    pub(crate) fn new(config: PipelineConfig) -> Self {
+        Self {
+            config,
+            destinations: HashMap::new(),
+        }
+    }
+
+    /// Returns how often timed out requests should be looked for
+    pub(crate) fn expiry_interval(&self) -> Duration {
+        (self.config.timeout / 4).max(Duration::from_millis(1))
+    }
+
+    /// Returns the request back if it can be sent now, otherwise queues it
+    pub(crate) fn admit(
+        &mut self,
+        dst: &str,
+        msg_id: MsgId,
+        msg: Box<RawValue>,
+    ) -> Option<Box<RawValue>> {
+        let destination = self.destinations.entry(dst.to_string()).or_default();
+        if destination.waiting.is_empty() && destination.in_flight.len() < self.config.max_in_flight
+        {
+            destination.in_flight.insert(msg_id, Instant::now());
+            return Some(msg);
+        }
+
+        destination.waiting.push_back(Waiting {
+            msg_id,
+            msg,
+            queued_at: Instant::now(),
+        });
+        None
+    }
+
+    /// Frees the slot of the answered request, returns requests that can be sent now
+    pub(crate) fn complete(&mut self, dst: &str, msg_id: MsgId) -> Vec<Released> {
+        let Some(destination) = self.destinations.get_mut(dst) else {
+            return Vec::new();
+        };
+        destination.in_flight.remove(&msg_id);
+
+        let mut released = Vec::new();
+        Self::release(self.config, dst, destination, &mut released);
+        released
+    }
+
+    /// Frees the slots of timed out requests, returns requests that can be sent now
+    pub(crate) fn expire(&mut self) -> Vec<Released> {
+        let mut released = Vec::new();
+        for (dst, destination) in self.destinations.iter_mut() {
+            if destination.waiting.is_empty() {
+                continue;
+            }
+            destination
+                .in_flight
+                .retain(|_, sent_at| sent_at.elapsed() < self.config.timeout);
+            Self::release(self.config, dst, destination, &mut released);
+        }
+        released
+    }
+
+    /// Moves waiting requests to free slots
+    fn release(
+        config: PipelineConfig,
+        dst: &str,
+        destination: &mut Destination,
+        released: &mut Vec<Released>,
+    ) {
+        while destination.in_flight.len() < config.max_in_flight {
+            let Some(waiting) = destination.waiting.pop_front() else {
+                break;
+            };
+            let now = Instant::now();
+            destination.in_flight.insert(waiting.msg_id, now);
+            released.push(Released {
+                dst: dst.to_string(),
+                msg: waiting.msg,
+                delay: now - waiting.queued_at,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufReader, Write},
+        thread,
+    };
+
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::{Handler, Message, Node};
+
+    fn pipeline(max_in_flight: usize, timeout: Duration) -> Pipeline {
+        let mut config = PipelineConfig::new(max_in_flight);
+        config.timeout = timeout;
+        Pipeline::new(config)
+    }
+
+    fn msg(n: usize) -> Box<RawValue> {
+        RawValue::from_string(n.to_string()).expect("raw value")
+    }
+
+    fn released(released: Vec<Released>) -> Vec<(String, String)> {
+        released
+            .into_iter()
+            .map(|r| (r.dst, r.msg.get().to_string()))
+            .collect()
+    }
+
+    fn sent(dst: &str, msgs: &[usize]) -> Vec<(String, String)> {
+        msgs.iter()
+            .map(|n| (dst.to_string(), n.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn requests_over_the_limit_are_queued() {
+        let mut pipeline = pipeline(2, Duration::from_secs(60));
+        assert!(pipeline.admit("n1", MsgId(1), msg(1)).is_some());
+        assert!(pipeline.admit("n1", MsgId(2), msg(2)).is_some());
+        assert!(pipeline.admit("n1", MsgId(3), msg(3)).is_none());
+        // destinations have their own limits
+        assert!(pipeline.admit("n2", MsgId(4), msg(4)).is_some());
+    }
+
+    #[test]
+    fn replies_release_queued_requests_in_order() {
+        let mut pipeline = pipeline(1, Duration::from_secs(60));
+        assert!(pipeline.admit("n1", MsgId(1), msg(1)).is_some());
+        for n in 2..=4 {
+            assert!(pipeline.admit("n1", MsgId(n), msg(n)).is_none());
+        }
+
+        // unknown destinations and requests do not free any slot
+        assert!(pipeline.complete("n2", MsgId(1)).is_empty());
+        assert!(pipeline.complete("n1", MsgId(7)).is_empty());
+
+        thread::sleep(Duration::from_millis(5));
+        let first = pipeline.complete("n1", MsgId(1));
+        assert!(first[0].delay >= Duration::from_millis(5));
+        assert_eq!(released(first), sent("n1", &[2]));
+        assert_eq!(
+            released(pipeline.complete("n1", MsgId(2))),
+            sent("n1", &[3])
+        );
+        assert_eq!(
+            released(pipeline.complete("n1", MsgId(3))),
+            sent("n1", &[4])
+        );
+        assert!(pipeline.complete("n1", MsgId(4)).is_empty());
+
+        // the queue is empty, so requests are sent right away again
+        assert!(pipeline.admit("n1", MsgId(5), msg(5)).is_some());
+    }
+
+    #[test]
+    fn timed_out_requests_free_their_slots() {
+        let mut pipeline = pipeline(1, Duration::from_millis(20));
+        assert!(pipeline.admit("n1", MsgId(1), msg(1)).is_some());
+        assert!(pipeline.admit("n1", MsgId(2), msg(2)).is_none());
+        assert!(pipeline.expire().is_empty());
+
+        thread::sleep(Duration::from_millis(30));
+        assert_eq!(released(pipeline.expire()), sent("n1", &[2]));
+        // the released request has a new timeout
+        assert!(pipeline.expire().is_empty());
+        // late reply to the expired request frees nothing
+        assert!(pipeline.admit("n1", MsgId(3), msg(3)).is_none());
+        assert!(pipeline.complete("n1", MsgId(1)).is_empty());
+        assert_eq!(
+            released(pipeline.complete("n1", MsgId(2))),
+            sent("n1", &[3])
+        );
+    }
+
+    #[test]
+    fn one_way_messages_bypass_the_limit() {
+        let ids = ["n0".to_string(), "n1".to_string()];
+        let mut node: Node = Node::with_ids("n0", &ids).expect("creating node");
+        node.capture_output();
+        node.set_pipeline_config(Some(PipelineConfig::new(1)));
+
+        node.send_to("n1", Payload::Request { n: 0 })
+            .expect("sending request");
+        for n in 1..=2 {
+            node.notify("n1", Payload::Request { n })
+                .expect("sending one-way message");
+        }
+        // the queued request is waiting for the first one, the one-way messages are not
+        node.send_to("n1", Payload::Request { n: 3 })
+            .expect("sending request");
+
+        assert_eq!(node.take_output().len(), 3);
+        assert_eq!(node.in_flight_rpcs().len(), 2);
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(rename_all = "snake_case", tag = "type")]
+    enum Payload {
+        Start,
+        Request { n: usize },
+    }
+
+    /// Sends two requests to `n1` when started
+    struct Requester;
+
+    impl Handler<Payload> for Requester {
+        fn handle(&mut self, _msg: Message<Payload>, mut node: Node) -> anyhow::Result<()> {
+            for n in 0..2 {
+                node.send_to("n1", Payload::Request { n })?;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn requests_are_released_without_further_input() {
+        let ids = ["n0".to_string(), "n1".to_string()];
+        let mut node: Node = Node::with_ids("n0", &ids).expect("creating node");
+        node.capture_output();
+        node.set_pipeline_config(Some(PipelineConfig {
+            max_in_flight: 1,
+            timeout: Duration::from_millis(20),
+        }));
+        let output = node.clone();
+
+        let (reader, mut writer) = std::io::pipe().expect("creating pipe");
+        let jh = thread::spawn(move || {
+            node.run_shards(BufReader::new(reader), vec![Requester], |_| {
+                crate::shard::ShardKey::Barrier
+            })
+        });
+        writeln!(
+            writer,
+            r#"{{"src": "c1", "dest": "n0", "body": {{"type": "start", "msg_id": 1}}}}"#
+        )
+        .expect("writing input");
+
+        // the first request is never answered, the input stays open
+        let mut requests = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while requests.len() < 2 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+            requests.extend(output.take_output());
+        }
+        assert_eq!(requests.len(), 2, "{requests:?}");
+        assert!(requests[1].contains(r#""n":1"#), "{requests:?}");
+
+        drop(writer);
+        jh.join().expect("joining node").expect("running node");
+    }
+}
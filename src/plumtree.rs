@@ -0,0 +1,418 @@
+//! Epidemic broadcast trees (Plumtree)
+//!
+//! Splits neighbours of the node into eager and lazy peers on top of the [`Gossip`] engine, whose peers are
+//! the eager ones. New state is pushed to the eager peers only, so it travels along a spanning tree, lazy peers
+//! get just announcements (`IHave`) of what they are not known to have in every gossip round.
+//!
+//! Unlike in the paper, the tree is shared by all broadcasting nodes. Building it by pruning duplicates
+//! of concurrent broadcasts would cut it at several places at once, so every node computes the same tree
+//! of minimal depth from the topology instead, and its links are never pruned. When a link of the tree fails
+//! (eg. in a partition), unacknowledged pushes are repaired by the gossip engine once it recovers,
+//! and the tree routes around it in the meantime:
+//! - announced state that does not arrive through the tree within a few rounds is requested from
+//!   the announcer by a graft, which also makes the link eager
+//! - a grafted peer sending only what we already have closes a cycle, we prune it and tell it to prune us
+//!
+//! When a neighbour in the tree is suspected of failure (see [`Gossip::set_suspected`]), [`Plumtree::route_around`]
+//! grafts all lazy neighbours that are not suspected right away, and they are not pruned until it is trusted again.
+//!
+//! Handler using the tree is expected to:
+//! - call [`Plumtree::set_topology`] when the topology arrives
+//! - [`Gossip::push`] new state, [`Plumtree::prune`] the sender of gossip with nothing new
+//! - acknowledge announcements and pass them to [`Plumtree::announced`]
+//! - call [`Plumtree::graft`] or [`Plumtree::prune`] when graft or prune arrives, both are one-way
+//!   messages (see [`Node::notify`]) the handler does not reply to
+//! - call [`Plumtree::round`] in every gossip round
+//! - call [`Plumtree::route_around`] when suspected peers change
+//!
+//! See [Epidemic Broadcast Trees](https://asc.di.fct.unl.pt/~jleitao/pdf/srds07-leitao.pdf).
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use anyhow::Context;
+use serde::Serialize;
+
+use crate::{crdt::Crdt, gossip::Gossip, Node};
+
+/// Number of gossip rounds after which announced state that did not arrive is grafted
+const GRAFT_ROUNDS: usize = 2;
+
+/// Spanning tree of eager peers
+pub struct Plumtree<S> {
+    /// Our neighbours in the initial tree
+    tree: BTreeSet<String>,
+    /// Neighbours that get only announcements
+    lazy: BTreeSet<String>,
+    /// peer => announced state we have not received yet, round of the announcement
+    missing: HashMap<String, (S, usize)>,
+    /// Gossip round counter
+    round: usize,
+}
+
+impl<S: Crdt> Default for Plumtree<S> {
+    fn default() -> Self {
+        Self {
+            tree: BTreeSet::new(),
+            lazy: BTreeSet::new(),
+            missing: HashMap::new(),
+            round: 0,
+        }
+    }
+}
+
+impl<S: Crdt> Plumtree<S> {
+    /// Creates empty tree
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets neighbours of the node from the topology of the cluster, neighbours in the spanning tree
+    /// are eager peers, the others lazy ones
+    pub fn set_topology(
+        &mut self,
+        gossip: &mut Gossip<S>,
+        node_id: &str,
+        topology: &HashMap<String, Vec<String>>,
+    ) {
+        let tree = spanning_tree(topology);
+        let tree_neighbours = tree.get(node_id);
+        let (eager, lazy): (BTreeSet<String>, _) = topology
+            .get(node_id)
+            .into_iter()
+            .flatten()
+            .cloned()
+            .partition(|peer| tree_neighbours.is_some_and(|t| t.contains(peer.as_str())));
+
+        self.tree = eager.clone();
+        self.lazy = lazy;
+        self.missing.clear();
+        gossip.set_peers(eager.into_iter().collect());
+    }
+
+    /// Returns neighbours that get only announcements
+    pub fn lazy(&self) -> &BTreeSet<String> {
+        &self.lazy
+    }
+
+    /// Moves grafted peer to the lazy ones, returns `false` if it is not a grafted peer
+    /// or we route around a suspected peer
+    pub fn prune(&mut self, gossip: &mut Gossip<S>, peer: &str) -> bool {
+        if self.tree.contains(peer) || self.routing_around(gossip) {
+            return false;
+        }
+        let mut peers = gossip.peers().to_vec();
+        let Some(i) = peers.iter().position(|p| p == peer) else {
+            return false;
+        };
+        peers.remove(i);
+        gossip.set_peers(peers);
+        self.lazy.insert(peer.to_string());
+        true
+    }
+
+    /// Moves lazy peer to the eager ones, returns `false` if the peer is not our neighbour
+    pub fn graft(&mut self, gossip: &mut Gossip<S>, peer: &str) -> bool {
+        if !self.lazy.remove(peer) {
+            return gossip.peers().iter().any(|p| p == peer);
+        }
+        let mut peers = gossip.peers().to_vec();
+        peers.push(peer.to_string());
+        gossip.set_peers(peers);
+        true
+    }
+
+    /// Remembers the part of the state announced by the neighbour that we do not have yet
+    pub fn announced(&mut self, gossip: &Gossip<S>, peer: &str, announced: &S) {
+        if !self.lazy.contains(peer) && !gossip.peers().iter().any(|p| p == peer) {
+            return;
+        }
+        let Some(missing) = announced.delta(gossip.state()) else {
+            return;
+        };
+        let round = self.round;
+        self.missing
+            .entry(peer.to_string())
+            .and_modify(|(m, _)| m.merge(&missing))
+            .or_insert((missing, round));
+    }
+
+    /// Announces state to the lazy peers and grafts the peers whose announced state did not arrive
+    /// since the previous round. `to_ihave` and `to_graft` wrap the state into message payloads.
+    pub fn round<P, C>(
+        &mut self,
+        node: &mut Node<C>,
+        gossip: &mut Gossip<S>,
+        to_ihave: impl Fn(S) -> P,
+        to_graft: impl Fn(S) -> P,
+    ) -> anyhow::Result<()>
+    where
+        P: Serialize,
+        C: Clone,
+    {
+        self.round += 1;
+        let round = self.round;
+
+        for peer in &self.lazy {
+            if gossip.is_suspected(peer) {
+                continue;
+            }
+            if let Some(delta) = gossip.delta_for(peer) {
+                // acknowledged like gossip, so the announcement is repeated until the peer gets it
+                gossip.send(node, peer, delta, &to_ihave)?;
+            }
+        }
+
+        let mut grafts = Vec::new();
+        self.missing.retain(|peer, (missing, since)| {
+            let Some(still) = missing.delta(gossip.state()) else {
+                return false;
+            };
+            if *since + GRAFT_ROUNDS < round {
+                grafts.push((peer.clone(), still.clone()));
+                *since = round;
+            }
+            *missing = still;
+            true
+        });
+
+        for (peer, missing) in grafts {
+            self.graft(gossip, &peer);
+            node.notify(&peer, to_graft(missing))
+                .with_context(|| format!("sending graft to {peer}"))?;
+        }
+
+        Ok(())
+    }
+
+    /// Grafts lazy peers that are not suspected while a neighbour in the tree is suspected,
+    /// so the state flows through the other links
+    pub fn route_around<P, C>(
+        &mut self,
+        node: &mut Node<C>,
+        gossip: &mut Gossip<S>,
+        to_graft: impl Fn(S) -> P,
+    ) -> anyhow::Result<()>
+    where
+        P: Serialize,
+        C: Clone,
+    {
+        if !self.routing_around(gossip) {
+            return Ok(());
+        }
+        let peers: Vec<String> = self
+            .lazy
+            .iter()
+            .filter(|peer| !gossip.is_suspected(peer))
+            .cloned()
+            .collect();
+        for peer in peers {
+            self.graft(gossip, &peer);
+            // nothing is missing, the peer just makes the link eager too
+            node.notify(&peer, to_graft(S::default()))
+                .with_context(|| format!("sending graft to {peer}"))?;
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if a neighbour in the tree is suspected
+    fn routing_around(&self, gossip: &Gossip<S>) -> bool {
+        self.tree.iter().any(|peer| gossip.is_suspected(peer))
+    }
+}
+
+/// Returns node => its neighbours in spanning trees of minimal depth of all connected components
+/// of the topology, links are considered bidirectional
+fn spanning_tree(topology: &HashMap<String, Vec<String>>) -> HashMap<&str, BTreeSet<&str>> {
+    // sorted, so every node computes the same tree
+    let mut links: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+    for (node, neighbours) in topology {
+        for neighbour in neighbours.iter().filter(|n| *n != node) {
+            links.entry(node).or_default().insert(neighbour);
+            links.entry(neighbour).or_default().insert(node);
+        }
+    }
+
+    let mut tree: HashMap<&str, BTreeSet<&str>> = HashMap::new();
+    let mut visited = BTreeSet::new();
+    for &start in links.keys() {
+        if visited.contains(start) {
+            continue;
+        }
+        let component: Vec<&str> = bfs(&links, start).into_iter().map(|(n, ..)| n).collect();
+        visited.extend(component.iter().copied());
+
+        // root in the center of the component gives the shallowest tree
+        let root = component
+            .iter()
+            .copied()
+            .min_by_key(|node| {
+                (
+                    bfs(&links, node).last().map_or(0, |(.., depth)| *depth),
+                    *node,
+                )
+            })
+            .expect("component contains the start");
+        for (node, parent, _) in bfs(&links, root) {
+            if let Some(parent) = parent {
+                tree.entry(node).or_default().insert(parent);
+                tree.entry(parent).or_default().insert(node);
+            }
+        }
+    }
+    tree
+}
+
+/// Visits nodes reachable from the root breadth-first, returns them with their parent and depth in visiting order
+fn bfs<'a>(
+    links: &BTreeMap<&'a str, BTreeSet<&'a str>>,
+    root: &'a str,
+) -> Vec<(&'a str, Option<&'a str>, usize)> {
+    let mut visited = vec![(root, None, 0)];
+    let mut seen = BTreeSet::from([root]);
+    let mut i = 0;
+    while let Some(&(node, _, depth)) = visited.get(i) {
+        i += 1;
+        for &next in links.get(node).into_iter().flatten() {
+            if seen.insert(next) {
+                visited.push((next, Some(node), depth + 1));
+            }
+        }
+    }
+    visited
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{crdt::RangeSet, gossip::PeerSelection};
+
+    /// Returns topology of `n` x `n` grid, nodes are numbered by rows
+    fn grid(n: usize) -> HashMap<String, Vec<String>> {
+        (0..n * n)
+            .map(|i| {
+                let (row, col) = (i / n, i % n);
+                let mut neighbours = Vec::new();
+                if row > 0 {
+                    neighbours.push(i - n);
+                }
+                if row + 1 < n {
+                    neighbours.push(i + n);
+                }
+                if col > 0 {
+                    neighbours.push(i - 1);
+                }
+                if col + 1 < n {
+                    neighbours.push(i + 1);
+                }
+                let neighbours = neighbours.into_iter().map(|j| format!("n{j}")).collect();
+                (format!("n{i}"), neighbours)
+            })
+            .collect()
+    }
+
+    /// Returns tree and gossip of the node, the neighbours are split to eager and lazy ones
+    fn node(
+        id: &str,
+        topology: &HashMap<String, Vec<String>>,
+    ) -> (Plumtree<RangeSet<i64>>, Gossip<RangeSet<i64>>) {
+        let mut tree = Plumtree::new();
+        let mut gossip = Gossip::new(PeerSelection::All);
+        tree.set_topology(&mut gossip, id, topology);
+        (tree, gossip)
+    }
+
+    fn eager(gossip: &Gossip<RangeSet<i64>>) -> BTreeSet<&str> {
+        gossip.peers().iter().map(String::as_str).collect()
+    }
+
+    #[test]
+    fn spanning_tree_is_shallow_and_connects_all_nodes() {
+        let topology = grid(3);
+        let tree = spanning_tree(&topology);
+
+        // links are symmetric and there are n - 1 of them
+        let links: usize = tree.values().map(BTreeSet::len).sum();
+        assert_eq!(links, 2 * 8);
+        for (node, neighbours) in &tree {
+            for neighbour in neighbours {
+                assert!(tree[neighbour].contains(node), "{node} - {neighbour}");
+                assert!(topology[*node].iter().any(|n| n == neighbour));
+            }
+        }
+        // rooted in the center, every node is at most two links away
+        let links: BTreeMap<&str, BTreeSet<&str>> = tree.clone().into_iter().collect();
+        let visited = bfs(&links, "n4");
+        assert_eq!(visited.len(), 9);
+        assert!(visited.iter().all(|(.., depth)| *depth <= 2));
+
+        // every node computes the same tree, whatever the order of its topology map
+        let reordered: HashMap<String, Vec<String>> = topology.clone().into_iter().collect();
+        assert_eq!(spanning_tree(&reordered), tree);
+    }
+
+    #[test]
+    fn spanning_tree_spans_every_component() {
+        let mut topology = grid(2);
+        topology.insert("n8".to_string(), vec!["n9".to_string()]);
+        topology.insert("n9".to_string(), Vec::new());
+        let tree = spanning_tree(&topology);
+
+        assert_eq!(tree["n8"], BTreeSet::from(["n9"]));
+        assert_eq!(tree["n9"], BTreeSet::from(["n8"]));
+        let links: usize = tree.values().map(BTreeSet::len).sum();
+        assert_eq!(links, 2 * (3 + 1));
+    }
+
+    #[test]
+    fn neighbours_outside_the_tree_are_lazy() {
+        let topology = grid(3);
+        let (tree, gossip) = node("n4", &topology);
+        // the center is the root, all its neighbours are in the tree
+        assert_eq!(eager(&gossip), BTreeSet::from(["n1", "n3", "n5", "n7"]));
+        assert!(tree.lazy().is_empty());
+
+        let (tree, gossip) = node("n0", &topology);
+        assert_eq!(eager(&gossip).len() + tree.lazy().len(), 2);
+        assert_eq!(eager(&gossip).len(), 1);
+    }
+
+    #[test]
+    fn graft_makes_lazy_peer_eager() {
+        let topology = grid(3);
+        let (mut tree, mut gossip) = node("n0", &topology);
+        let lazy = tree.lazy().first().cloned().expect("lazy neighbour");
+
+        assert!(tree.graft(&mut gossip, &lazy));
+        assert!(tree.lazy().is_empty());
+        assert!(eager(&gossip).contains(lazy.as_str()));
+        // eager peers stay eager, other nodes are not our neighbours
+        assert!(tree.graft(&mut gossip, &lazy));
+        assert_eq!(eager(&gossip).len(), 2);
+        assert!(!tree.graft(&mut gossip, "n8"));
+        assert_eq!(eager(&gossip).len(), 2);
+    }
+
+    #[test]
+    fn only_grafted_peers_are_pruned() {
+        let topology = grid(3);
+        let (mut tree, mut gossip) = node("n0", &topology);
+        let in_tree = gossip.peers()[0].clone();
+        let lazy = tree.lazy().first().cloned().expect("lazy neighbour");
+
+        // links of the tree are never pruned, lazy peers cannot be pruned again
+        assert!(!tree.prune(&mut gossip, &in_tree));
+        assert!(!tree.prune(&mut gossip, &lazy));
+
+        tree.graft(&mut gossip, &lazy);
+        assert!(tree.prune(&mut gossip, &lazy));
+        assert_eq!(eager(&gossip), BTreeSet::from([in_tree.as_str()]));
+        assert!(tree.lazy().contains(&lazy));
+
+        // while we route around a suspected neighbour in the tree, grafted peers stay
+        tree.graft(&mut gossip, &lazy);
+        gossip.set_suspected(&in_tree, true);
+        assert!(!tree.prune(&mut gossip, &lazy));
+        gossip.set_suspected(&in_tree, false);
+        assert!(tree.prune(&mut gossip, &lazy));
+    }
+}
@@ -0,0 +1,544 @@
+//! Bounded event queue
+//!
+//! Queue between the thread reading STDIN (and the command thread) and the thread running the handler.
+//! When the handler cannot keep up with incoming messages, the queue fills up
+//! and the configured [`OverloadPolicy`] decides what happens with new messages.
+//!
+//! Events are divided into [`Priority`] classes. By default the classes are served by weighted
+//! round-robin: when all classes are backlogged, every round handles up to `weight` events of each class,
+//! so commands (eg. periodic gossip) are not delayed behind a long backlog of client requests,
+//! while client requests still get their share and never starve.
+//! Events of the same class are always handled in the order of arrival. With
+//! [`OverloadPolicy::PrioritizeReplies`] replies to our requests are handled before all other events.
+//!
+//! Capacity, policy and scheduling can be set with [`Node::set_queue_config`](crate::Node::set_queue_config)
+//! or with environment variables:
+//! - `GOSSIPY_QUEUE_CAPACITY` - maximum number of queued events, at least 1 (default 10000)
+//! - `GOSSIPY_QUEUE_POLICY` - `block`, `shed-clients` or `prioritize-replies` (default `block`)
+//! - `GOSSIPY_QUEUE_SCHEDULING` - `fifo` or weights of the priority classes
+//!   in the order commands, replies, peers, clients (default `8,4,2,1`)
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Condvar, Mutex},
+};
+
+use anyhow::{bail, Context};
+
+use crate::metrics::Metrics;
+
+/// What to do with a new message when the queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverloadPolicy {
+    /// Block the reader until there is a free space in the queue
+    Block,
+    /// Reject new client requests with `temporarily-unavailable` error, block on other messages
+    ShedClients,
+    /// Admit replies to our requests even over capacity and handle them before other messages
+    /// regardless of the scheduling, block on other messages
+    PrioritizeReplies,
+}
+
+/// Priority class of queued events, from the highest priority
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// Commands (eg. timers) and admin messages
+    Command = 0,
+    /// Replies to requests sent by us
+    Reply = 1,
+    /// Messages from other nodes
+    Peer = 2,
+    /// Requests from Maelstrom clients
+    Client = 3,
+}
+
+/// Number of priority classes
+pub const PRIORITIES: usize = 4;
+
+/// All priority classes indexed by their value
+const PRIORITY_CLASSES: [Priority; PRIORITIES] = [
+    Priority::Command,
+    Priority::Reply,
+    Priority::Peer,
+    Priority::Client,
+];
+
+/// Order in which events of different priority classes are handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheduling {
+    /// Events are handled in the order of arrival regardless of the class
+    Fifo,
+    /// Weighted round-robin, weights are indexed by [`Priority`]
+    Weighted([usize; PRIORITIES]),
+}
+
+/// Event queue configuration
+#[derive(Debug, Clone, Copy)]
+pub struct QueueConfig {
+    /// Maximum number of queued events, at least 1
+    pub capacity: usize,
+    pub policy: OverloadPolicy,
+    pub scheduling: Scheduling,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 10_000,
+            policy: OverloadPolicy::Block,
+            scheduling: Scheduling::Weighted([8, 4, 2, 1]),
+        }
+    }
+}
+
+impl QueueConfig {
+    /// Reads configuration from environment variables, missing values are set to defaults
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut config = Self::default();
+        if let Ok(capacity) = std::env::var("GOSSIPY_QUEUE_CAPACITY") {
+            config.capacity = capacity.parse().context("parsing GOSSIPY_QUEUE_CAPACITY")?;
+        }
+        if let Ok(policy) = std::env::var("GOSSIPY_QUEUE_POLICY") {
+            config.policy = match policy.as_str() {
+                "block" => OverloadPolicy::Block,
+                "shed-clients" => OverloadPolicy::ShedClients,
+                "prioritize-replies" => OverloadPolicy::PrioritizeReplies,
+                p => bail!("unknown GOSSIPY_QUEUE_POLICY '{p}'"),
+            };
+        }
+        if let Ok(scheduling) = std::env::var("GOSSIPY_QUEUE_SCHEDULING") {
+            config.scheduling = match scheduling.as_str() {
+                "fifo" => Scheduling::Fifo,
+                weights => {
+                    let weights = weights
+                        .split(',')
+                        .map(|w| w.trim().parse::<usize>())
+                        .collect::<Result<Vec<_>, _>>()
+                        .context("parsing GOSSIPY_QUEUE_SCHEDULING weights")?;
+                    match <[usize; PRIORITIES]>::try_from(weights) {
+                        Ok(weights) if weights.iter().all(|&w| w > 0) => {
+                            Scheduling::Weighted(weights)
+                        }
+                        _ => bail!(
+                            "GOSSIPY_QUEUE_SCHEDULING must contain {PRIORITIES} positive weights"
+                        ),
+                    }
+                }
+            };
+        }
+        anyhow::ensure!(
+            config.capacity >= 1,
+            "GOSSIPY_QUEUE_CAPACITY must be at least 1"
+        );
+
+        Ok(config)
+    }
+
+    /// Checks that the queue is able to hold an event, a reader pushing to an empty queue
+    /// of capacity 0 would wait forever
+    pub fn validate(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(self.capacity >= 1, "queue capacity must be at least 1");
+        Ok(())
+    }
+}
+
+/// Class of the queued event, decides how the event is treated when the queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum EventClass {
+    /// Request sent by Maelstrom client
+    ClientRequest,
+    /// Reply to a request sent by us
+    Reply,
+    /// Message from other node
+    Peer,
+    /// Command sent by the node itself
+    Command,
+    /// Always admitted, regardless of the policy (eg. admin messages)
+    Control,
+}
+
+impl EventClass {
+    fn priority(self) -> Priority {
+        match self {
+            EventClass::Control | EventClass::Command => Priority::Command,
+            EventClass::Reply => Priority::Reply,
+            EventClass::Peer => Priority::Peer,
+            EventClass::ClientRequest => Priority::Client,
+        }
+    }
+}
+
+/// Result of pushing an event to the queue
+pub(crate) enum Push<T> {
+    Queued,
+    /// The event was rejected due to overload and is returned back
+    Shed(T),
+    /// The queue was closed
+    Closed(T),
+}
+
+struct State<T> {
+    /// (sequence number, event) for every priority class
+    classes: [VecDeque<(u64, T)>; PRIORITIES],
+    /// Number of events every class may still take in the current round-robin round
+    credits: [usize; PRIORITIES],
+    /// Sequence number of the next pushed event
+    seq: u64,
+    closed: bool,
+}
+
+impl<T> State<T> {
+    fn len(&self) -> usize {
+        self.classes.iter().map(VecDeque::len).sum()
+    }
+
+    /// Returns class of the next event to be handled
+    fn next_class(&mut self, config: &QueueConfig) -> Option<usize> {
+        let reply = Priority::Reply as usize;
+        if config.policy == OverloadPolicy::PrioritizeReplies && !self.classes[reply].is_empty() {
+            return Some(reply);
+        }
+        match config.scheduling {
+            Scheduling::Fifo => (0..PRIORITIES)
+                .filter_map(|c| self.classes[c].front().map(|(seq, _)| (*seq, c)))
+                .min()
+                .map(|(_, c)| c),
+            Scheduling::Weighted(weights) => {
+                let backlogged = |s: &Self, c: usize| !s.classes[c].is_empty();
+                if !(0..PRIORITIES).any(|c| backlogged(self, c)) {
+                    return None;
+                }
+                if !(0..PRIORITIES).any(|c| backlogged(self, c) && self.credits[c] > 0) {
+                    // every backlogged class used up its share => start new round
+                    self.credits = weights;
+                }
+
+                let class = (0..PRIORITIES)
+                    .find(|&c| backlogged(self, c) && self.credits[c] > 0)
+                    .expect("backlogged class has credits");
+                self.credits[class] -= 1;
+                Some(class)
+            }
+        }
+    }
+}
+
+/// Bounded multi-producer single-consumer queue
+pub(crate) struct EventQueue<T> {
+    state: Mutex<State<T>>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    config: QueueConfig,
+    metrics: Arc<Metrics>,
+}
+
+impl<T> EventQueue<T> {
+    pub(crate) fn new(config: QueueConfig, metrics: Arc<Metrics>) -> Self {
+        Self {
+            state: Mutex::new(State {
+                classes: Default::default(),
+                credits: [0; PRIORITIES],
+                seq: 0,
+                closed: false,
+            }),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            config,
+            metrics,
+        }
+    }
+
+    /// Pushes event to the queue, applying the overload policy if the queue is full
+    pub(crate) fn push(&self, event: T, class: EventClass) -> Push<T> {
+        let mut state = self.state.lock().expect("lock");
+        if state.closed {
+            return Push::Closed(event);
+        }
+
+        let policy = self.config.policy;
+        let over_capacity = match class {
+            EventClass::Control => true,
+            EventClass::Reply => policy == OverloadPolicy::PrioritizeReplies,
+            EventClass::ClientRequest | EventClass::Peer | EventClass::Command => false,
+        };
+
+        if !over_capacity && state.len() >= self.config.capacity {
+            if class == EventClass::ClientRequest && policy == OverloadPolicy::ShedClients {
+                self.metrics.event_shed();
+                return Push::Shed(event);
+            }
+
+            self.metrics.reader_blocked();
+            while state.len() >= self.config.capacity && !state.closed {
+                state = self.not_full.wait(state).expect("lock");
+            }
+            if state.closed {
+                return Push::Closed(event);
+            }
+        }
+
+        let seq = state.seq;
+        state.seq += 1;
+        let priority = class.priority();
+        state.classes[priority as usize].push_back((seq, event));
+        let priority_depth = state.classes[priority as usize].len();
+        self.metrics
+            .queue_depth(state.len(), priority, priority_depth);
+        self.not_empty.notify_one();
+
+        Push::Queued
+    }
+
+    /// Removes the next event from the queue, blocks while the queue is empty.
+    /// Returns `None` when the queue is closed and all events were consumed.
+    pub(crate) fn pop(&self) -> Option<T> {
+        let mut state = self.state.lock().expect("lock");
+        loop {
+            if let Some(class) = state.next_class(&self.config) {
+                let (_, event) = state.classes[class].pop_front().expect("class has events");
+                let priority_depth = state.classes[class].len();
+                self.metrics
+                    .queue_depth(state.len(), PRIORITY_CLASSES[class], priority_depth);
+                self.not_full.notify_one();
+                return Some(event);
+            }
+            if state.closed {
+                return None;
+            }
+
+            state = self.not_empty.wait(state).expect("lock");
+        }
+    }
+
+    /// Returns `true` if the queue was closed
+    pub(crate) fn is_closed(&self) -> bool {
+        self.state.lock().expect("lock").closed
+    }
+
+    /// Closes the queue, remaining events can still be consumed
+    pub(crate) fn close(&self) {
+        self.state.lock().expect("lock").closed = true;
+        self.not_empty.notify_all();
+        self.not_full.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::mpsc, thread, time::Duration};
+
+    use super::*;
+
+    fn queue(capacity: usize, policy: OverloadPolicy) -> Arc<EventQueue<u32>> {
+        let config = QueueConfig {
+            capacity,
+            policy,
+            ..QueueConfig::default()
+        };
+        Arc::new(EventQueue::new(config, Arc::new(Metrics::default())))
+    }
+
+    /// Pushes the event from another thread, returns receiver of the push result
+    fn push_in_background(
+        queue: &Arc<EventQueue<u32>>,
+        event: u32,
+        class: EventClass,
+    ) -> mpsc::Receiver<bool> {
+        let (tx, rx) = mpsc::channel();
+        let queue = queue.clone();
+        thread::spawn(move || {
+            let queued = matches!(queue.push(event, class), Push::Queued);
+            tx.send(queued).expect("sending push result");
+        });
+        rx
+    }
+
+    /// Returns `true` if the push waits for a free space in the queue
+    fn is_blocked(rx: &mpsc::Receiver<bool>) -> bool {
+        rx.recv_timeout(Duration::from_millis(50)).is_err()
+    }
+
+    #[test]
+    fn full_queue_blocks_until_event_is_consumed() {
+        let queue = queue(1, OverloadPolicy::Block);
+        assert!(matches!(queue.push(1, EventClass::Peer), Push::Queued));
+
+        let client = push_in_background(&queue, 2, EventClass::ClientRequest);
+        assert!(is_blocked(&client));
+        assert_eq!(queue.metrics.snapshot().reader_blocked, 1);
+
+        assert_eq!(queue.pop(), Some(1));
+        assert_eq!(client.recv(), Ok(true));
+        assert_eq!(queue.pop(), Some(2));
+    }
+
+    #[test]
+    fn closing_releases_blocked_reader() {
+        let queue = queue(1, OverloadPolicy::Block);
+        queue.push(1, EventClass::Peer);
+        let peer = push_in_background(&queue, 2, EventClass::Peer);
+        assert!(is_blocked(&peer));
+
+        queue.close();
+        assert_eq!(peer.recv(), Ok(false));
+        // queued events are still consumed
+        assert_eq!(queue.pop(), Some(1));
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test]
+    fn client_requests_are_shed_when_full() {
+        let queue = queue(2, OverloadPolicy::ShedClients);
+        queue.push(1, EventClass::ClientRequest);
+        queue.push(2, EventClass::Peer);
+
+        assert!(matches!(
+            queue.push(3, EventClass::ClientRequest),
+            Push::Shed(3)
+        ));
+        assert_eq!(queue.metrics.snapshot().events_shed, 1);
+
+        // other messages are not shed, they wait
+        let peer = push_in_background(&queue, 4, EventClass::Peer);
+        assert!(is_blocked(&peer));
+        queue.pop();
+        assert_eq!(peer.recv(), Ok(true));
+    }
+
+    #[test]
+    fn zero_capacity_is_rejected() {
+        let config = QueueConfig {
+            capacity: 0,
+            ..QueueConfig::default()
+        };
+        assert!(config.validate().is_err());
+        assert!(QueueConfig::default().validate().is_ok());
+    }
+
+    #[test]
+    fn replies_are_admitted_over_capacity() {
+        let queue = queue(1, OverloadPolicy::PrioritizeReplies);
+        queue.push(1, EventClass::Peer);
+
+        assert!(matches!(queue.push(2, EventClass::Reply), Push::Queued));
+        assert!(matches!(queue.push(3, EventClass::Reply), Push::Queued));
+
+        let client = push_in_background(&queue, 4, EventClass::ClientRequest);
+        assert!(is_blocked(&client));
+        // the reader waits until the queue gets below capacity
+        for _ in 0..3 {
+            queue.pop();
+        }
+        assert_eq!(client.recv(), Ok(true));
+    }
+
+    #[test]
+    fn replies_wait_under_other_policies() {
+        let queue = queue(1, OverloadPolicy::ShedClients);
+        queue.push(1, EventClass::Peer);
+        let reply = push_in_background(&queue, 2, EventClass::Reply);
+        assert!(is_blocked(&reply));
+        queue.pop();
+        assert_eq!(reply.recv(), Ok(true));
+    }
+
+    #[test]
+    fn replies_are_handled_first_when_prioritized() {
+        let queue = queue(10, OverloadPolicy::PrioritizeReplies);
+        queue.push(1, EventClass::Command);
+        queue.push(2, EventClass::Peer);
+        queue.push(3, EventClass::Reply);
+        queue.push(4, EventClass::Reply);
+        let order: Vec<_> = (0..4).filter_map(|_| queue.pop()).collect();
+        assert_eq!(order, vec![3, 4, 1, 2]);
+    }
+
+    /// Returns queue with the scheduling and `n` events of every class, numbered by class * 100 + i
+    fn backlogged(scheduling: Scheduling, n: u32) -> Arc<EventQueue<u32>> {
+        let config = QueueConfig {
+            scheduling,
+            ..QueueConfig::default()
+        };
+        let queue = Arc::new(EventQueue::new(config, Arc::new(Metrics::default())));
+        for i in 0..n {
+            for (class, event_class) in CLASSES.iter().enumerate() {
+                queue.push(class as u32 * 100 + i, *event_class);
+            }
+        }
+        queue
+    }
+
+    /// Event classes indexed by their priority
+    const CLASSES: [EventClass; PRIORITIES] = [
+        EventClass::Command,
+        EventClass::Reply,
+        EventClass::Peer,
+        EventClass::ClientRequest,
+    ];
+
+    #[test]
+    fn backlogged_classes_get_their_weights() {
+        let queue = backlogged(Scheduling::Weighted([8, 4, 2, 1]), 20);
+        let classes: Vec<_> = (0..30).map(|_| queue.pop().expect("event") / 100).collect();
+        // two rounds of 8 commands, 4 replies, 2 peer messages and 1 client request
+        let round = [vec![0; 8], vec![1; 4], vec![2; 2], vec![3]].concat();
+        assert_eq!(classes, [round.clone(), round].concat());
+    }
+
+    #[test]
+    fn credits_are_reset_when_backlogged_classes_use_them_up() {
+        let config = QueueConfig {
+            scheduling: Scheduling::Weighted([2, 1, 1, 1]),
+            ..QueueConfig::default()
+        };
+        let queue = EventQueue::new(config, Arc::new(Metrics::default()));
+        for i in 0..3 {
+            queue.push(i, EventClass::Command);
+            queue.push(100 + i, EventClass::ClientRequest);
+        }
+        // idle classes do not hold the round back
+        let order: Vec<_> = (0..6).filter_map(|_| queue.pop()).collect();
+        assert_eq!(order, vec![0, 1, 100, 2, 101, 102]);
+    }
+
+    #[test]
+    fn clients_do_not_starve_under_constant_command_load() {
+        let config = QueueConfig {
+            scheduling: Scheduling::Weighted([8, 4, 2, 1]),
+            ..QueueConfig::default()
+        };
+        let queue = EventQueue::new(config, Arc::new(Metrics::default()));
+        for i in 0..10 {
+            queue.push(1000 + i, EventClass::ClientRequest);
+        }
+        for i in 0..8 {
+            queue.push(i, EventClass::Command);
+        }
+
+        // every client request is handled after at most 8 commands
+        let mut next_command = 8;
+        for client in 1000..1010 {
+            for _ in 0..8 {
+                assert!(queue.pop().is_some_and(|event| event < 1000));
+                // every handled command is replaced by a new one
+                queue.push(next_command, EventClass::Command);
+                next_command += 1;
+            }
+            assert_eq!(queue.pop(), Some(client));
+        }
+    }
+
+    #[test]
+    fn fifo_ignores_classes() {
+        let queue = backlogged(Scheduling::Fifo, 2);
+        let order: Vec<_> = (0..8).filter_map(|_| queue.pop()).collect();
+        assert_eq!(order, vec![0, 100, 200, 300, 1, 101, 201, 301]);
+    }
+
+    #[test]
+    fn control_events_are_always_admitted() {
+        let queue = queue(1, OverloadPolicy::Block);
+        queue.push(1, EventClass::Peer);
+        assert!(matches!(queue.push(2, EventClass::Control), Push::Queued));
+    }
+}
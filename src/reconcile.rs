@@ -0,0 +1,152 @@
+//! Range-based set reconciliation
+//!
+//! Finds the difference of [`RangeSet`]s of two nodes without sending either of them whole and without
+//! any bookkeeping of what the other node knows. Nodes exchange fingerprints (hashes) of the elements
+//! in windows of the value domain, starting with one window covering all values. A window whose
+//! fingerprints differ is split by the ranges of the replying node into smaller windows until it contains
+//! only a few ranges, which are then sent as they are, and the other node answers them with just the
+//! elements the sender is missing. The exchanged data is thus proportional to the difference of the sets
+//! (times the logarithm of their size), which makes it suitable for repair after long partitions.
+//!
+//! Node reconciling with a peer:
+//! - sends it the parts returned by [`start`]
+//! - answers parts it receives by [`reply`]: merges [`Reply::learned`] elements into its set, sends
+//!   [`Reply::missing`] elements to the peer and [`Reply::parts`] back to it, unless empty
+//!
+//! See [Range-Based Set Reconciliation](https://arxiv.org/abs/2212.13567).
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    crdt::{Crdt, Discrete, RangeSet},
+    hash::Fnv1a,
+};
+
+/// Number of windows a window with differing fingerprints is split into
+const BRANCHES: usize = 16;
+/// Maximum number of ranges in a window that are sent as they are instead of its fingerprint
+const MAX_ITEMS: usize = 8;
+
+/// Part of the set in a window of values, from `start` to `end` inclusive
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+#[serde(bound(
+    serialize = "T: Discrete + Serialize",
+    deserialize = "T: Discrete + DeserializeOwned"
+))]
+pub enum Part<T: Discrete> {
+    /// Hash of the elements in the window
+    Fingerprint { start: T, end: T, hash: u64 },
+    /// All elements in the window
+    Items {
+        start: T,
+        end: T,
+        items: RangeSet<T>,
+    },
+}
+
+/// Answer to the parts received from a peer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reply<T: Discrete> {
+    /// Parts to send back to the peer
+    pub parts: Vec<Part<T>>,
+    /// Elements sent by the peer
+    pub learned: RangeSet<T>,
+    /// Elements the peer does not have
+    pub missing: RangeSet<T>,
+}
+
+/// Returns parts that start reconciliation of the set with a peer
+pub fn start<T: Discrete>(set: &RangeSet<T>) -> Vec<Part<T>> {
+    vec![window(set, T::MIN, T::MAX)]
+}
+
+/// Compares parts received from a peer with the set
+pub fn reply<T: Discrete>(set: &RangeSet<T>, parts: &[Part<T>]) -> Reply<T> {
+    let mut reply = Reply {
+        parts: Vec::new(),
+        learned: RangeSet::new(),
+        missing: RangeSet::new(),
+    };
+
+    for received in parts {
+        match *received {
+            Part::Fingerprint { start, end, hash } => {
+                if start > end || fingerprint(set, start, end) == hash {
+                    continue;
+                }
+                let ours = set.range(start, end);
+                if ours.ranges().count() <= MAX_ITEMS {
+                    reply.parts.push(Part::Items {
+                        start,
+                        end,
+                        items: ours,
+                    });
+                } else {
+                    for (start, end) in split(&ours, start, end) {
+                        reply.parts.push(window(set, start, end));
+                    }
+                }
+            }
+            Part::Items {
+                start,
+                end,
+                ref items,
+            } => {
+                // elements outside of the window are ignored, the peer does not expect them
+                let theirs = items.range(start, end);
+                if let Some(missing) = set.range(start, end).delta(&theirs) {
+                    reply.missing.merge(&missing);
+                }
+                reply.learned.merge(&theirs);
+            }
+        }
+    }
+
+    reply
+}
+
+/// Returns the elements of the window if there are only a few ranges of them, its fingerprint otherwise
+fn window<T: Discrete>(set: &RangeSet<T>, start: T, end: T) -> Part<T> {
+    let items = set.range(start, end);
+    if items.ranges().count() <= MAX_ITEMS {
+        Part::Items { start, end, items }
+    } else {
+        Part::Fingerprint {
+            start,
+            end,
+            hash: fingerprint(set, start, end),
+        }
+    }
+}
+
+/// Returns hash of the elements in the window, the ranges are canonical so equal sets have equal hashes.
+/// The hash is sent to other nodes, so it is computed by [`Fnv1a`] over the bounds of the ranges
+/// as little-endian `i128`s.
+fn fingerprint<T: Discrete>(set: &RangeSet<T>, start: T, end: T) -> u64 {
+    let mut hash = Fnv1a::new();
+    for (start, end) in set.range(start, end).ranges() {
+        hash.write(&start.to_i128().to_le_bytes());
+        hash.write(&end.to_i128().to_le_bytes());
+    }
+    hash.finish()
+}
+
+/// Splits the window into at most [`BRANCHES`] windows with about the same number of the ranges
+/// of `items` (elements of the window, with more than one range)
+fn split<T: Discrete>(items: &RangeSet<T>, start: T, end: T) -> Vec<(T, T)> {
+    let starts: Vec<T> = items.ranges().map(|(s, _)| s).collect();
+    let mut windows = Vec::new();
+    let mut window_start = start;
+    for i in 1..BRANCHES {
+        let split = starts[i * starts.len() / BRANCHES];
+        // the first range starts at or after the window start, the others after it
+        if split > window_start {
+            let end = split.predecessor().expect("above window start");
+            windows.push((window_start, end));
+            window_start = split;
+        }
+    }
+    windows.push((window_start, end));
+    windows
+}
@@ -0,0 +1,73 @@
+//! Small pseudo-random generator
+//!
+//! Xorshift generator used for peer selection and by the local simulation (services, runner),
+//! where a fixed seed makes a run reproducible.
+
+use std::hash::{BuildHasher, Hasher, RandomState};
+
+/// Xorshift pseudo-random generator
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Default for Rng {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Rng {
+    /// Creates randomly seeded generator
+    pub fn new() -> Self {
+        // randomly seeded hasher gives us different seed in every process
+        Self::seeded(RandomState::new().build_hasher().finish())
+    }
+
+    /// Creates generator producing always the same sequence for the same seed
+    pub fn seeded(seed: u64) -> Self {
+        // xorshift state must not be zero
+        Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Returns number in range `0..n`, `n` must not be zero
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    /// Returns number in range `low..=high`
+    pub fn between(&mut self, low: u64, high: u64) -> u64 {
+        low + self.below(high - low + 1)
+    }
+
+    /// Returns number in range `0.0..1.0`
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Returns `true` with probability `p`
+    pub fn chance(&mut self, p: f64) -> bool {
+        self.next_f64() < p
+    }
+
+    /// Returns random element of the slice
+    pub fn pick<'a, T>(&mut self, items: &'a [T]) -> Option<&'a T> {
+        if items.is_empty() {
+            return None;
+        }
+        items.get(self.below(items.len() as u64) as usize)
+    }
+
+    /// Fisher-Yates shuffle
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = self.below(i as u64 + 1) as usize;
+            items.swap(i, j);
+        }
+    }
+}
@@ -0,0 +1,916 @@
+//! Local end-to-end runner
+//!
+//! A small stand-in for Maelstrom used by the `gossipy-run` binary. It spawns the nodes as child processes,
+//! talks to them over STDIN/STDOUT with the Maelstrom protocol and:
+//! - initializes the nodes and sends them the workload setup (eg. broadcast topology)
+//! - routes messages between the nodes with configurable latency
+//! - answers requests to `lin-kv`, `seq-kv` and `lww-kv` by the local [services](crate::kv_store)
+//! - runs clients invoking operations of the [workload](crate::workload) at the given rate
+//! - records the operations to a [history](crate::history) file
+//!
+//! The run ends with a recovery period in which no operations are invoked, followed by final
+//! operations on every node (eg. broadcast reads). The history is then verified by the workload's
+//! [checker](crate::checker). Nodes' STDERR is written to `<node id>.log` in the output directory
+//! next to `history.jsonl` and `results.edn`.
+
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BinaryHeap, HashMap, HashSet},
+    fs::{self, File},
+    io::{BufRead, BufReader, Write},
+    path::PathBuf,
+    process::{Child, ChildStdin, Command, Stdio},
+    str::FromStr,
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::{
+    checker::{self, ConsistencyModel},
+    error,
+    history::{self, Op, OpType},
+    kv_store::{self, KvServer},
+    rng::Rng,
+    workload::{self, Invocation, Workload, WorkloadOptions},
+    Message, MsgId,
+};
+
+/// Source of the messages sent by the runner itself (`init`, workload setup)
+const CONTROL_ID: &str = "ctl";
+/// How often the key-value services replicate their state
+const SERVICE_TICK: Duration = Duration::from_millis(100);
+/// How long nodes have to answer `init` and setup messages
+const SETUP_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long nodes have to exit after their STDIN is closed
+const EXIT_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub const USAGE: &str = "\
+Usage: gossipy-run -w <workload> --bin <node binary> [node args...] [options]
+
+Options:
+  -w, --workload <name>     echo, unique-ids, broadcast, g-counter, kafka, txn-rw-register
+                            or lin-kv
+      --bin <path>          node binary, positional arguments are passed to the nodes
+      --node-count <n>      number of nodes (default 1)
+      --concurrency <n>     number of clients, `<k>n` means k clients per node (default 1n)
+      --rate <ops>          operations per second (default 5)
+      --time-limit <s>      how long operations are invoked (default 10)
+      --latency <ms>        network latency (default 0)
+      --latency-dist <d>    constant, uniform or exponential (default constant)
+      --timeout <ms>        client timeout (default 5000)
+      --recovery-time <s>   quiet period before final operations (default 2)
+      --topology <t>        broadcast topology: grid, line or total (default grid)
+      --key-count <n>       number of keys of kafka, txn-rw-register and lin-kv (default 4)
+      --max-txn-length <n>  maximum number of operations in a transaction (default 4)
+      --consistency-models <m>
+                            model checked by txn-rw-register: read-uncommitted
+                            or read-committed (default read-committed)
+      --seed <n>            seed of the workload and network randomness
+      --out <dir>           output directory (default store/<workload>)";
+
+/// Distribution of the network latency
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LatencyDist {
+    /// Every message is delayed by the latency
+    Constant,
+    /// Delay is uniformly distributed between zero and twice the latency
+    Uniform,
+    /// Delay is exponentially distributed with the latency as mean
+    Exponential,
+}
+
+impl FromStr for LatencyDist {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "constant" => Ok(LatencyDist::Constant),
+            "uniform" => Ok(LatencyDist::Uniform),
+            "exponential" => Ok(LatencyDist::Exponential),
+            _ => {
+                bail!("unknown latency distribution {s}, expected constant, uniform or exponential")
+            }
+        }
+    }
+}
+
+/// Runner configuration
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Node binary
+    pub bin: PathBuf,
+    /// Arguments of the node binary
+    pub args: Vec<String>,
+    pub workload: String,
+    pub workload_options: WorkloadOptions,
+    pub node_count: usize,
+    /// Number of clients
+    pub concurrency: usize,
+    /// Operations per second
+    pub rate: f64,
+    /// How long operations are invoked
+    pub time_limit: Duration,
+    pub latency: Duration,
+    pub latency_dist: LatencyDist,
+    /// Time after which an operation without reply is considered indefinite
+    pub timeout: Duration,
+    /// Quiet period before the final operations
+    pub recovery: Duration,
+    pub seed: u64,
+    /// Model checked by the txn-rw-register checker
+    pub consistency_model: ConsistencyModel,
+    /// Directory with history and node logs
+    pub out: PathBuf,
+}
+
+impl Config {
+    /// Creates configuration with default options
+    pub fn new(bin: impl Into<PathBuf>, workload: &str) -> Self {
+        Self {
+            bin: bin.into(),
+            args: Vec::new(),
+            workload: workload.to_string(),
+            workload_options: WorkloadOptions::default(),
+            node_count: 1,
+            concurrency: 1,
+            rate: 5.0,
+            time_limit: Duration::from_secs(10),
+            latency: Duration::ZERO,
+            latency_dist: LatencyDist::Constant,
+            timeout: Duration::from_secs(5),
+            recovery: Duration::from_secs(2),
+            seed: Rng::new().next_u64(),
+            consistency_model: ConsistencyModel::ReadCommitted,
+            out: PathBuf::from("store").join(workload),
+        }
+    }
+
+    /// Parses command line arguments (without the program name), see [`USAGE`]
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> anyhow::Result<Self> {
+        let mut args = args.into_iter();
+        let mut options = HashMap::new();
+        let mut positional = Vec::new();
+        while let Some(arg) = args.next() {
+            let name = match arg.as_str() {
+                "-w" => "workload",
+                _ => match arg.strip_prefix("--") {
+                    Some(name) => name,
+                    None => {
+                        positional.push(arg);
+                        continue;
+                    }
+                },
+            }
+            .to_string();
+            let value = args
+                .next()
+                .with_context(|| format!("missing value of --{name}"))?;
+            options.insert(name, value);
+        }
+
+        let workload = options.remove("workload").context("missing --workload")?;
+        let bin = options.remove("bin").context("missing --bin")?;
+        let mut config = Self::new(bin, &workload);
+        config.args = positional;
+
+        if let Some(n) = options.remove("node-count") {
+            config.node_count = n.parse().context("parsing --node-count")?;
+        }
+        config.concurrency = config.node_count;
+        let mut take = |name: &str| options.remove(name).map(|value| (name.to_string(), value));
+        if let Some((_, n)) = take("concurrency") {
+            config.concurrency = match n.strip_suffix('n') {
+                Some(k) => k.parse::<usize>().context("parsing --concurrency")? * config.node_count,
+                None => n.parse().context("parsing --concurrency")?,
+            };
+        }
+        if let Some((_, rate)) = take("rate") {
+            config.rate = rate.parse().context("parsing --rate")?;
+        }
+        if let Some((_, s)) = take("time-limit") {
+            config.time_limit = Duration::from_secs_f64(s.parse().context("parsing --time-limit")?);
+        }
+        if let Some((_, ms)) = take("latency") {
+            config.latency = Duration::from_millis(ms.parse().context("parsing --latency")?);
+        }
+        if let Some((_, dist)) = take("latency-dist") {
+            config.latency_dist = dist.parse()?;
+        }
+        if let Some((_, ms)) = take("timeout") {
+            config.timeout = Duration::from_millis(ms.parse().context("parsing --timeout")?);
+        }
+        if let Some((_, s)) = take("recovery-time") {
+            config.recovery =
+                Duration::from_secs_f64(s.parse().context("parsing --recovery-time")?);
+        }
+        if let Some((_, topology)) = take("topology") {
+            config.workload_options.topology = topology.parse()?;
+        }
+        if let Some((_, n)) = take("key-count") {
+            config.workload_options.key_count = n.parse().context("parsing --key-count")?;
+        }
+        if let Some((_, n)) = take("max-txn-length") {
+            config.workload_options.max_txn_length =
+                n.parse().context("parsing --max-txn-length")?;
+        }
+        if let Some((_, model)) = take("consistency-models") {
+            config.consistency_model = model.parse()?;
+        }
+        if let Some((_, seed)) = take("seed") {
+            config.seed = seed.parse().context("parsing --seed")?;
+        }
+        if let Some((_, out)) = take("out") {
+            config.out = PathBuf::from(out);
+        }
+        if let Some(name) = options.keys().next() {
+            bail!("unknown option --{name}");
+        }
+
+        if config.node_count == 0 || config.concurrency == 0 {
+            bail!("--node-count and --concurrency must be positive");
+        }
+        if config.rate.is_nan() || config.rate <= 0.0 {
+            bail!("--rate must be positive");
+        }
+        Ok(config)
+    }
+}
+
+/// Statistics of the run
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Summary {
+    /// f => completed operations
+    pub ops: BTreeMap<String, OpCounts>,
+    pub messages: MessageCounts,
+    /// Messages between nodes per client operation
+    pub msgs_per_op: f64,
+    /// Latency of successful operations
+    pub latency_ms: LatencyStats,
+    /// Recorded history
+    pub history: PathBuf,
+    /// Result of the history checker
+    pub valid: bool,
+    /// Checker results
+    pub results: PathBuf,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct OpCounts {
+    pub ok: usize,
+    pub fail: usize,
+    pub info: usize,
+}
+
+/// Numbers of messages sent over the network
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MessageCounts {
+    pub all: usize,
+    /// Between the nodes
+    pub servers: usize,
+    /// Between the nodes and the key-value services
+    pub services: usize,
+    /// Between the nodes and the clients
+    pub clients: usize,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LatencyStats {
+    pub median: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+/// Runs the test, writes history and checker results to the output directory
+pub fn run(config: &Config) -> anyhow::Result<Summary> {
+    let workload = workload::workload(&config.workload, &config.workload_options)?;
+    fs::create_dir_all(&config.out)
+        .with_context(|| format!("creating {}", config.out.display()))?;
+
+    let mut runner = Runner::start(config, workload)?;
+    let result = runner.run();
+    runner.stop();
+    result?;
+
+    let path = config.out.join("history.jsonl");
+    history::write(&path, &runner.history)?;
+    let mut summary = runner.summary(path);
+
+    let mut results = checker::check(&config.workload, &runner.history, config.consistency_model)?;
+    results.net = Some(json!({"all": summary.messages, "msgs-per-op": summary.msgs_per_op}));
+    summary.valid = results.valid;
+    fs::write(
+        &summary.results,
+        checker::to_edn(&serde_json::to_value(&results)?),
+    )
+    .with_context(|| format!("writing {}", summary.results.display()))?;
+
+    Ok(summary)
+}
+
+/// Message waiting for delivery
+struct Delivery {
+    at: Instant,
+    /// Tiebreaker keeping the order of messages with the same delivery time
+    seq: u64,
+    dst: String,
+    line: String,
+}
+
+impl PartialEq for Delivery {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Delivery {}
+
+impl PartialOrd for Delivery {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Delivery {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // reversed, so the heap pops the earliest delivery
+        (other.at, other.seq).cmp(&(self.at, self.seq))
+    }
+}
+
+/// Messages in flight
+struct Network {
+    latency: Duration,
+    dist: LatencyDist,
+    queue: BinaryHeap<Delivery>,
+    seq: u64,
+}
+
+impl Network {
+    fn send(&mut self, dst: &str, line: String, rng: &mut Rng) {
+        let latency = self.latency.as_secs_f64();
+        let delay = match self.dist {
+            LatencyDist::Constant => latency,
+            LatencyDist::Uniform => 2.0 * latency * rng.next_f64(),
+            LatencyDist::Exponential => -latency * (1.0 - rng.next_f64()).ln(),
+        };
+        self.seq += 1;
+        self.queue.push(Delivery {
+            at: Instant::now() + Duration::from_secs_f64(delay),
+            seq: self.seq,
+            dst: dst.to_string(),
+            line,
+        });
+    }
+
+    fn next_at(&self) -> Option<Instant> {
+        self.queue.peek().map(|delivery| delivery.at)
+    }
+
+    /// Returns next message due for delivery
+    fn due(&mut self, now: Instant) -> Option<Delivery> {
+        if self.next_at()? <= now {
+            self.queue.pop()
+        } else {
+            None
+        }
+    }
+}
+
+/// Operation waiting for reply
+struct Pending {
+    msg_id: MsgId,
+    invocation: Invocation,
+    invoked_at: Instant,
+}
+
+/// Client invoking one operation at a time on its node
+struct Client {
+    /// History process, changed after an indefinite operation
+    process: usize,
+    node: usize,
+    pending: Option<Pending>,
+}
+
+impl Client {
+    fn id(&self) -> String {
+        format!("c{}", self.process)
+    }
+}
+
+struct NodeProcess {
+    child: Child,
+    /// Closed on stop
+    stdin: Option<ChildStdin>,
+}
+
+struct Runner<'a> {
+    config: &'a Config,
+    start: Instant,
+    rng: Rng,
+    node_ids: Vec<String>,
+    nodes: Vec<NodeProcess>,
+    /// Lines written by the nodes, `None` when the node closed its STDOUT
+    lines: Receiver<(usize, Option<String>)>,
+    network: Network,
+    services: HashMap<String, KvServer>,
+    next_tick: Instant,
+    workload: Box<dyn Workload>,
+    clients: Vec<Client>,
+    /// client ID => index of the client
+    client_index: HashMap<String, usize>,
+    next_process: usize,
+    /// Messages sent by the runner waiting for reply
+    control: HashSet<MsgId>,
+    next_msg_id: usize,
+    /// When the next operation is invoked
+    next_op_at: Instant,
+    history: Vec<Op>,
+    latencies: Vec<Duration>,
+    messages: MessageCounts,
+}
+
+impl<'a> Runner<'a> {
+    /// Spawns the nodes
+    fn start(config: &'a Config, workload: Box<dyn Workload>) -> anyhow::Result<Self> {
+        let node_ids: Vec<String> = (0..config.node_count).map(|i| format!("n{i}")).collect();
+        let (tx, lines) = mpsc::channel();
+
+        let mut nodes = Vec::new();
+        for (i, id) in node_ids.iter().enumerate() {
+            let log = config.out.join(format!("{id}.log"));
+            let log = File::create(&log).with_context(|| format!("creating {}", log.display()))?;
+            let mut child = Command::new(&config.bin)
+                .args(&config.args)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(log)
+                .spawn()
+                .with_context(|| format!("spawning {}", config.bin.display()))?;
+
+            let stdout = child.stdout.take().expect("stdout is piped");
+            let tx = tx.clone();
+            std::thread::spawn(move || {
+                for line in BufReader::new(stdout).lines() {
+                    let Ok(line) = line else { break };
+                    if tx.send((i, Some(line))).is_err() {
+                        return;
+                    }
+                }
+                let _ = tx.send((i, None));
+            });
+
+            let stdin = child.stdin.take();
+            nodes.push(NodeProcess { child, stdin });
+        }
+
+        let mut rng = Rng::seeded(config.seed);
+        let clients: Vec<Client> = (0..config.concurrency)
+            .map(|process| Client {
+                process,
+                node: process % config.node_count,
+                pending: None,
+            })
+            .collect();
+        let client_index = clients
+            .iter()
+            .enumerate()
+            .map(|(i, client)| (client.id(), i))
+            .collect();
+        let services = [
+            kv_store::LIN_KV_SERVICE_ID,
+            kv_store::SEQ_KV_SERVICE_ID,
+            kv_store::LWW_KV_SERVICE_ID,
+        ]
+        .into_iter()
+        .map(|id| {
+            let service =
+                kv_store::service(id, Rng::seeded(rng.next_u64())).expect("known service");
+            (id.to_string(), KvServer::new(id, service))
+        })
+        .collect();
+
+        let now = Instant::now();
+        Ok(Self {
+            config,
+            start: now,
+            rng,
+            node_ids,
+            nodes,
+            lines,
+            network: Network {
+                latency: config.latency,
+                dist: config.latency_dist,
+                queue: BinaryHeap::new(),
+                seq: 0,
+            },
+            services,
+            next_tick: now + SERVICE_TICK,
+            workload,
+            next_process: clients.len(),
+            clients,
+            client_index,
+            control: HashSet::new(),
+            next_msg_id: 0,
+            next_op_at: now,
+            history: Vec::new(),
+            latencies: Vec::new(),
+            messages: MessageCounts::default(),
+        })
+    }
+
+    fn run(&mut self) -> anyhow::Result<()> {
+        // init and workload setup
+        for i in 0..self.nodes.len() {
+            let body =
+                json!({"type": "init", "node_id": self.node_ids[i], "node_ids": self.node_ids});
+            self.send_control(i, body);
+        }
+        self.wait_for_control("init")?;
+        for (i, body) in self.workload.setup(&self.node_ids) {
+            self.send_control(i, body);
+        }
+        self.wait_for_control("setup")?;
+
+        self.next_op_at = Instant::now();
+        self.run_until(Instant::now() + self.config.time_limit, true, |_| false)?;
+        self.run_until(Instant::now() + self.config.timeout, false, Self::idle)?;
+        self.run_until(Instant::now() + self.config.recovery, false, |_| false)?;
+
+        // final operations on every node by new clients
+        let finish = self.workload.finish();
+        for node in 0..self.nodes.len() {
+            for invocation in finish.iter() {
+                let i = self.clients.len();
+                self.clients.push(Client {
+                    process: self.next_process,
+                    node,
+                    pending: None,
+                });
+                self.next_process += 1;
+                self.client_index.insert(self.clients[i].id(), i);
+                self.invoke(i, invocation.clone());
+            }
+        }
+        self.run_until(Instant::now() + self.config.timeout, false, Self::idle)?;
+
+        // operations still without reply would time out
+        for i in 0..self.clients.len() {
+            if self.clients[i].pending.is_some() {
+                self.complete_indefinite(
+                    i,
+                    json!({"code": error::TIMEOUT, "text": "no reply before the end of the test"}),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Closes nodes' STDIN and waits for them to exit, kills them after a timeout
+    fn stop(&mut self) {
+        for node in self.nodes.iter_mut() {
+            node.stdin.take();
+        }
+        let deadline = Instant::now() + EXIT_TIMEOUT;
+        for (node, id) in self.nodes.iter_mut().zip(self.node_ids.iter()) {
+            loop {
+                match node.child.try_wait() {
+                    Ok(Some(_)) => break,
+                    Ok(None) if Instant::now() < deadline => {
+                        std::thread::sleep(Duration::from_millis(10))
+                    }
+                    _ => {
+                        eprintln!("node {id} did not exit, killing it");
+                        let _ = node.child.kill();
+                        let _ = node.child.wait();
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns `true` if no client waits for reply
+    fn idle(&self) -> bool {
+        self.clients.iter().all(|client| client.pending.is_none())
+    }
+
+    fn wait_for_control(&mut self, phase: &str) -> anyhow::Result<()> {
+        self.run_until(Instant::now() + SETUP_TIMEOUT, false, |runner| {
+            runner.control.is_empty()
+        })?;
+        if !self.control.is_empty() {
+            bail!("{} {phase} messages were not answered", self.control.len());
+        }
+        Ok(())
+    }
+
+    /// Routes messages until the deadline or until `done`, invokes new operations if `invoking`
+    fn run_until(
+        &mut self,
+        deadline: Instant,
+        invoking: bool,
+        done: impl Fn(&Self) -> bool,
+    ) -> anyhow::Result<()> {
+        loop {
+            let now = Instant::now();
+            if now >= deadline || done(self) {
+                return Ok(());
+            }
+
+            let mut wake = deadline.min(self.next_tick);
+            if let Some(at) = self.network.next_at() {
+                wake = wake.min(at);
+            }
+            if invoking {
+                wake = wake.min(self.next_op_at);
+            }
+            for client in self.clients.iter() {
+                if let Some(pending) = &client.pending {
+                    wake = wake.min(pending.invoked_at + self.config.timeout);
+                }
+            }
+
+            match self.lines.recv_timeout(wake.saturating_duration_since(now)) {
+                Ok((node, Some(line))) => self.receive(node, line),
+                Ok((node, None)) => {
+                    let id = &self.node_ids[node];
+                    let log = self.config.out.join(format!("{id}.log"));
+                    bail!("node {id} exited, see {}", log.display());
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => bail!("all nodes exited"),
+            }
+
+            let now = Instant::now();
+            while let Some(delivery) = self.network.due(now) {
+                self.deliver(delivery)?;
+            }
+            self.expire(now);
+            if now >= self.next_tick {
+                self.services.values_mut().for_each(KvServer::tick);
+                self.next_tick = now + SERVICE_TICK;
+            }
+            if invoking && now >= self.next_op_at {
+                self.next_op_at += Duration::from_secs_f64(1.0 / self.config.rate);
+                self.invoke_next();
+            }
+        }
+    }
+
+    /// Handles line written by the node
+    fn receive(&mut self, node: usize, line: String) {
+        let Ok(msg) = serde_json::from_str::<Message<Value>>(&line) else {
+            eprintln!("{}: malformed message {line}", self.node_ids[node]);
+            return;
+        };
+
+        self.messages.all += 1;
+        if self.is_node(&msg.dst) {
+            self.messages.servers += 1;
+        } else if self.services.contains_key(&msg.dst) {
+            self.messages.services += 1;
+        } else {
+            self.messages.clients += 1;
+        }
+        self.network.send(&msg.dst, line, &mut self.rng);
+    }
+
+    fn deliver(&mut self, delivery: Delivery) -> anyhow::Result<()> {
+        let Delivery { dst, line, .. } = delivery;
+
+        if let Some(node) = self.node_ids.iter().position(|id| *id == dst) {
+            let stdin = self.nodes[node].stdin.as_mut().expect("open until stop");
+            return writeln!(stdin, "{line}").with_context(|| format!("writing to node {dst}"));
+        }
+
+        if let Some(service) = self.services.get_mut(&dst) {
+            if let Some(reply) = service.handle_line(&line) {
+                self.messages.all += 1;
+                self.messages.services += 1;
+                let line = serde_json::to_string(&reply).context("serializing reply")?;
+                self.network.send(&reply.dst, line, &mut self.rng);
+            }
+            return Ok(());
+        }
+
+        let Ok(msg) = serde_json::from_str::<Message<Value>>(&line) else {
+            return Ok(());
+        };
+        let Some(in_reply_to) = msg.body.in_reply_to else {
+            return Ok(());
+        };
+        if dst == CONTROL_ID {
+            self.control.remove(&in_reply_to);
+        } else if let Some(&i) = self.client_index.get(&dst) {
+            let expected = self.clients[i]
+                .pending
+                .as_ref()
+                .map(|pending| pending.msg_id);
+            if expected == Some(in_reply_to) {
+                self.complete(i, msg.body.payload);
+            }
+        } else {
+            eprintln!("dropping message to unknown destination {dst}");
+        }
+        Ok(())
+    }
+
+    fn is_node(&self, id: &str) -> bool {
+        self.node_ids.iter().any(|node_id| node_id == id)
+    }
+
+    fn new_msg_id(&mut self) -> MsgId {
+        self.next_msg_id += 1;
+        MsgId(self.next_msg_id)
+    }
+
+    fn send_control(&mut self, node: usize, mut body: Value) {
+        let msg_id = self.new_msg_id();
+        body["msg_id"] = json!(msg_id);
+        self.control.insert(msg_id);
+        let line = json!({"src": CONTROL_ID, "dest": self.node_ids[node], "body": body});
+        let dst = self.node_ids[node].clone();
+        self.network.send(&dst, line.to_string(), &mut self.rng);
+    }
+
+    /// Invokes next operation of the workload on a random idle client
+    fn invoke_next(&mut self) {
+        let idle: Vec<usize> = (0..self.clients.len())
+            .filter(|i| self.clients[*i].pending.is_none())
+            .collect();
+        let Some(&i) = self.rng.pick(&idle) else {
+            return;
+        };
+        let invocation = self.workload.invoke(self.clients[i].process, &mut self.rng);
+        self.invoke(i, invocation);
+    }
+
+    fn invoke(&mut self, i: usize, invocation: Invocation) {
+        let msg_id = self.new_msg_id();
+        let client = &self.clients[i];
+        let dst = self.node_ids[client.node].clone();
+
+        let mut body = invocation.request.clone();
+        body["msg_id"] = json!(msg_id);
+        let line = json!({"src": client.id(), "dest": dst, "body": body});
+
+        self.history.push(Op {
+            process: client.process,
+            ty: OpType::Invoke,
+            f: invocation.f.to_string(),
+            value: invocation.value.clone(),
+            time: self.time(),
+            node: dst.clone(),
+            error: None,
+        });
+        self.clients[i].pending = Some(Pending {
+            msg_id,
+            invocation,
+            invoked_at: Instant::now(),
+        });
+        self.messages.all += 1;
+        self.messages.clients += 1;
+        self.network.send(&dst, line.to_string(), &mut self.rng);
+    }
+
+    /// Completes pending operation of the client with the reply
+    fn complete(&mut self, i: usize, reply: Value) {
+        let client = &mut self.clients[i];
+        let pending = client.pending.take().expect("operation is pending");
+        let expected = format!(
+            "{}_ok",
+            pending.invocation.request["type"].as_str().unwrap_or("")
+        );
+
+        let (ty, value, error) = match reply["type"].as_str() {
+            Some(ty) if ty == expected => {
+                let value = self
+                    .workload
+                    .complete(client.process, &pending.invocation, &reply);
+                (OpType::Ok, value, None)
+            }
+            Some("error") => {
+                let definite = reply["code"]
+                    .as_u64()
+                    .is_some_and(|code| error::is_definite(code as u16));
+                let ty = if definite { OpType::Fail } else { OpType::Info };
+                (ty, pending.invocation.value.clone(), Some(reply))
+            }
+            _ => (OpType::Info, pending.invocation.value.clone(), Some(reply)),
+        };
+
+        if ty == OpType::Ok {
+            self.latencies.push(pending.invoked_at.elapsed());
+        }
+        self.record(i, ty, &pending.invocation, value, error);
+    }
+
+    /// Completes pending operation of the client as indefinite
+    fn complete_indefinite(&mut self, i: usize, error: Value) {
+        let pending = self.clients[i]
+            .pending
+            .take()
+            .expect("operation is pending");
+        let value = pending.invocation.value.clone();
+        self.record(i, OpType::Info, &pending.invocation, value, Some(error));
+    }
+
+    fn record(
+        &mut self,
+        i: usize,
+        ty: OpType,
+        invocation: &Invocation,
+        value: Value,
+        error: Option<Value>,
+    ) {
+        let time = self.time();
+        let client = &mut self.clients[i];
+        self.history.push(Op {
+            process: client.process,
+            ty,
+            f: invocation.f.to_string(),
+            value,
+            time,
+            node: self.node_ids[client.node].clone(),
+            error,
+        });
+
+        if ty == OpType::Info {
+            // the process may still be running the operation, so the client continues as a new process
+            self.client_index.remove(&client.id());
+            client.process = self.next_process;
+            self.next_process += 1;
+            self.client_index.insert(client.id(), i);
+        }
+    }
+
+    /// Completes timed out operations as indefinite
+    fn expire(&mut self, now: Instant) {
+        for i in 0..self.clients.len() {
+            let expired = self.clients[i]
+                .pending
+                .as_ref()
+                .is_some_and(|pending| pending.invoked_at + self.config.timeout <= now);
+            if expired {
+                self.complete_indefinite(
+                    i,
+                    json!({"code": error::TIMEOUT, "text": "client timed out"}),
+                );
+            }
+        }
+    }
+
+    /// Returns nanoseconds since the start
+    fn time(&self) -> u64 {
+        self.start.elapsed().as_nanos() as u64
+    }
+
+    fn summary(&self, history: PathBuf) -> Summary {
+        let results = history.with_file_name("results.edn");
+        let mut ops: BTreeMap<String, OpCounts> = BTreeMap::new();
+        for op in self.history.iter() {
+            let counts = ops.entry(op.f.clone()).or_default();
+            match op.ty {
+                OpType::Invoke => {}
+                OpType::Ok => counts.ok += 1,
+                OpType::Fail => counts.fail += 1,
+                OpType::Info => counts.info += 1,
+            }
+        }
+        let completed: usize = ops
+            .values()
+            .map(|counts| counts.ok + counts.fail + counts.info)
+            .sum();
+
+        let mut latencies: Vec<f64> = self
+            .latencies
+            .iter()
+            .map(|latency| latency.as_secs_f64() * 1000.0)
+            .collect();
+        latencies.sort_by(f64::total_cmp);
+        let percentile = |p: f64| {
+            latencies
+                .get(((latencies.len() as f64 * p) as usize).min(latencies.len().saturating_sub(1)))
+                .copied()
+                .unwrap_or(0.0)
+        };
+
+        Summary {
+            ops,
+            messages: self.messages.clone(),
+            msgs_per_op: self.messages.servers as f64 / completed.max(1) as f64,
+            latency_ms: LatencyStats {
+                median: percentile(0.5),
+                p99: percentile(0.99),
+                max: latencies.last().copied().unwrap_or(0.0),
+            },
+            history,
+            valid: false,
+            results,
+        }
+    }
+}
@@ -0,0 +1,226 @@
+//! Key-sharded parallel handler execution
+//!
+//! Opt-in runtime mode started by [`Node::run_sharded`](crate::Node::run_sharded).
+//! The runtime runs N instances (shards) of the handler, every one in its own thread with its own event queue.
+//! Handler declares a shard key for every message and messages with the same key are always handled
+//! by the same shard in the order of arrival, so the per-key ordering is preserved.
+//!
+//! - replies to our requests are handled by the shard that sent the request
+//! - commands are delivered to every shard
+//! - messages that touch several keys (eg. kafka poll of several logs) are declared as [`ShardKey::Barrier`],
+//!   they are handled by shard 0 exclusively, after all previously received events were handled
+//!   and before any following message is handled
+//! - admin messages are handled as barriers
+//!
+//! Shards may share state (eg. through `Arc<Mutex<_>>`), [`shard_of`] tells which shard owns the key.
+//! Keys are hashed by the stable [`fnv1a`] hash, so a key is owned by the same shard in every build
+//! as long as the number of shards does not change.
+
+use std::sync::{Condvar, Mutex};
+
+use crate::{hash::fnv1a, Handler, Message};
+
+/// Routing of a message to a shard
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShardKey {
+    /// Hash of the key, use [`ShardKey::of`] to create it
+    Key(u64),
+    /// Message is handled exclusively by shard 0
+    Barrier,
+}
+
+impl ShardKey {
+    /// Creates shard key from bytes of the key (eg. a string)
+    pub fn of<K: AsRef<[u8]> + ?Sized>(key: &K) -> Self {
+        ShardKey::Key(fnv1a(key.as_ref()))
+    }
+}
+
+/// Returns index of the shard that handles messages with the key
+pub fn shard_of<K: AsRef<[u8]> + ?Sized>(key: &K, shards: usize) -> usize {
+    match ShardKey::of(key) {
+        ShardKey::Key(hash) => (hash % shards as u64) as usize,
+        ShardKey::Barrier => 0,
+    }
+}
+
+/// Handler that can be run in multiple shards
+pub trait ShardedHandler<Payload, Command = ()>: Handler<Payload, Command> {
+    /// Returns shard key of the incoming message (other than reply to our request)
+    fn shard_key(msg: &Message<Payload>) -> ShardKey;
+}
+
+/// Counter of events that were queued but not handled yet, used to wait for all shards to become idle
+#[derive(Default)]
+pub(crate) struct Outstanding {
+    /// (number of events, some shard stopped)
+    state: Mutex<(usize, bool)>,
+    idle: Condvar,
+}
+
+impl Outstanding {
+    pub(crate) fn add(&self) {
+        self.state.lock().expect("lock").0 += 1;
+    }
+
+    pub(crate) fn done(&self) {
+        let mut state = self.state.lock().expect("lock");
+        state.0 -= 1;
+        if state.0 == 0 {
+            self.idle.notify_all();
+        }
+    }
+
+    /// Marks that some shard stopped, so its events will never be handled
+    pub(crate) fn abort(&self) {
+        self.state.lock().expect("lock").1 = true;
+        self.idle.notify_all();
+    }
+
+    /// Blocks until all queued events are handled (or some shard stopped)
+    pub(crate) fn wait_idle(&self) {
+        let mut state = self.state.lock().expect("lock");
+        while state.0 > 0 && !state.1 {
+            state = self.idle.wait(state).expect("lock");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Cursor, sync::Arc, thread, time::Duration};
+
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::Node;
+
+    const SHARDS: usize = 4;
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(rename_all = "snake_case", tag = "type")]
+    enum Payload {
+        Op { key: String, seq: usize },
+        Poll { seq: usize },
+    }
+
+    /// (shard, message) in the order the messages were handled
+    type Log = Arc<Mutex<Vec<(usize, Payload)>>>;
+
+    struct Recorder {
+        log: Log,
+    }
+
+    impl Handler<Payload> for Recorder {
+        fn handle(&mut self, msg: Message<Payload>, node: Node) -> anyhow::Result<()> {
+            let seq = match &msg.body.payload {
+                Payload::Op { seq, .. } | Payload::Poll { seq } => *seq,
+            };
+            // let the shards interleave
+            thread::sleep(Duration::from_micros((seq % 5) as u64 * 100));
+            let entry = (node.shard, msg.body.payload);
+            self.log.lock().expect("lock").push(entry);
+            Ok(())
+        }
+    }
+
+    fn shard_key(msg: &Message<Payload>) -> ShardKey {
+        match &msg.body.payload {
+            Payload::Op { key, .. } => ShardKey::of(key.as_str()),
+            Payload::Poll { .. } => ShardKey::Barrier,
+        }
+    }
+
+    /// Runs the messages through sharded runtime, returns the log of handled messages
+    fn run(payloads: &[Payload]) -> Vec<(usize, Payload)> {
+        let input: String = payloads
+            .iter()
+            .enumerate()
+            .map(|(i, payload)| {
+                let mut body = serde_json::to_value(payload).expect("payload");
+                body["msg_id"] = i.into();
+                let msg = serde_json::json!({"src": "c1", "dest": "n0", "body": body});
+                format!("{msg}\n")
+            })
+            .collect();
+
+        let mut node: Node = Node::with_ids("n0", &["n0".to_string()]).expect("creating node");
+        node.capture_output();
+        let log = Log::default();
+        let handlers = (0..SHARDS).map(|_| Recorder { log: log.clone() }).collect();
+        node.run_shards(Cursor::new(input), handlers, shard_key)
+            .expect("running shards");
+
+        let log = log.lock().expect("lock").clone();
+        assert_eq!(log.len(), payloads.len());
+        log
+    }
+
+    fn ops(seqs: std::ops::Range<usize>) -> impl Iterator<Item = Payload> {
+        seqs.map(|seq| Payload::Op {
+            key: format!("k{}", seq % 7),
+            seq,
+        })
+    }
+
+    #[test]
+    fn keys_are_handled_in_order_by_their_shard() {
+        let payloads: Vec<_> = ops(0..200).collect();
+        let log = run(&payloads);
+
+        let mut last_seq: std::collections::HashMap<String, usize> = Default::default();
+        for (shard, payload) in log {
+            let Payload::Op { key, seq } = payload else {
+                unreachable!("only ops were sent");
+            };
+            assert_eq!(
+                shard,
+                shard_of(&key, SHARDS),
+                "{key} handled by shard {shard}"
+            );
+            if let Some(last) = last_seq.insert(key.clone(), seq) {
+                assert!(last < seq, "{key}: {seq} handled after {last}");
+            }
+        }
+    }
+
+    #[test]
+    fn barriers_are_handled_alone_by_shard_0() {
+        let mut payloads: Vec<_> = ops(0..50).collect();
+        payloads.push(Payload::Poll { seq: 50 });
+        payloads.extend(ops(51..100));
+        payloads.push(Payload::Poll { seq: 100 });
+        payloads.extend(ops(101..150));
+        let log = run(&payloads);
+
+        for (i, (shard, payload)) in log.iter().enumerate() {
+            let Payload::Poll { seq } = payload else {
+                continue;
+            };
+            assert_eq!(*shard, 0);
+            // everything received before the barrier was handled before it, nothing after it
+            let before: Vec<usize> = log[..i]
+                .iter()
+                .map(|(_, p)| match p {
+                    Payload::Op { seq, .. } | Payload::Poll { seq } => *seq,
+                })
+                .collect();
+            assert_eq!(before.len(), *seq);
+            assert!(
+                before.iter().all(|s| s < seq),
+                "{before:?} before barrier {seq}"
+            );
+        }
+    }
+
+    #[test]
+    fn shard_of_key_is_stable() {
+        // test vectors of the FNV-1a hash, they must not change between builds
+        assert_eq!(ShardKey::of(""), ShardKey::Key(0xcbf29ce484222325));
+        assert_eq!(ShardKey::of("a"), ShardKey::Key(0xaf63dc4c8601ec8c));
+        assert_eq!(ShardKey::of("foobar"), ShardKey::Key(0x85944171f73967e8));
+        assert_eq!(shard_of("k1", 4), 1);
+        assert_eq!(shard_of("k2", 4), 0);
+        assert_eq!(shard_of("anything", 1), 0);
+    }
+}
@@ -0,0 +1,458 @@
+//! Client workloads of `gossipy-run`
+//!
+//! A workload generates the client operations of one of the challenges (requests sent to the nodes)
+//! and turns their replies into the values recorded in the [history](crate::history).
+//! Requests and replies are the Maelstrom workload messages, so the nodes can not tell
+//! whether they run under Maelstrom or under `gossipy-run`.
+
+use std::{collections::HashMap, str::FromStr};
+
+use anyhow::bail;
+use serde_json::{json, Map, Value};
+
+use crate::rng::Rng;
+
+/// Operation to be sent to a node
+#[derive(Debug, Clone)]
+pub struct Invocation {
+    /// Function recorded in the history
+    pub f: &'static str,
+    /// Argument recorded in the history
+    pub value: Value,
+    /// Message body sent to the node (without `msg_id`)
+    pub request: Value,
+}
+
+impl Invocation {
+    fn new(f: &'static str, value: Value, request: Value) -> Self {
+        Self { f, value, request }
+    }
+}
+
+/// Generator of client operations
+pub trait Workload: Send {
+    /// Messages sent to every node after `init`, before any operation (eg. topology),
+    /// returns `(node index, message body)`
+    fn setup(&mut self, _node_ids: &[String]) -> Vec<(usize, Value)> {
+        Vec::new()
+    }
+
+    /// Returns next operation of the client process
+    fn invoke(&mut self, process: usize, rng: &mut Rng) -> Invocation;
+
+    /// Returns value of the successfully completed operation from the reply body
+    fn complete(&mut self, process: usize, invocation: &Invocation, reply: &Value) -> Value;
+
+    /// Operations sent to every node at the end of the test, when the cluster has quiesced
+    fn finish(&mut self) -> Vec<Invocation> {
+        Vec::new()
+    }
+}
+
+/// Options of the workloads
+#[derive(Debug, Clone)]
+pub struct WorkloadOptions {
+    /// Broadcast topology
+    pub topology: Topology,
+    /// Number of keys of kafka, txn-rw-register and lin-kv workloads
+    pub key_count: usize,
+    /// Maximum number of micro-operations in a transaction
+    pub max_txn_length: usize,
+}
+
+impl Default for WorkloadOptions {
+    fn default() -> Self {
+        Self {
+            topology: Topology::Grid,
+            key_count: 4,
+            max_txn_length: 4,
+        }
+    }
+}
+
+/// Names of the supported workloads
+pub const WORKLOADS: &[&str] = &[
+    "echo",
+    "unique-ids",
+    "broadcast",
+    "g-counter",
+    "kafka",
+    "txn-rw-register",
+    "lin-kv",
+];
+
+/// Returns workload with the name
+pub fn workload(name: &str, options: &WorkloadOptions) -> anyhow::Result<Box<dyn Workload>> {
+    Ok(match name {
+        "echo" => Box::new(Echo::default()),
+        "unique-ids" => Box::new(UniqueIds),
+        "broadcast" => Box::new(Broadcast {
+            topology: options.topology,
+            next: 0,
+        }),
+        "g-counter" => Box::new(GCounter),
+        "kafka" => Box::new(Kafka {
+            key_count: options.key_count,
+            next: 0,
+            positions: HashMap::new(),
+        }),
+        "txn-rw-register" => Box::new(TxnRwRegister {
+            key_count: options.key_count,
+            max_txn_length: options.max_txn_length,
+            next: HashMap::new(),
+        }),
+        "lin-kv" => Box::new(LinKv {
+            key_count: options.key_count,
+        }),
+        _ => bail!(
+            "unknown workload {name}, expected one of: {}",
+            WORKLOADS.join(", ")
+        ),
+    })
+}
+
+/// Broadcast topology
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Topology {
+    /// Nodes in a square grid connected to their horizontal and vertical neighbours
+    Grid,
+    /// Every node connected to its predecessor and successor
+    Line,
+    /// Every node connected to every other node
+    Total,
+}
+
+impl FromStr for Topology {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "grid" => Ok(Topology::Grid),
+            "line" => Ok(Topology::Line),
+            "total" => Ok(Topology::Total),
+            _ => bail!("unknown topology {s}, expected grid, line or total"),
+        }
+    }
+}
+
+impl Topology {
+    /// Returns neighbours of every node
+    pub fn neighbours(&self, node_ids: &[String]) -> HashMap<String, Vec<String>> {
+        let n = node_ids.len();
+        let width = (1..=n).find(|w| w * w >= n).unwrap_or(1);
+        let neighbours = |i: usize| -> Vec<usize> {
+            match self {
+                Topology::Total => (0..n).filter(|j| *j != i).collect(),
+                Topology::Line => [i.checked_sub(1), Some(i + 1).filter(|j| *j < n)]
+                    .into_iter()
+                    .flatten()
+                    .collect(),
+                Topology::Grid => {
+                    let (row, col) = (i / width, i % width);
+                    let mut neighbours = Vec::new();
+                    if row > 0 {
+                        neighbours.push(i - width);
+                    }
+                    if col > 0 {
+                        neighbours.push(i - 1);
+                    }
+                    if col + 1 < width && i + 1 < n {
+                        neighbours.push(i + 1);
+                    }
+                    if i + width < n {
+                        neighbours.push(i + width);
+                    }
+                    neighbours
+                }
+            }
+        };
+
+        node_ids
+            .iter()
+            .enumerate()
+            .map(|(i, id)| {
+                let ids = neighbours(i)
+                    .into_iter()
+                    .map(|j| node_ids[j].clone())
+                    .collect();
+                (id.clone(), ids)
+            })
+            .collect()
+    }
+}
+
+#[derive(Default)]
+struct Echo {
+    next: usize,
+}
+
+impl Workload for Echo {
+    fn invoke(&mut self, _process: usize, _rng: &mut Rng) -> Invocation {
+        self.next += 1;
+        let echo = format!("Please echo {}", self.next);
+        Invocation::new("echo", json!(echo), json!({"type": "echo", "echo": echo}))
+    }
+
+    fn complete(&mut self, _process: usize, _invocation: &Invocation, reply: &Value) -> Value {
+        reply["echo"].clone()
+    }
+}
+
+struct UniqueIds;
+
+impl Workload for UniqueIds {
+    fn invoke(&mut self, _process: usize, _rng: &mut Rng) -> Invocation {
+        Invocation::new("generate", Value::Null, json!({"type": "generate"}))
+    }
+
+    fn complete(&mut self, _process: usize, _invocation: &Invocation, reply: &Value) -> Value {
+        reply["id"].clone()
+    }
+}
+
+struct Broadcast {
+    topology: Topology,
+    /// Next broadcast value, every value is broadcast once
+    next: u64,
+}
+
+impl Broadcast {
+    fn read() -> Invocation {
+        Invocation::new("read", Value::Null, json!({"type": "read"}))
+    }
+}
+
+impl Workload for Broadcast {
+    fn setup(&mut self, node_ids: &[String]) -> Vec<(usize, Value)> {
+        let topology = self.topology.neighbours(node_ids);
+        (0..node_ids.len())
+            .map(|i| (i, json!({"type": "topology", "topology": topology})))
+            .collect()
+    }
+
+    fn invoke(&mut self, _process: usize, rng: &mut Rng) -> Invocation {
+        if rng.chance(0.5) {
+            return Self::read();
+        }
+        let message = self.next;
+        self.next += 1;
+        Invocation::new(
+            "broadcast",
+            json!(message),
+            json!({"type": "broadcast", "message": message}),
+        )
+    }
+
+    fn complete(&mut self, _process: usize, invocation: &Invocation, reply: &Value) -> Value {
+        match invocation.f {
+            "read" => reply["messages"].clone(),
+            _ => invocation.value.clone(),
+        }
+    }
+
+    fn finish(&mut self) -> Vec<Invocation> {
+        vec![Self::read()]
+    }
+}
+
+struct GCounter;
+
+impl GCounter {
+    fn read() -> Invocation {
+        Invocation::new("read", Value::Null, json!({"type": "read"}))
+    }
+}
+
+impl Workload for GCounter {
+    fn invoke(&mut self, _process: usize, rng: &mut Rng) -> Invocation {
+        if rng.chance(0.25) {
+            return Self::read();
+        }
+        let delta = rng.between(1, 5);
+        Invocation::new("add", json!(delta), json!({"type": "add", "delta": delta}))
+    }
+
+    fn complete(&mut self, _process: usize, invocation: &Invocation, reply: &Value) -> Value {
+        match invocation.f {
+            "read" => reply["value"].clone(),
+            _ => invocation.value.clone(),
+        }
+    }
+
+    fn finish(&mut self) -> Vec<Invocation> {
+        vec![Self::read()]
+    }
+}
+
+struct Kafka {
+    key_count: usize,
+    /// Next sent message, every message is sent once
+    next: u64,
+    /// process => key => next offset to poll
+    positions: HashMap<usize, HashMap<String, u64>>,
+}
+
+impl Kafka {
+    fn keys(key_count: usize) -> impl Iterator<Item = String> {
+        (0..key_count).map(|k| format!("k{k}"))
+    }
+
+    fn poll(offsets: Map<String, Value>) -> Invocation {
+        let offsets = Value::Object(offsets);
+        Invocation::new(
+            "poll",
+            json!({"offsets": offsets}),
+            json!({"type": "poll", "offsets": offsets}),
+        )
+    }
+}
+
+impl Workload for Kafka {
+    fn invoke(&mut self, process: usize, rng: &mut Rng) -> Invocation {
+        let positions = self.positions.entry(process).or_default();
+        match rng.below(10) {
+            0..=4 => {
+                let key = format!("k{}", rng.below(self.key_count as u64));
+                let msg = self.next;
+                self.next += 1;
+                Invocation::new(
+                    "send",
+                    json!({"key": key, "msg": msg}),
+                    json!({"type": "send", "key": key, "msg": msg}),
+                )
+            }
+            8 if !positions.is_empty() => {
+                // commit everything we have polled
+                let offsets: Map<String, Value> = positions
+                    .iter()
+                    .map(|(key, next)| (key.clone(), json!(next - 1)))
+                    .collect();
+                Invocation::new(
+                    "commit_offsets",
+                    json!({"offsets": offsets}),
+                    json!({"type": "commit_offsets", "offsets": offsets}),
+                )
+            }
+            9 => {
+                let keys: Vec<String> = Self::keys(self.key_count).collect();
+                Invocation::new(
+                    "list_committed_offsets",
+                    json!({"keys": keys}),
+                    json!({"type": "list_committed_offsets", "keys": keys}),
+                )
+            }
+            _ => {
+                let offsets = Self::keys(self.key_count)
+                    .map(|key| {
+                        let offset = positions.get(&key).copied().unwrap_or(0);
+                        (key, json!(offset))
+                    })
+                    .collect();
+                Self::poll(offsets)
+            }
+        }
+    }
+
+    fn complete(&mut self, process: usize, invocation: &Invocation, reply: &Value) -> Value {
+        match invocation.f {
+            "send" => {
+                let mut value = invocation.value.clone();
+                value["offset"] = reply["offset"].clone();
+                value
+            }
+            "poll" => {
+                let positions = self.positions.entry(process).or_default();
+                for (key, msgs) in reply["msgs"].as_object().into_iter().flatten() {
+                    let last = msgs
+                        .as_array()
+                        .and_then(|msgs| msgs.last())
+                        .and_then(|msg| msg[0].as_u64());
+                    if let Some(last) = last {
+                        let position = positions.entry(key.clone()).or_default();
+                        *position = (*position).max(last + 1);
+                    }
+                }
+                json!({"offsets": invocation.value["offsets"], "msgs": reply["msgs"]})
+            }
+            "list_committed_offsets" => json!({"offsets": reply["offsets"]}),
+            _ => invocation.value.clone(),
+        }
+    }
+
+    fn finish(&mut self) -> Vec<Invocation> {
+        let offsets = Self::keys(self.key_count)
+            .map(|key| (key, json!(0)))
+            .collect();
+        vec![Self::poll(offsets)]
+    }
+}
+
+struct TxnRwRegister {
+    key_count: usize,
+    max_txn_length: usize,
+    /// key => next written value, every value is written to the key once
+    next: HashMap<u64, u64>,
+}
+
+impl Workload for TxnRwRegister {
+    fn invoke(&mut self, _process: usize, rng: &mut Rng) -> Invocation {
+        let length = rng.between(1, self.max_txn_length as u64);
+        let txn: Vec<Value> = (0..length)
+            .map(|_| {
+                let key = rng.below(self.key_count as u64);
+                if rng.chance(0.5) {
+                    json!(["r", key, null])
+                } else {
+                    let value = self.next.entry(key).or_insert(1);
+                    *value += 1;
+                    json!(["w", key, *value - 1])
+                }
+            })
+            .collect();
+        Invocation::new("txn", json!(txn), json!({"type": "txn", "txn": txn}))
+    }
+
+    fn complete(&mut self, _process: usize, _invocation: &Invocation, reply: &Value) -> Value {
+        reply["txn"].clone()
+    }
+}
+
+struct LinKv {
+    key_count: usize,
+}
+
+impl Workload for LinKv {
+    fn invoke(&mut self, _process: usize, rng: &mut Rng) -> Invocation {
+        let key = rng.below(self.key_count as u64);
+        match rng.below(3) {
+            0 => Invocation::new(
+                "read",
+                json!([key, null]),
+                json!({"type": "read", "key": key}),
+            ),
+            1 => {
+                let value = rng.below(5);
+                Invocation::new(
+                    "write",
+                    json!([key, value]),
+                    json!({"type": "write", "key": key, "value": value}),
+                )
+            }
+            _ => {
+                let (from, to) = (rng.below(5), rng.below(5));
+                Invocation::new(
+                    "cas",
+                    json!([key, [from, to]]),
+                    json!({"type": "cas", "key": key, "from": from, "to": to}),
+                )
+            }
+        }
+    }
+
+    fn complete(&mut self, _process: usize, invocation: &Invocation, reply: &Value) -> Value {
+        match invocation.f {
+            "read" => json!([invocation.value[0], reply["value"]]),
+            _ => invocation.value.clone(),
+        }
+    }
+}
@@ -0,0 +1,719 @@
+//! Simulation of broadcast clusters
+//!
+//! Runs [`BroadcastHandler`]s of the `broadcast` binary in one process, delivers their messages without latency
+//! (in random order) and drives gossip rounds explicitly. Proptest generates random topologies, broadcasts and
+//! partitions that eventually heal; every node must converge to all broadcast values and the gossip must not send
+//! values its peers are known to have. In eager mode the values must reach every node without any gossip round,
+//! Plumtree must push them only along its spanning tree unless messages get lost.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    ops::Range,
+    time::Duration,
+};
+
+use gossipy::broadcast::{AntiEntropy, BroadcastHandler, Command, Mode};
+use gossipy::crdt::RangeSet;
+use gossipy::failure::DetectorConfig;
+use gossipy::pipeline::PipelineConfig;
+use gossipy::{rng::Rng, workload::Topology, Handler, Node};
+use proptest::{prelude::*, sample::Index};
+use serde_json::{json, Value};
+
+/// Maximum number of gossip rounds after the last broadcast and partition until the cluster converges
+const CONVERGENCE_ROUNDS: usize = 20;
+
+#[derive(Debug, Clone)]
+struct Scenario {
+    mode: Mode,
+    anti_entropy: AntiEntropy,
+    node_count: usize,
+    /// Links between nodes, `(a, b)` with `a < b`
+    links: BTreeSet<(usize, usize)>,
+    /// Broadcasts of the value by a client of the node, in every round
+    broadcasts: Vec<Vec<(usize, isize)>>,
+    partitions: Vec<Partition>,
+    /// Seed of the delivery order
+    seed: u64,
+}
+
+impl Scenario {
+    /// Returns all broadcast values
+    fn values(&self) -> BTreeSet<isize> {
+        self.broadcasts.iter().flatten().map(|(_, v)| *v).collect()
+    }
+}
+
+/// Nodes on different sides of the partition cannot talk to each other during the rounds
+#[derive(Debug, Clone)]
+struct Partition {
+    rounds: Range<usize>,
+    side: Vec<bool>,
+}
+
+#[derive(Debug, Default)]
+struct Stats {
+    /// Messages between nodes, including acknowledgements and messages lost in partitions
+    messages: usize,
+    /// Messages between nodes if the messages one node sends to another at once were batched
+    batches: usize,
+    /// Values carried by gossip messages
+    values_sent: usize,
+    /// Bytes of the value ranges in gossip messages
+    have_bytes: usize,
+    /// Bytes the values in gossip messages would take as a plain list
+    list_bytes: usize,
+    /// Graft and prune messages
+    repairs: usize,
+    /// Sync messages with digests
+    syncs: usize,
+}
+
+struct Cluster {
+    nodes: Vec<(Node<Command>, BroadcastHandler)>,
+    /// Side of every node if the network is partitioned
+    partition: Option<Vec<bool>>,
+    /// Links that lose all messages, `(a, b)` with `a < b`
+    cut: BTreeSet<(usize, usize)>,
+    stats: Stats,
+    /// Messages sent to clients
+    replies: Vec<Value>,
+    msg_id: u64,
+    rng: Rng,
+}
+
+impl Cluster {
+    fn new(
+        mode: Mode,
+        anti_entropy: AntiEntropy,
+        node_count: usize,
+        links: &BTreeSet<(usize, usize)>,
+        seed: u64,
+    ) -> Self {
+        let node_ids: Vec<String> = (0..node_count).map(|i| format!("n{i}")).collect();
+        let nodes = node_ids
+            .iter()
+            .map(|id| {
+                let mut node = Node::with_ids(id, &node_ids).expect("creating node");
+                node.capture_output();
+                // gossip rounds are driven by the test
+                let handler = BroadcastHandler::new(mode, anti_entropy, Duration::ZERO);
+                (node, handler)
+            })
+            .collect();
+
+        let mut cluster = Self {
+            nodes,
+            partition: None,
+            cut: BTreeSet::new(),
+            stats: Stats::default(),
+            replies: Vec::new(),
+            msg_id: 0,
+            rng: Rng::seeded(seed),
+        };
+
+        let mut topology: HashMap<&str, Vec<&str>> = node_ids
+            .iter()
+            .map(|id| (id.as_str(), Vec::new()))
+            .collect();
+        for &(a, b) in links {
+            topology.entry(&node_ids[a]).or_default().push(&node_ids[b]);
+            topology.entry(&node_ids[b]).or_default().push(&node_ids[a]);
+        }
+        for node in 0..node_count {
+            cluster.request(node, json!({"type": "topology", "topology": topology}));
+        }
+        cluster
+    }
+
+    /// Sends client request to the node and delivers all messages it causes
+    fn request(&mut self, node: usize, body: Value) {
+        self.send(node, body);
+        self.deliver();
+    }
+
+    /// Sends client request to the node, the messages it causes wait for the next delivery
+    fn send(&mut self, node: usize, mut body: Value) {
+        self.msg_id += 1;
+        body["msg_id"] = self.msg_id.into();
+        let msg = json!({"src": "c1", "dest": format!("n{node}"), "body": body});
+        self.handle(node, &msg.to_string());
+    }
+
+    fn handle(&mut self, node: usize, line: &str) {
+        let (node, handler) = &mut self.nodes[node];
+        node.handle_line(handler, line).expect("handling message");
+    }
+
+    /// Delivers messages until no node has anything to send
+    fn deliver(&mut self) {
+        while self.step() {}
+    }
+
+    /// Delivers messages the nodes have sent so far, messages crossing the partition are lost.
+    /// Returns `false` if there was nothing to deliver.
+    fn step(&mut self) -> bool {
+        let mut lines: Vec<String> = self
+            .nodes
+            .iter()
+            .flat_map(|(node, _)| node.take_output())
+            .collect();
+        if lines.is_empty() {
+            return false;
+        }
+        self.rng.shuffle(&mut lines);
+
+        let batches: BTreeSet<(usize, usize)> = lines
+            .iter()
+            .filter_map(|line| {
+                let msg: Value = serde_json::from_str(line).expect("valid message");
+                Some((node_index(&msg["src"])?, node_index(&msg["dest"])?))
+            })
+            .collect();
+        self.stats.batches += batches.len();
+
+        for line in lines {
+            let msg: Value = serde_json::from_str(&line).expect("valid message");
+            let (Some(src), Some(dst)) = (node_index(&msg["src"]), node_index(&msg["dest"])) else {
+                self.replies.push(msg);
+                continue;
+            };
+
+            self.stats.messages += 1;
+            match msg["body"]["type"].as_str() {
+                Some("gossip") => {
+                    let have: RangeSet<isize> =
+                        serde_json::from_value(msg["body"]["have"].clone()).expect("ranges");
+                    let list: Vec<isize> = have.iter().collect();
+                    self.stats.values_sent += list.len();
+                    self.stats.have_bytes += msg["body"]["have"].to_string().len();
+                    self.stats.list_bytes += json!(list).to_string().len();
+                }
+                Some("graft" | "prune") => self.stats.repairs += 1,
+                Some("sync") => self.stats.syncs += 1,
+                _ => {}
+            }
+            if let Some(side) = &self.partition {
+                if side[src] != side[dst] {
+                    continue;
+                }
+            }
+            if self.cut.contains(&(src.min(dst), src.max(dst))) {
+                continue;
+            }
+            self.handle(dst, &line);
+        }
+        true
+    }
+
+    /// Runs one gossip round of all nodes
+    fn gossip(&mut self) {
+        self.start_gossip();
+        self.deliver();
+    }
+
+    /// Starts gossip round of all nodes, the messages wait for the next delivery
+    fn start_gossip(&mut self) {
+        for (node, handler) in &mut self.nodes {
+            handler
+                .handle_command(Command::SendGossip, node.clone())
+                .expect("gossiping");
+        }
+    }
+
+    /// Returns `true` if every node knows all the values
+    fn converged(&self, values: &BTreeSet<isize>) -> bool {
+        self.nodes
+            .iter()
+            .all(|(_, handler)| handler.messages().iter().collect::<BTreeSet<_>>() == *values)
+    }
+
+    /// Makes nodes on both ends of the link suspect each other and delivers the messages it causes
+    fn suspect(&mut self, (a, b): (usize, usize)) {
+        for (node, peer) in [(a, b), (b, a)] {
+            let (node, handler) = &mut self.nodes[node];
+            handler
+                .set_suspected(&mut node.clone(), &format!("n{peer}"), true)
+                .expect("suspecting");
+        }
+        self.deliver();
+    }
+
+    /// Returns the values read from every node
+    fn read_all(&mut self) -> Vec<BTreeSet<isize>> {
+        (0..self.nodes.len())
+            .map(|node| {
+                self.replies.clear();
+                self.request(node, json!({"type": "read"}));
+                let reply = self.replies.pop().expect("read_ok");
+                serde_json::from_value(reply["body"]["messages"].clone()).expect("read values")
+            })
+            .collect()
+    }
+}
+
+/// Returns index of the node, `None` for clients
+fn node_index(id: &Value) -> Option<usize> {
+    id.as_str()?.strip_prefix('n')?.parse().ok()
+}
+
+/// Runs the scenario until all nodes know all broadcast values, returns `None` if they do not converge
+fn run(scenario: &Scenario) -> Option<Cluster> {
+    let mut cluster = Cluster::new(
+        scenario.mode,
+        scenario.anti_entropy,
+        scenario.node_count,
+        &scenario.links,
+        scenario.seed,
+    );
+    let values = scenario.values();
+
+    let healed = scenario
+        .partitions
+        .iter()
+        .map(|p| p.rounds.end)
+        .max()
+        .unwrap_or(0);
+    for round in 0..scenario.broadcasts.len().max(healed) {
+        cluster.partition = scenario
+            .partitions
+            .iter()
+            .find(|p| p.rounds.contains(&round))
+            .map(|p| p.side.clone());
+        for &(node, value) in scenario.broadcasts.get(round).into_iter().flatten() {
+            cluster.request(node, json!({"type": "broadcast", "message": value}));
+        }
+        cluster.gossip();
+    }
+
+    cluster.partition = None;
+    for _ in 0..CONVERGENCE_ROUNDS {
+        if cluster.converged(&values) {
+            return Some(cluster);
+        }
+        cluster.gossip();
+    }
+    cluster.converged(&values).then_some(cluster)
+}
+
+fn scenario() -> impl Strategy<Value = Scenario> {
+    (
+        prop_oneof![Just(Mode::Gossip), Just(Mode::Eager), Just(Mode::Plumtree)],
+        prop_oneof![Just(AntiEntropy::Deltas), Just(AntiEntropy::Digests)],
+        2usize..=8,
+    )
+        .prop_flat_map(|(mode, anti_entropy, n)| {
+            (
+                Just((mode, anti_entropy)),
+                Just(n),
+                // parents of a random spanning tree, so the topology is connected
+                prop::collection::vec(any::<Index>(), n - 1),
+                prop::collection::vec((0..n, 0..n), 0..n),
+                prop::collection::vec(prop::collection::vec((0..n, -1000isize..1000), 0..4), 1..20),
+                prop::collection::vec(
+                    (
+                        0usize..20,
+                        1usize..10,
+                        prop::collection::vec(any::<bool>(), n),
+                    ),
+                    0..3,
+                ),
+                any::<u64>(),
+            )
+        })
+        .prop_map(
+            |((mode, anti_entropy), n, parents, extra, broadcasts, partitions, seed)| {
+                let mut links: BTreeSet<(usize, usize)> = parents
+                    .iter()
+                    .enumerate()
+                    .map(|(i, parent)| (parent.index(i + 1), i + 1))
+                    .collect();
+                links.extend(
+                    extra
+                        .into_iter()
+                        .filter(|(a, b)| a != b)
+                        .map(|(a, b)| (a.min(b), a.max(b))),
+                );
+                let partitions = partitions
+                    .into_iter()
+                    .map(|(start, len, side)| Partition {
+                        rounds: start..start + len,
+                        side,
+                    })
+                    .collect();
+                Scenario {
+                    mode,
+                    anti_entropy,
+                    node_count: n,
+                    links,
+                    broadcasts,
+                    partitions,
+                    seed,
+                }
+            },
+        )
+}
+
+proptest! {
+    #[test]
+    fn every_node_reads_all_broadcast_values(scenario in scenario()) {
+        let values = scenario.values();
+        let Some(mut cluster) = run(&scenario) else {
+            return Err(TestCaseError::fail("cluster did not converge"));
+        };
+        for read in cluster.read_all() {
+            prop_assert_eq!(&read, &values);
+        }
+
+        // values learned from third parties are sent once more, then gossip stops as every peer acknowledged
+        // everything, digests are still exchanged but match
+        cluster.gossip();
+        let messages = cluster.stats.messages - cluster.stats.syncs;
+        cluster.gossip();
+        prop_assert_eq!(cluster.stats.messages - cluster.stats.syncs, messages);
+    }
+
+    #[test]
+    fn values_are_not_gossiped_to_peers_that_know_them(mut scenario in scenario()) {
+        scenario.partitions.clear();
+        let values = scenario.values();
+        let cluster = run(&scenario).expect("cluster converges without partitions");
+
+        // without message loss every value crosses every link at most once in each direction
+        let bound = values.len() * scenario.links.len() * 2;
+        prop_assert!(
+            cluster.stats.values_sent <= bound,
+            "{} values sent, expected at most {bound}",
+            cluster.stats.values_sent
+        );
+    }
+
+    #[test]
+    fn plumtree_pushes_only_along_the_tree(mut scenario in scenario()) {
+        scenario.mode = Mode::Plumtree;
+        scenario.partitions.clear();
+        let cluster = run(&scenario).expect("cluster converges without partitions");
+
+        // without message loss the tree needs no repairs and every broadcast crosses each of its links once
+        prop_assert_eq!(cluster.stats.repairs, 0);
+        let broadcasts = scenario.broadcasts.iter().flatten().count();
+        let bound = broadcasts * (scenario.node_count - 1);
+        prop_assert!(
+            cluster.stats.values_sent <= bound,
+            "{} values sent, expected at most {bound}",
+            cluster.stats.values_sent
+        );
+    }
+
+    #[test]
+    fn eager_push_reaches_every_node_without_gossip_rounds(scenario in scenario()) {
+        let mut cluster = Cluster::new(
+            Mode::Eager,
+            scenario.anti_entropy,
+            scenario.node_count,
+            &scenario.links,
+            scenario.seed,
+        );
+        let mut values = BTreeSet::new();
+        for &(node, value) in scenario.broadcasts.iter().flatten() {
+            cluster.request(node, json!({"type": "broadcast", "message": value}));
+            values.insert(value);
+            prop_assert!(cluster.converged(&values), "{value} did not reach every node");
+        }
+    }
+}
+
+#[test]
+fn grid_messages_per_operation() {
+    // without batching every push and every acknowledgement is a message
+    for (mode, max) in [
+        (Mode::Gossip, 20.0),
+        (Mode::Eager, 125.0),
+        (Mode::Plumtree, 60.0),
+    ] {
+        let stats = grid(mode);
+        let msgs_per_op = stats.messages as f64 / GRID_BROADCASTS as f64;
+        println!(
+            "{mode:?} on grid of 25 nodes: {msgs_per_op:.1} messages per broadcast, \
+             gossiped values take {} bytes as ranges, {} bytes as a list",
+            stats.have_bytes, stats.list_bytes
+        );
+        assert!(
+            msgs_per_op <= max,
+            "{mode:?}: {msgs_per_op:.1} messages per broadcast"
+        );
+        assert!(stats.have_bytes <= stats.list_bytes);
+    }
+}
+
+#[test]
+fn grid_messages_per_operation_with_batching() {
+    // like the net msgs-per-op of Maelstrom, gossipy-run with GOSSIPY_BATCH_WINDOW_MS=100 measured 6.5 for plumtree
+    for mode in [Mode::Gossip, Mode::Eager, Mode::Plumtree] {
+        let (stats, ops) = grid_batched(mode);
+        let msgs_per_op = stats.batches as f64 / ops as f64;
+        println!(
+            "{mode:?} on grid of 25 nodes with batching: {msgs_per_op:.1} messages per operation"
+        );
+        assert!(
+            msgs_per_op < 10.0,
+            "{mode:?}: {msgs_per_op:.1} messages per operation"
+        );
+    }
+}
+
+#[test]
+fn dense_values_are_gossiped_as_ranges() {
+    // the isolated node gets everything broadcast during the partition once it heals
+    let scenario = Scenario {
+        mode: Mode::Gossip,
+        anti_entropy: AntiEntropy::Deltas,
+        node_count: 2,
+        links: BTreeSet::from([(0, 1)]),
+        broadcasts: (0..10)
+            .map(|round| (0..50).map(|i| (0, round * 50 + i)).collect())
+            .collect(),
+        partitions: vec![Partition {
+            rounds: 0..10,
+            side: vec![false, true],
+        }],
+        seed: 1,
+    };
+
+    let stats = run(&scenario).expect("converges").stats;
+    assert!(
+        stats.have_bytes * 20 < stats.list_bytes,
+        "{} bytes as ranges, {} bytes as a list",
+        stats.have_bytes,
+        stats.list_bytes
+    );
+}
+
+#[test]
+fn plumtree_routes_around_suspected_neighbours() {
+    for center in 0..GRID_NODES {
+        let mut cluster = Cluster::new(
+            Mode::Plumtree,
+            AntiEntropy::Deltas,
+            GRID_NODES,
+            &grid_links(),
+            1,
+        );
+        // cut the first link of the tree of every node
+        let peers = cluster.nodes[center].1.neighbours().to_vec();
+        let Some(peer) = peers.first() else {
+            continue;
+        };
+        let link = {
+            let peer = node_index(&json!(peer)).expect("node");
+            (center.min(peer), center.max(peer))
+        };
+        cluster.cut.insert(link);
+        cluster.suspect(link);
+
+        let mut values = BTreeSet::new();
+        for value in 0..GRID_NODES {
+            cluster.request(value, json!({"type": "broadcast", "message": value}));
+            values.insert(value as isize);
+        }
+        // ends of the link with no other neighbours cannot route around it
+        let (a, b) = link;
+        if cluster.nodes[a].1.lazy().is_empty() && cluster.nodes[b].1.lazy().is_empty() {
+            continue;
+        }
+        assert!(
+            cluster.converged(&values),
+            "values did not route around {link:?}"
+        );
+    }
+}
+
+#[test]
+fn gossip_to_suspected_neighbours_backs_off() {
+    for anti_entropy in [AntiEntropy::Deltas, AntiEntropy::Digests] {
+        let links = BTreeSet::from([(0, 1)]);
+        let mut cluster = Cluster::new(Mode::Eager, anti_entropy, 2, &links, 1);
+        cluster.cut.insert((0, 1));
+        cluster.suspect((0, 1));
+        cluster.request(0, json!({"type": "broadcast", "message": 1}));
+        // no push to the suspected neighbour
+        assert_eq!(cluster.stats.messages, 0);
+
+        for _ in 0..20 {
+            cluster.gossip();
+        }
+        // gossip or sync in rounds 1, 3, 7 and 15 by both nodes
+        let expected = match anti_entropy {
+            AntiEntropy::Deltas => 4,
+            AntiEntropy::Digests => 8,
+        };
+        assert_eq!(cluster.stats.messages, expected, "{anti_entropy:?}");
+
+        // trusted neighbour gets gossip in the next round
+        cluster.cut.clear();
+        for (node, peer) in [(0, "n1"), (1, "n0")] {
+            let (node, handler) = &mut cluster.nodes[node];
+            handler
+                .set_suspected(&mut node.clone(), peer, false)
+                .expect("trusting");
+        }
+        cluster.gossip();
+        assert!(cluster.converged(&BTreeSet::from([1])), "{anti_entropy:?}");
+    }
+}
+
+#[test]
+fn acknowledgements_trust_suspected_neighbours_again() {
+    let links = BTreeSet::from([(0, 1)]);
+    let mut cluster = Cluster::new(Mode::Eager, AntiEntropy::Deltas, 2, &links, 1);
+    for (_, handler) in &mut cluster.nodes {
+        handler.set_detector(DetectorConfig::new(Duration::from_secs(1)));
+    }
+    cluster.suspect((0, 1));
+
+    // the gossip is acknowledged before any heartbeat
+    cluster.request(0, json!({"type": "broadcast", "message": 1}));
+    cluster.gossip();
+    let (_, handler) = &cluster.nodes[0];
+    let state = handler.debug_state().expect("debug state");
+    assert_eq!(state["suspected"], json!([]), "{state}");
+}
+
+#[test]
+fn nodes_count_all_messages_to_other_nodes() {
+    for anti_entropy in [AntiEntropy::Deltas, AntiEntropy::Digests] {
+        let mut cluster = Cluster::new(Mode::Plumtree, anti_entropy, GRID_NODES, &grid_links(), 1);
+        for value in 0..GRID_NODES {
+            cluster.request(value, json!({"type": "broadcast", "message": value}));
+            cluster.gossip();
+        }
+        cluster.read_all();
+
+        // the pacer budgets syncs, grafts, prunes and acknowledgements too, replies to clients are not included
+        let counted: u64 = cluster
+            .nodes
+            .iter()
+            .map(|(node, _)| node.metrics().messages_sent_to_nodes)
+            .sum();
+        assert!(cluster.stats.messages > 0);
+        assert_eq!(counted, cluster.stats.messages as u64, "{anti_entropy:?}");
+    }
+}
+
+#[test]
+fn digest_rounds_flow_with_one_request_in_flight() {
+    // like GOSSIPY_MAX_IN_FLIGHT=1, syncs are not answered so they must not hold the only slot
+    let mut cluster = Cluster::new(
+        Mode::Gossip,
+        AntiEntropy::Digests,
+        GRID_NODES,
+        &grid_links(),
+        1,
+    );
+    for (node, _) in &mut cluster.nodes {
+        node.set_pipeline_config(Some(PipelineConfig::new(1)));
+    }
+
+    let mut values = BTreeSet::new();
+    for value in 0..GRID_NODES {
+        cluster.request(value, json!({"type": "broadcast", "message": value}));
+        values.insert(value as isize);
+        cluster.gossip();
+    }
+    for _ in 0..10 {
+        cluster.gossip();
+    }
+    assert!(cluster.converged(&values), "values did not gossip");
+    for (node, _) in &cluster.nodes {
+        let rpcs = node.in_flight_rpcs();
+        assert!(rpcs.is_empty(), "{}: {rpcs:?}", node.id());
+    }
+}
+
+/// Number of values broadcast by [`grid`]
+const GRID_BROADCASTS: usize = 200;
+
+/// Number of nodes in the grid
+const GRID_NODES: usize = 25;
+
+/// Returns links of a 5x5 grid
+fn grid_links() -> BTreeSet<(usize, usize)> {
+    let node_ids: Vec<String> = (0..GRID_NODES).map(|i| format!("n{i}")).collect();
+    Topology::Grid
+        .neighbours(&node_ids)
+        .iter()
+        .flat_map(|(id, neighbours)| {
+            let a = node_index(&json!(id)).expect("node");
+            neighbours
+                .iter()
+                .map(move |b| (a, node_index(&json!(b)).expect("node")))
+        })
+        .filter(|(a, b)| a < b)
+        .collect()
+}
+
+/// Broadcasts values on a 5x5 grid, returns statistics of messages between nodes
+fn grid(mode: Mode) -> Stats {
+    // eg. 50 broadcasts per second with the default gossip interval of 200 ms
+    const PER_ROUND: usize = 10;
+
+    let scenario = Scenario {
+        mode,
+        anti_entropy: AntiEntropy::Deltas,
+        node_count: GRID_NODES,
+        links: grid_links(),
+        broadcasts: (0..GRID_BROADCASTS)
+            .map(|i| ((i * 7) % GRID_NODES, i as isize))
+            .collect::<Vec<_>>()
+            .chunks(PER_ROUND)
+            .map(<[_]>::to_vec)
+            .collect(),
+        partitions: Vec::new(),
+        seed: 1,
+    };
+
+    run(&scenario).expect("grid converges").stats
+}
+
+/// Runs operations on a 5x5 grid with batching, half of them broadcasts and half reads like the Maelstrom
+/// broadcast workload, returns statistics of messages between nodes and the number of operations
+///
+/// Every delivery takes the latency of 100 ms and carries messages batched for as long, 100 operations
+/// per second arrive in the meantime and nodes gossip every 200 ms, like in the runs of gossipy-run.
+fn grid_batched(mode: Mode) -> (Stats, usize) {
+    const OPS_PER_DELIVERY: usize = 10;
+    const DELIVERIES_PER_ROUND: usize = 2;
+
+    let mut cluster = Cluster::new(mode, AntiEntropy::Deltas, GRID_NODES, &grid_links(), 1);
+    // the topology is not part of the load
+    cluster.stats = Stats::default();
+
+    let mut ops = 0;
+    let mut values = BTreeSet::new();
+    let mut delivery = 0;
+    while values.len() < GRID_BROADCASTS || !cluster.converged(&values) {
+        assert!(delivery < 1000, "grid does not converge");
+        if values.len() < GRID_BROADCASTS {
+            for op in 0..OPS_PER_DELIVERY {
+                let node = (ops * 7) % GRID_NODES;
+                let body = if op % 2 == 0 {
+                    let value = values.len() as isize;
+                    values.insert(value);
+                    json!({"type": "broadcast", "message": value})
+                } else {
+                    json!({"type": "read"})
+                };
+                cluster.send(node, body);
+                ops += 1;
+            }
+        }
+        if delivery % DELIVERIES_PER_ROUND == 0 {
+            cluster.start_gossip();
+        }
+        cluster.step();
+        delivery += 1;
+    }
+    // acknowledgements are still on the way
+    cluster.deliver();
+    (cluster.stats, ops)
+}
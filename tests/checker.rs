@@ -0,0 +1,234 @@
+use gossipy::checker::{self, ConsistencyModel};
+use gossipy::history::{Op, OpType};
+use serde_json::{json, Value};
+
+/// Builds history from `(process, type, f, value)`, times are increasing
+fn history(ops: &[(usize, OpType, &str, Value)]) -> Vec<Op> {
+    ops.iter()
+        .enumerate()
+        .map(|(time, (process, ty, f, value))| Op {
+            process: *process,
+            ty: *ty,
+            f: f.to_string(),
+            value: value.clone(),
+            time: time as u64,
+            node: format!("n{}", process % 2),
+            error: None,
+        })
+        .collect()
+}
+
+/// Operation invoked and completed by the process without any concurrent operation
+fn ok(process: usize, f: &str, invoke: Value, ok: Value) -> [(usize, OpType, &str, Value); 2] {
+    [
+        (process, OpType::Invoke, f, invoke),
+        (process, OpType::Ok, f, ok),
+    ]
+}
+
+fn txns(txns: &[Value]) -> Vec<Op> {
+    let ops: Vec<_> = txns
+        .iter()
+        .enumerate()
+        .flat_map(|(process, txn)| ok(process, "txn", txn.clone(), txn.clone()))
+        .collect();
+    history(&ops)
+}
+
+fn anomalies(history: &[Op]) -> Vec<&'static str> {
+    checker::txn_rw_register(history, ConsistencyModel::ReadCommitted).anomaly_types
+}
+
+#[test]
+fn unique_ids_finds_duplicates() {
+    let ops = [
+        ok(0, "generate", Value::Null, json!("a")),
+        ok(1, "generate", Value::Null, json!("b")),
+        ok(0, "generate", Value::Null, json!("a")),
+    ]
+    .concat();
+    let result = checker::unique_ids(&history(&ops));
+    assert!(!result.valid);
+    assert_eq!(result.duplicated, vec![(json!("a"), 2)]);
+}
+
+#[test]
+fn broadcast_finds_lost_and_unexpected_messages() {
+    let ops = [
+        ok(0, "broadcast", json!(1), json!(1)),
+        ok(1, "broadcast", json!(2), json!(2)),
+        ok(0, "read", Value::Null, json!([1, 2])),
+        ok(1, "read", Value::Null, json!([1, 2])),
+    ]
+    .concat();
+    assert!(checker::broadcast(&history(&ops)).valid);
+
+    let ops = [
+        ok(0, "broadcast", json!(1), json!(1)),
+        ok(1, "broadcast", json!(2), json!(2)),
+        ok(0, "read", Value::Null, json!([1, 2])),
+        ok(1, "read", Value::Null, json!([1, 7])),
+    ]
+    .concat();
+    let result = checker::broadcast(&history(&ops));
+    assert!(!result.valid);
+    assert_eq!(result.lost, vec![json!(2)]);
+    assert_eq!(result.unexpected, vec![json!(7)]);
+}
+
+#[test]
+fn g_counter_checks_final_reads_against_bounds() {
+    let mut ops = [
+        ok(0, "add", json!(2), json!(2)),
+        ok(1, "read", Value::Null, json!(0)),
+        ok(1, "add", json!(3), json!(3)),
+    ]
+    .concat();
+    // indefinite add may or may not be counted
+    ops.push((0, OpType::Invoke, "add", json!(4)));
+    ops.push((0, OpType::Info, "add", json!(4)));
+    ops.extend(ok(2, "read", Value::Null, json!(9)));
+    ops.extend(ok(3, "read", Value::Null, json!(5)));
+    let result = checker::g_counter(&history(&ops));
+    assert!(result.valid, "{result:?}");
+
+    ops.extend(ok(3, "read", Value::Null, json!(4)));
+    let result = checker::g_counter(&history(&ops));
+    assert!(!result.valid);
+    assert_eq!(result.errors, vec![json!({"node": "n1", "value": 4})]);
+}
+
+#[test]
+fn kafka_finds_lost_writes_and_inconsistent_offsets() {
+    let send = |key: &str, msg: u64, offset: u64| {
+        ok(
+            0,
+            "send",
+            json!({"key": key, "msg": msg}),
+            json!({"key": key, "msg": msg, "offset": offset}),
+        )
+    };
+    let poll = |msgs: Value| {
+        ok(
+            1,
+            "poll",
+            json!({"offsets": {"k0": 0}}),
+            json!({"offsets": {"k0": 0}, "msgs": msgs}),
+        )
+    };
+
+    let ops = [
+        send("k0", 10, 0),
+        send("k0", 11, 1),
+        poll(json!({"k0": [[0, 10], [1, 11]]})),
+    ]
+    .concat();
+    assert!(checker::kafka(&history(&ops)).valid);
+
+    let ops = [
+        send("k0", 10, 0),
+        send("k0", 11, 1),
+        send("k0", 12, 2),
+        poll(json!({"k0": [[0, 10], [2, 12]]})),
+    ]
+    .concat();
+    let result = checker::kafka(&history(&ops));
+    assert!(!result.valid);
+    assert_eq!(result.lost_write_count, 1);
+    assert_eq!(result.lost_writes[0]["offset"], 1);
+
+    let ops = [send("k0", 10, 0), poll(json!({"k0": [[0, 11], [0, 12]]}))].concat();
+    let result = checker::kafka(&history(&ops));
+    assert!(!result.valid);
+    assert_eq!(result.inconsistent_offsets.len(), 1);
+    assert_eq!(result.nonmonotonic_polls.len(), 1);
+}
+
+#[test]
+fn txn_accepts_read_committed_history() {
+    let history = txns(&[
+        json!([["w", 1, 1], ["w", 2, 1]]),
+        json!([["r", 1, 1], ["w", 1, 2]]),
+        json!([["r", 1, 2], ["r", 2, 1]]),
+    ]);
+    let result = checker::txn_rw_register(&history, ConsistencyModel::ReadCommitted);
+    assert!(result.valid, "{result:?}");
+    assert!(result.anomaly_types.is_empty());
+}
+
+#[test]
+fn txn_finds_g0_write_cycle() {
+    // each transaction overwrites what the other one wrote
+    let history = txns(&[
+        json!([["w", 1, 1], ["r", 2, 2], ["w", 2, 1]]),
+        json!([["w", 2, 2], ["r", 1, 1], ["w", 1, 2]]),
+    ]);
+    assert_eq!(anomalies(&history), vec!["G0"]);
+    let result = checker::txn_rw_register(&history, ConsistencyModel::ReadUncommitted);
+    assert!(!result.valid);
+    assert_eq!(result.not, vec!["read-uncommitted", "read-committed"]);
+}
+
+#[test]
+fn txn_finds_g1a_aborted_read() {
+    let mut ops = vec![
+        (0, OpType::Invoke, "txn", json!([["w", 1, 1]])),
+        (0, OpType::Fail, "txn", json!([["w", 1, 1]])),
+    ];
+    ops.extend(ok(1, "txn", json!([["r", 1, null]]), json!([["r", 1, 1]])));
+    let history = history(&ops);
+    assert_eq!(anomalies(&history), vec!["G1a"]);
+    let result = checker::txn_rw_register(&history, ConsistencyModel::ReadUncommitted);
+    assert!(result.valid);
+}
+
+#[test]
+fn txn_finds_g1b_intermediate_read() {
+    let history = txns(&[json!([["w", 1, 1], ["w", 1, 2]]), json!([["r", 1, 1]])]);
+    assert_eq!(anomalies(&history), vec!["G1b"]);
+}
+
+#[test]
+fn txn_finds_g1c_circular_information_flow() {
+    let history = txns(&[
+        json!([["w", 1, 1], ["r", 2, 1]]),
+        json!([["w", 2, 1], ["r", 1, 1]]),
+    ]);
+    assert_eq!(anomalies(&history), vec!["G1c"]);
+    let result = checker::txn_rw_register(&history, ConsistencyModel::ReadCommitted);
+    assert!(!result.valid);
+    assert_eq!(result.anomalies["G1c"][0].as_array().unwrap().len(), 2);
+}
+
+#[test]
+fn txn_indefinite_writers_are_only_sources_of_dependencies() {
+    // the indefinite transaction may have read the write of the other one, but its reads are unknown
+    let mut ops = vec![(
+        0,
+        OpType::Invoke,
+        "txn",
+        json!([["w", 1, 1], ["r", 2, null]]),
+    )];
+    ops.extend(ok(
+        1,
+        "txn",
+        json!([["r", 1, null], ["w", 2, 1]]),
+        json!([["r", 1, 1], ["w", 2, 1]]),
+    ));
+    ops.push((2, OpType::Invoke, "txn", json!([["w", 3, 1], ["w", 3, 2]])));
+    ops.push((2, OpType::Info, "txn", json!([["w", 3, 1], ["w", 3, 2]])));
+    ops.extend(ok(3, "txn", json!([["r", 3, null]]), json!([["r", 3, 1]])));
+    let history = history(&ops);
+
+    // intermediate writes of indefinite transactions are still found
+    assert_eq!(anomalies(&history), vec!["G1b"]);
+}
+
+#[test]
+fn results_render_as_edn() {
+    let value = json!({"valid?": true, "workload": {"lost": [1, null], "by-f": {"read": "ok"}}});
+    assert_eq!(
+        checker::to_edn(&value),
+        "{:valid? true,\n :workload {:by-f {:read \"ok\"}, :lost [1 nil]}}\n"
+    );
+}
@@ -0,0 +1,118 @@
+use gossipy::clock::{HlcTimestamp, HybridClock, LamportClock, VersionVector};
+use proptest::prelude::*;
+
+fn ts(wall: u64, logical: u32) -> HlcTimestamp {
+    HlcTimestamp { wall, logical }
+}
+
+#[test]
+fn lamport_clock_orders_send_before_receive() {
+    let mut a = LamportClock::new();
+    let mut b = LamportClock::new();
+    assert_eq!(a.tick(), 1);
+    assert_eq!(a.tick(), 2);
+
+    let sent = a.tick();
+    let received = b.merge(sent);
+    assert!(received > sent);
+    // merging an older time still advances the clock
+    assert_eq!(b.merge(1), received + 1);
+}
+
+#[test]
+fn version_vectors_detect_concurrency() {
+    let mut a = VersionVector::new();
+    a.increment("n1");
+    let mut b = a.clone();
+    assert_eq!(a.partial_cmp(&b), Some(std::cmp::Ordering::Equal));
+
+    b.increment("n2");
+    assert!(a.happened_before(&b));
+    assert!(!b.happened_before(&a));
+
+    a.increment("n1");
+    assert!(a.concurrent_with(&b));
+
+    a.merge(&b);
+    assert_eq!(a.get("n1"), 2);
+    assert_eq!(a.get("n2"), 1);
+    assert_eq!(a.get("n3"), 0);
+    assert!(b.happened_before(&a));
+}
+
+#[test]
+fn hybrid_clock_does_not_go_back_with_physical_time() {
+    let mut clock = HybridClock::new();
+    assert_eq!(clock.tick_at(100), ts(100, 0));
+    assert_eq!(clock.tick_at(90), ts(100, 1));
+    assert_eq!(clock.tick_at(100), ts(100, 2));
+    assert_eq!(clock.tick_at(101), ts(101, 0));
+}
+
+#[test]
+fn hybrid_clock_merges_timestamps_from_the_future() {
+    let mut clock = HybridClock::new();
+    clock.tick_at(100);
+    let received = ts(200, 5);
+    assert_eq!(clock.merge_at(received, 150), ts(200, 6));
+    // physical time catches up
+    assert_eq!(clock.merge_at(received, 300), ts(300, 0));
+}
+
+#[test]
+fn exhausted_logical_counter_advances_wall_time() {
+    let mut clock = HybridClock::new();
+    let last = ts(100, u32::MAX);
+    clock.merge_at(ts(100, u32::MAX - 1), 100);
+    assert_eq!(clock.last(), last);
+
+    assert_eq!(clock.tick_at(100), ts(101, 0));
+
+    let mut clock = HybridClock::new();
+    assert_eq!(clock.merge_at(last, 50), ts(101, 0));
+}
+
+#[test]
+fn lamport_clock_saturates_at_maximum_time() {
+    let mut clock = LamportClock::new();
+    assert_eq!(clock.merge(u64::MAX), u64::MAX);
+    assert_eq!(clock.tick(), u64::MAX);
+    assert_eq!(clock.merge(1), u64::MAX);
+}
+
+#[test]
+fn hybrid_clock_saturates_at_maximum_wall_time() {
+    let mut clock = HybridClock::new();
+    assert_eq!(clock.merge_at(ts(u64::MAX, 0), 100), ts(u64::MAX, 1));
+
+    let last = ts(u64::MAX, u32::MAX);
+    assert_eq!(clock.merge_at(last, 100), last);
+    assert_eq!(clock.tick_at(100), last);
+    assert_eq!(clock.tick_at(u64::MAX), last);
+}
+
+proptest! {
+    #[test]
+    fn hybrid_timestamps_increase(
+        events in prop::collection::vec(
+            (any::<bool>(), 0u64..5, 0u64..5, prop_oneof![0u32..3, (u32::MAX - 2)..=u32::MAX]),
+            1..50,
+        )
+    ) {
+        let mut clock = HybridClock::new();
+        let mut last = clock.last();
+        for (merge, physical, wall, logical) in events {
+            let received = HlcTimestamp { wall, logical };
+            let now = if merge {
+                clock.merge_at(received, physical)
+            } else {
+                clock.tick_at(physical)
+            };
+            prop_assert!(now > last);
+            if merge {
+                prop_assert!(now > received);
+            }
+            last = now;
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use gossipy::clock::HlcTimestamp;
+use std::collections::BTreeSet;
+
+use gossipy::crdt::{
+    Crdt, GCounter, GSet, LWWMap, LWWRegister, ORSet, PNCounter, RangeSet, TwoPSet,
+};
+use proptest::prelude::*;
+
+fn node_id() -> impl Strategy<Value = String> {
+    prop::sample::select(vec!["n0", "n1", "n2"]).prop_map(str::to_string)
+}
+
+fn timestamp() -> impl Strategy<Value = HlcTimestamp> {
+    (0u64..5, 0u32..3).prop_map(|(wall, logical)| HlcTimestamp { wall, logical })
+}
+
+fn merged<T: Crdt>(a: &T, b: &T) -> T {
+    let mut a = a.clone();
+    a.merge(b);
+    a
+}
+
+fn g_counter() -> impl Strategy<Value = GCounter> {
+    prop::collection::vec((node_id(), 0u64..10), 0..8).prop_map(|ops| {
+        let mut counter = GCounter::new();
+        for (node, delta) in ops {
+            counter.increment(&node, delta);
+        }
+        counter
+    })
+}
+
+fn pn_counter() -> impl Strategy<Value = PNCounter> {
+    prop::collection::vec((node_id(), any::<bool>(), 0u64..10), 0..8).prop_map(|ops| {
+        let mut counter = PNCounter::new();
+        for (node, inc, delta) in ops {
+            if inc {
+                counter.increment(&node, delta);
+            } else {
+                counter.decrement(&node, delta);
+            }
+        }
+        counter
+    })
+}
+
+fn g_set() -> impl Strategy<Value = GSet<u8>> {
+    prop::collection::vec(0u8..20, 0..8).prop_map(|values| values.into_iter().collect())
+}
+
+fn range_set() -> impl Strategy<Value = RangeSet<i8>> {
+    // ranges reaching the bounds of the type cover the overflow corner cases
+    let bound = prop_oneof![Just(i8::MIN), Just(i8::MAX), -20i8..20];
+    prop::collection::vec((bound.clone(), bound), 0..6).prop_map(|ranges| {
+        let mut set = RangeSet::new();
+        for (a, b) in ranges {
+            set.insert_range(a.min(b), a.max(b));
+        }
+        set
+    })
+}
+
+fn two_p_set() -> impl Strategy<Value = TwoPSet<u8>> {
+    prop::collection::vec((any::<bool>(), 0u8..10), 0..12).prop_map(|ops| {
+        let mut set = TwoPSet::new();
+        for (insert, value) in ops {
+            if insert {
+                set.insert(value);
+            } else {
+                set.remove(&value);
+            }
+        }
+        set
+    })
+}
+
+fn or_set() -> impl Strategy<Value = ORSet<u8>> {
+    (
+        node_id(),
+        prop::collection::vec((any::<bool>(), 0u8..10), 0..12),
+    )
+        .prop_map(|(node, ops)| {
+            let mut set = ORSet::new();
+            for (insert, value) in ops {
+                if insert {
+                    set.insert(&node, value);
+                } else {
+                    set.remove(&value);
+                }
+            }
+            set
+        })
+}
+
+fn lww_register() -> impl Strategy<Value = LWWRegister<u8>> {
+    prop::collection::vec((prop::option::of(0u8..10), timestamp(), node_id()), 0..4).prop_map(
+        |ops| {
+            let mut register = LWWRegister::new();
+            for (value, ts, node) in ops {
+                match value {
+                    Some(value) => register.set(value, ts, &node),
+                    None => register.clear(ts, &node),
+                }
+            }
+            register
+        },
+    )
+}
+
+fn lww_map() -> impl Strategy<Value = LWWMap<u8, u8>> {
+    prop::collection::vec(
+        (0u8..5, prop::option::of(0u8..10), timestamp(), node_id()),
+        0..10,
+    )
+    .prop_map(|ops| {
+        let mut map = LWWMap::new();
+        for (key, value, ts, node) in ops {
+            match value {
+                Some(value) => map.insert(key, value, ts, &node),
+                None => map.remove(key, ts, &node),
+            }
+        }
+        map
+    })
+}
+
+/// Generates property tests checking the CRDT laws for the given strategy
+macro_rules! crdt_laws {
+    ($name:ident, $strategy:expr) => {
+        mod $name {
+            use super::*;
+
+            proptest! {
+                #[test]
+                fn merge_is_commutative(a in $strategy, b in $strategy) {
+                    prop_assert_eq!(merged(&a, &b), merged(&b, &a));
+                }
+
+                #[test]
+                fn merge_is_associative(a in $strategy, b in $strategy, c in $strategy) {
+                    prop_assert_eq!(merged(&merged(&a, &b), &c), merged(&a, &merged(&b, &c)));
+                }
+
+                #[test]
+                fn merge_is_idempotent(a in $strategy, b in $strategy) {
+                    prop_assert_eq!(merged(&a, &a), a.clone());
+                    let ab = merged(&a, &b);
+                    prop_assert_eq!(merged(&ab, &b), ab);
+                }
+
+                #[test]
+                fn delta_is_equivalent_to_full_state(a in $strategy, b in $strategy) {
+                    let expected = merged(&b, &a);
+                    match a.delta(&b) {
+                        Some(delta) => prop_assert_eq!(merged(&b, &delta), expected),
+                        None => prop_assert_eq!(b, expected),
+                    }
+                }
+
+                #[test]
+                fn survives_serde_round_trip(a in $strategy) {
+                    let json = serde_json::to_string(&a).unwrap();
+                    prop_assert_eq!(serde_json::from_str(&json).ok(), Some(a));
+                }
+            }
+        }
+    };
+}
+
+crdt_laws!(g_counter_laws, g_counter());
+crdt_laws!(pn_counter_laws, pn_counter());
+crdt_laws!(g_set_laws, g_set());
+crdt_laws!(range_set_laws, range_set());
+crdt_laws!(two_p_set_laws, two_p_set());
+crdt_laws!(or_set_laws, or_set());
+crdt_laws!(lww_register_laws, lww_register());
+crdt_laws!(lww_map_laws, lww_map());
+
+#[test]
+fn or_set_concurrent_add_wins() {
+    let mut a = ORSet::new();
+    a.insert("n0", 1);
+    let mut b = a.clone();
+
+    a.remove(&1);
+    b.insert("n1", 1);
+
+    a.merge(&b);
+    assert!(a.contains(&1));
+    assert_eq!(a.iter().collect::<Vec<_>>(), vec![&1]);
+}
+
+#[test]
+fn pn_counter_value() {
+    let mut a = PNCounter::new();
+    a.increment("n0", 5);
+    let mut b = PNCounter::new();
+    b.decrement("n1", 7);
+
+    a.merge(&b);
+    assert_eq!(a.value(), -2);
+}
+
+proptest! {
+    #[test]
+    fn range_set_behaves_as_set(a in range_set(), b in range_set()) {
+        let set = |s: &RangeSet<i8>| s.iter().collect::<BTreeSet<_>>();
+        prop_assert_eq!(a.len(), set(&a).len());
+        prop_assert!(set(&a).iter().all(|v| a.contains(v)));
+
+        let delta = a.delta(&b).map(|d| set(&d)).unwrap_or_default();
+        prop_assert_eq!(delta, set(&a).difference(&set(&b)).copied().collect());
+        let common = set(&a.intersection(&b));
+        prop_assert_eq!(common, set(&a).intersection(&set(&b)).copied().collect());
+
+        // adjacent and overlapping ranges are always joined
+        let ranges: Vec<_> = a.ranges().collect();
+        prop_assert!(ranges.windows(2).all(|w| w[0].1 < w[1].0 - 1));
+    }
+}
+
+#[test]
+fn range_set_is_serialized_as_ranges() {
+    let mut set: RangeSet<isize> = (1..=500).chain(502..=900).collect();
+    set.insert(501);
+    set.insert(1000);
+    set.extend([1002, 1003]);
+    assert_eq!(
+        serde_json::to_string(&set).unwrap(),
+        "[[1,900],1000,1002,1003]"
+    );
+
+    let set: RangeSet<isize> = serde_json::from_str("[[5,7],[1,3],4]").unwrap();
+    assert_eq!(set.ranges().collect::<Vec<_>>(), vec![(1, 7)]);
+    assert!(serde_json::from_str::<RangeSet<isize>>("[[3,1]]").is_err());
+}
@@ -0,0 +1,59 @@
+use std::time::{Duration, Instant};
+
+use gossipy::failure::{DetectorConfig, FailureDetector};
+
+const INTERVAL: Duration = Duration::from_millis(100);
+
+/// Returns detector that got heartbeats of `n1` every interval (shifted by `jitter` in every other one)
+/// until the returned time
+fn detector(heartbeats: u32, jitter: Duration) -> (FailureDetector, Instant) {
+    let mut detector = FailureDetector::new(DetectorConfig::new(INTERVAL));
+    let start = Instant::now();
+    let mut now = start;
+    for i in 0..heartbeats {
+        now = start + INTERVAL * i + if i % 2 == 1 { jitter } else { Duration::ZERO };
+        detector.heartbeat("n1", now);
+    }
+    (detector, now)
+}
+
+#[test]
+fn peer_without_heartbeats_is_available() {
+    let (detector, now) = detector(10, Duration::ZERO);
+    assert_eq!(detector.phi("n2", now), 0.0);
+    assert!(detector.is_available("n2", now + Duration::from_secs(60)));
+}
+
+#[test]
+fn suspicion_grows_with_missing_heartbeats() {
+    let (detector, last) = detector(20, Duration::ZERO);
+    assert!(detector.is_available("n1", last + INTERVAL));
+
+    let phis: Vec<f64> = (1..6)
+        .map(|i| detector.phi("n1", last + INTERVAL * i))
+        .collect();
+    assert!(phis.windows(2).all(|w| w[0] < w[1]), "{phis:?}");
+    // suspected after the third missing heartbeat
+    assert!(detector.is_available("n1", last + INTERVAL * 2));
+    assert!(!detector.is_available("n1", last + INTERVAL * 3));
+}
+
+#[test]
+fn irregular_heartbeats_are_suspected_later() {
+    let (regular, regular_last) = detector(20, Duration::ZERO);
+    let (irregular, irregular_last) = detector(20, Duration::from_millis(80));
+
+    let late = INTERVAL * 3;
+    assert!(irregular.phi("n1", irregular_last + late) < regular.phi("n1", regular_last + late));
+    assert!(irregular.is_available("n1", irregular_last + late));
+}
+
+#[test]
+fn heartbeat_clears_suspicion() {
+    let (mut detector, last) = detector(20, Duration::ZERO);
+    let now = last + Duration::from_secs(5);
+    assert!(!detector.is_available("n1", now));
+
+    detector.heartbeat("n1", now);
+    assert!(detector.is_available("n1", now + INTERVAL));
+}
@@ -0,0 +1,167 @@
+use std::collections::BTreeSet;
+
+use gossipy::crdt::RangeSet;
+use gossipy::gossip::{Gossip, PeerSelection};
+use gossipy::{MsgId, Node};
+use serde_json::{json, Value};
+
+/// Number of gossip rounds after which unacknowledged gossip message is forgotten
+const PENDING_ROUNDS: usize = 10;
+
+fn node() -> Node<()> {
+    let ids: Vec<String> = (0..6).map(|i| format!("n{i}")).collect();
+    let mut node = Node::with_ids("n0", &ids).expect("creating node");
+    node.capture_output();
+    node
+}
+
+fn peers(n: usize) -> Vec<String> {
+    (1..=n).map(|i| format!("n{i}")).collect()
+}
+
+/// Runs gossip round, returns the sent messages as (destination, message ID, values)
+fn gossip(
+    gossip: &mut Gossip<RangeSet<i64>>,
+    node: &mut Node<()>,
+) -> Vec<(String, MsgId, Vec<i64>)> {
+    gossip
+        .gossip(node, |have| json!({"type": "gossip", "have": have}))
+        .expect("gossiping");
+    node.take_output()
+        .into_iter()
+        .map(|line| {
+            let msg: Value = serde_json::from_str(&line).expect("valid message");
+            let dst = msg["dest"].as_str().expect("destination").to_string();
+            let msg_id = MsgId(msg["body"]["msg_id"].as_u64().expect("msg_id") as usize);
+            let have: RangeSet<i64> =
+                serde_json::from_value(msg["body"]["have"].clone()).expect("values");
+            (dst, msg_id, have.iter().collect())
+        })
+        .collect()
+}
+
+fn engine(values: impl IntoIterator<Item = i64>, peers: Vec<String>) -> Gossip<RangeSet<i64>> {
+    let mut gossip: Gossip<RangeSet<i64>> = Gossip::new(PeerSelection::All);
+    gossip.set_peers(peers);
+    gossip.update(|state| state.extend(values));
+    gossip
+}
+
+#[test]
+fn peers_get_only_what_they_do_not_have() {
+    let mut node = node();
+    let mut engine = engine([1, 2, 3], peers(2));
+
+    // n1 sent us 1 and 4, so it has them
+    let new = engine.receive("n1", &RangeSet::from_iter([1, 4]));
+    assert_eq!(new, Some(RangeSet::from_iter([4])));
+    assert_eq!(engine.delta_for("n1"), Some(RangeSet::from_iter([2, 3])));
+    assert_eq!(
+        engine.delta_for("n2"),
+        Some(RangeSet::from_iter([1, 2, 3, 4]))
+    );
+
+    let mut sent = gossip(&mut engine, &mut node);
+    sent.sort();
+    let sent: Vec<_> = sent
+        .into_iter()
+        .map(|(dst, _, values)| (dst, values))
+        .collect();
+    assert_eq!(
+        sent,
+        vec![
+            ("n1".to_string(), vec![2, 3]),
+            ("n2".to_string(), vec![1, 2, 3, 4])
+        ]
+    );
+
+    // values the node already has are not new
+    assert_eq!(engine.receive("n2", &RangeSet::from_iter([2])), None);
+}
+
+#[test]
+fn acknowledged_deltas_are_known_to_the_peer() {
+    let mut node = node();
+    let mut engine = engine([1, 2], peers(1));
+
+    let sent = gossip(&mut engine, &mut node);
+    let [(_, msg_id, _)] = sent.as_slice() else {
+        panic!("one message expected: {sent:?}");
+    };
+    // unacknowledged delta is sent again
+    assert_eq!(gossip(&mut engine, &mut node).len(), 1);
+
+    assert!(engine.ack(*msg_id).is_some());
+    assert_eq!(engine.known("n1"), Some(&RangeSet::from_iter([1, 2])));
+    assert_eq!(engine.delta_for("n1"), None);
+    assert!(gossip(&mut engine, &mut node).is_empty());
+
+    // the acknowledgement is accepted only once
+    assert_eq!(engine.ack(*msg_id), None);
+
+    engine.update(|state| state.insert(3));
+    let sent = gossip(&mut engine, &mut node);
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].2, vec![3]);
+}
+
+#[test]
+fn unacknowledged_messages_expire() {
+    let mut node = node();
+    let mut engine = engine([1], peers(1));
+
+    let sent = gossip(&mut engine, &mut node);
+    let late = sent[0].1;
+    let sent = gossip(&mut engine, &mut node);
+    let in_time = sent[0].1;
+
+    // the second message was sent a round later, so it is still pending
+    for _ in 0..PENDING_ROUNDS - 1 {
+        engine.expire();
+    }
+    assert_eq!(engine.ack(late), None);
+    assert!(engine.ack(in_time).is_some());
+    assert_eq!(engine.delta_for("n1"), None);
+}
+
+#[test]
+fn random_selection_gossips_with_fanout_peers() {
+    let mut node = node();
+    let mut engine = engine([1], peers(5));
+    engine.set_selection(PeerSelection::Random(2));
+
+    let mut reached = BTreeSet::new();
+    for _ in 0..50 {
+        let sent = gossip(&mut engine, &mut node);
+        let destinations: BTreeSet<_> = sent.into_iter().map(|(dst, ..)| dst).collect();
+        assert_eq!(destinations.len(), 2);
+        reached.extend(destinations);
+    }
+    // unacknowledged peers stay candidates in every round
+    assert_eq!(reached, peers(5).into_iter().collect());
+}
+
+#[test]
+fn rounds_without_deltas_select_fanout_peers() {
+    let mut engine = engine([1], peers(5));
+    engine.set_selection(PeerSelection::Random(2));
+
+    let mut reached = BTreeSet::new();
+    for _ in 0..50 {
+        let selected: BTreeSet<_> = engine.start_round().into_iter().collect();
+        assert_eq!(selected.len(), 2);
+        reached.extend(selected);
+    }
+    assert_eq!(reached, peers(5).into_iter().collect());
+
+    // suspected peers are selected only after they back off
+    engine.set_selection(PeerSelection::All);
+    engine.set_suspected("n1", true);
+    let mut rounds = Vec::new();
+    for round in 1..=8 {
+        if engine.start_round().contains(&"n1".to_string()) {
+            rounds.push(round);
+        }
+    }
+    assert_eq!(rounds, vec![1, 3, 7]);
+}
@@ -0,0 +1,189 @@
+use gossipy::kv_store::{
+    self, KvRequest, KvResponse, KvServer, KvService, LinKv, LwwKv, SeqKv,
+    ERROR_KEY_DOES_NOT_EXIST, ERROR_PRECONDITION_FAILED,
+};
+use gossipy::rng::Rng;
+use serde_json::{json, Value};
+
+fn read(key: &str) -> KvRequest {
+    KvRequest::Read { key: json!(key) }
+}
+
+fn write(key: &str, value: u64) -> KvRequest {
+    KvRequest::Write {
+        key: json!(key),
+        value: json!(value),
+    }
+}
+
+fn cas(key: &str, from: u64, to: u64, create_if_not_exists: bool) -> KvRequest {
+    KvRequest::Cas {
+        key: json!(key),
+        from: json!(from),
+        to: json!(to),
+        create_if_not_exists,
+    }
+}
+
+fn read_value(service: &mut dyn KvService, client: &str, key: &str) -> Option<u64> {
+    match service.handle(client, read(key)) {
+        KvResponse::ReadOk { value } => value.as_u64(),
+        _ => None,
+    }
+}
+
+fn error_code(response: KvResponse) -> Option<u16> {
+    match response {
+        KvResponse::Error { code, .. } => Some(code),
+        _ => None,
+    }
+}
+
+#[test]
+fn lin_kv_semantics() {
+    let mut kv = LinKv::default();
+    assert_eq!(
+        error_code(kv.handle("n0", read("x"))),
+        Some(ERROR_KEY_DOES_NOT_EXIST)
+    );
+    assert_eq!(
+        error_code(kv.handle("n0", cas("x", 1, 2, false))),
+        Some(ERROR_KEY_DOES_NOT_EXIST)
+    );
+    assert_eq!(kv.handle("n0", cas("x", 1, 2, true)), KvResponse::CasOk);
+    assert_eq!(
+        error_code(kv.handle("n1", cas("x", 1, 3, false))),
+        Some(ERROR_PRECONDITION_FAILED)
+    );
+    assert_eq!(kv.handle("n1", cas("x", 2, 3, false)), KvResponse::CasOk);
+    assert_eq!(kv.handle("n0", write("y", 7)), KvResponse::WriteOk);
+    assert_eq!(read_value(&mut kv, "n0", "x"), Some(3));
+    assert_eq!(read_value(&mut kv, "n1", "y"), Some(7));
+}
+
+#[test]
+fn seq_kv_reads_may_be_stale_but_monotonic() {
+    let mut kv = SeqKv::new(Rng::seeded(1));
+    for i in 1..=20 {
+        assert_eq!(kv.handle("writer", write("x", i)), KvResponse::WriteOk);
+    }
+
+    let mut stale = false;
+    for reader in 0..20 {
+        let client = format!("c{reader}");
+        let mut last = 0;
+        for _ in 0..10 {
+            let value = read_value(&mut kv, &client, "x").unwrap_or(0);
+            assert!(value >= last, "read went back from {last} to {value}");
+            stale |= value < 20;
+            last = value;
+        }
+    }
+    assert!(stale, "expected some stale reads");
+
+    // own writes are always visible
+    assert_eq!(read_value(&mut kv, "writer", "x"), Some(20));
+}
+
+#[test]
+fn seq_kv_reads_of_new_clients_survive_pruning() {
+    let mut kv = SeqKv::new(Rng::seeded(5));
+    kv.handle("writer", write("y", 1));
+    // more writes than versions kept between prunings
+    for i in 1..=1500 {
+        kv.handle("writer", write("x", i));
+    }
+
+    for reader in 0..50 {
+        let client = format!("c{reader}");
+        // versions before the pruning are gone, so reads do not go back before it
+        let x = read_value(&mut kv, &client, "x").expect("x was written");
+        assert!(x >= 1023, "read pruned value {x}");
+        assert_eq!(read_value(&mut kv, &client, "y"), Some(1));
+    }
+}
+
+#[test]
+fn seq_kv_cas_applies_to_latest_value() {
+    let mut kv = SeqKv::new(Rng::seeded(2));
+    kv.handle("n0", write("x", 1));
+    assert_eq!(kv.handle("n1", cas("x", 1, 2, false)), KvResponse::CasOk);
+    assert_eq!(read_value(&mut kv, "n1", "x"), Some(2));
+}
+
+#[test]
+fn lww_kv_loses_concurrent_updates_and_converges() {
+    let mut kv = LwwKv::new(Rng::seeded(3));
+    let mut lost = false;
+    for i in 0..20 {
+        // every client increments the counter before the replicas are merged
+        for client in ["n0", "n1", "n2"] {
+            let current = read_value(&mut kv, client, "x").unwrap_or(0);
+            kv.handle(client, cas("x", current, current + 1, true));
+        }
+        kv.tick();
+        lost |= read_value(&mut kv, "n0", "x") != Some(3 * (i + 1));
+    }
+    assert!(lost, "expected lost updates");
+
+    let values: Vec<_> = (0..10).map(|_| read_value(&mut kv, "n0", "x")).collect();
+    assert!(values.windows(2).all(|w| w[0] == w[1]), "replicas diverged");
+}
+
+#[test]
+fn lww_kv_is_reproducible_from_seed() {
+    let run = || {
+        let mut kv = LwwKv::new(Rng::seeded(6));
+        let mut responses = Vec::new();
+        for i in 0..50 {
+            for client in ["n0", "n1"] {
+                let current = read_value(&mut kv, client, "x").unwrap_or(0);
+                responses.push(kv.handle(client, cas("x", current, current + 1, true)));
+            }
+            if i % 3 == 0 {
+                kv.tick();
+            }
+            responses.push(kv.handle("n2", read("x")));
+        }
+        responses
+    };
+    assert_eq!(run(), run());
+}
+
+#[test]
+fn server_answers_messages() {
+    let service = kv_store::service(kv_store::LIN_KV_SERVICE_ID, Rng::seeded(4)).unwrap();
+    let mut server = KvServer::new(kv_store::LIN_KV_SERVICE_ID, service);
+
+    let reply = server
+        .handle_line(r#"{"src":"n0","dest":"lin-kv","body":{"type":"write","msg_id":1,"key":"x","value":[1,2]}}"#)
+        .unwrap();
+    assert_eq!(reply.src, "lin-kv");
+    assert_eq!(reply.dst, "n0");
+    assert_eq!(reply.body.in_reply_to.map(|id| id.0), Some(1));
+    assert_eq!(reply.body.payload, KvResponse::WriteOk);
+
+    let reply = server
+        .handle_line(r#"{"src":"n1","dest":"lin-kv","body":{"type":"read","msg_id":5,"key":"x"}}"#)
+        .unwrap();
+    assert_eq!(
+        reply.body.payload,
+        KvResponse::ReadOk {
+            value: json!([1, 2])
+        }
+    );
+
+    let reply = server
+        .handle_line(r#"{"src":"n1","dest":"lin-kv","body":{"type":"cas","msg_id":6,"key":"x"}}"#)
+        .unwrap();
+    assert_eq!(error_code(reply.body.payload), Some(12));
+
+    let reply = server
+        .handle_line(r#"{"src":"n1","dest":"lin-kv","body":{"type":"delete","msg_id":7}}"#)
+        .unwrap();
+    let encoded: Value = serde_json::to_value(&reply).unwrap();
+    assert_eq!(error_code(reply.body.payload), Some(10));
+    assert_eq!(encoded["dest"], "n1");
+    assert_eq!(encoded["body"]["type"], "error");
+    assert_eq!(encoded["body"]["in_reply_to"], 7);
+}
@@ -0,0 +1,216 @@
+use gossipy::history::{Op, OpType};
+use gossipy::kv_store::{KvRequest, KvResponse, KvService, LinKv, SeqKv};
+use gossipy::linearizability;
+use gossipy::rng::Rng;
+use proptest::prelude::*;
+use serde_json::{json, Value};
+
+/// Builds history from `(process, type, f, value)`, times are increasing
+fn history(ops: &[(usize, OpType, &str, Value)]) -> Vec<Op> {
+    ops.iter()
+        .enumerate()
+        .map(|(time, (process, ty, f, value))| Op {
+            process: *process,
+            ty: *ty,
+            f: f.to_string(),
+            value: value.clone(),
+            time: time as u64,
+            node: "n0".to_string(),
+            error: None,
+        })
+        .collect()
+}
+
+fn invoke(process: usize, f: &str, value: Value) -> (usize, OpType, &str, Value) {
+    (process, OpType::Invoke, f, value)
+}
+
+fn ok(process: usize, f: &str, value: Value) -> (usize, OpType, &str, Value) {
+    (process, OpType::Ok, f, value)
+}
+
+#[test]
+fn sequential_history() {
+    let valid = history(&[
+        invoke(0, "read", json!(["x", null])),
+        ok(0, "read", json!(["x", null])),
+        invoke(0, "write", json!(["x", 1])),
+        ok(0, "write", json!(["x", 1])),
+        invoke(1, "cas", json!(["x", [1, 2]])),
+        ok(1, "cas", json!(["x", [1, 2]])),
+        invoke(0, "read", json!(["x", null])),
+        ok(0, "read", json!(["x", 2])),
+    ]);
+    assert!(linearizability::check(&valid).valid);
+
+    let mut invalid = valid.clone();
+    invalid[7].value = json!(["x", 1]);
+    assert!(!linearizability::check(&invalid).valid);
+}
+
+#[test]
+fn concurrent_operations_may_take_effect_in_any_order() {
+    let history = history(&[
+        invoke(0, "write", json!(["x", 1])),
+        invoke(1, "write", json!(["x", 2])),
+        invoke(2, "read", json!(["x", null])),
+        ok(2, "read", json!(["x", 1])),
+        invoke(3, "read", json!(["x", null])),
+        ok(3, "read", json!(["x", 2])),
+        ok(0, "write", json!(["x", 1])),
+        ok(1, "write", json!(["x", 2])),
+    ]);
+    assert!(linearizability::check(&history).valid);
+}
+
+#[test]
+fn indefinite_operations_may_or_may_not_take_effect() {
+    let mut ops = vec![
+        invoke(0, "write", json!(["x", 1])),
+        (0, OpType::Info, "write", json!(["x", 1])),
+        invoke(1, "cas", json!(["x", [5, 6]])),
+        (1, OpType::Info, "cas", json!(["x", [5, 6]])),
+        // failed write did not happen
+        invoke(2, "write", json!(["x", 3])),
+        (2, OpType::Fail, "write", json!(["x", 3])),
+        invoke(3, "read", json!(["x", null])),
+        ok(3, "read", json!(["x", null])),
+        invoke(3, "read", json!(["x", null])),
+        ok(3, "read", json!(["x", 1])),
+    ];
+    assert!(linearizability::check(&history(&ops)).valid);
+
+    ops.extend([
+        invoke(3, "read", json!(["x", null])),
+        ok(3, "read", json!(["x", 3])),
+    ]);
+    assert!(!linearizability::check(&history(&ops)).valid);
+}
+
+#[test]
+fn counterexample_is_minimal_and_per_key() {
+    let history = history(&[
+        invoke(0, "write", json!(["y", 7])),
+        ok(0, "write", json!(["y", 7])),
+        invoke(0, "write", json!(["x", 1])),
+        ok(0, "write", json!(["x", 1])),
+        invoke(1, "read", json!(["x", null])),
+        ok(1, "read", json!(["x", 1])),
+        invoke(0, "write", json!(["x", 2])),
+        ok(0, "write", json!(["x", 2])),
+        invoke(2, "read", json!(["y", null])),
+        ok(2, "read", json!(["y", 7])),
+        // stale read
+        invoke(1, "read", json!(["x", null])),
+        ok(1, "read", json!(["x", 1])),
+    ]);
+    let result = linearizability::check(&history);
+    assert!(!result.valid);
+    assert_eq!(result.key_count, 2);
+    assert_eq!(result.failures.len(), 1);
+
+    let failure = &result.failures[0];
+    assert_eq!(failure.key, json!("x"));
+    assert_eq!(failure.op_count, 4);
+    let ops: Vec<(&Value, &Value)> = failure
+        .counterexample
+        .iter()
+        .map(|op| (&op["f"], &op["value"]))
+        .collect();
+    assert_eq!(
+        ops,
+        vec![
+            (&json!("write"), &json!(["x", 1])),
+            (&json!("write"), &json!(["x", 2])),
+            (&json!("read"), &json!(["x", 1])),
+        ]
+    );
+}
+
+/// Runs concurrent clients against the service, every request takes effect at a random moment
+/// between its invocation and completion
+fn simulate(service: &mut dyn KvService, seed: u64, steps: usize) -> Vec<Op> {
+    const CLIENTS: usize = 4;
+    let mut rng = Rng::seeded(seed);
+    // process => (f, invocation value, reply once applied)
+    let mut pending: Vec<Option<(&str, Value, Option<Value>)>> = vec![None; CLIENTS];
+    let mut history = Vec::new();
+    let mut record = |process: usize, ty, f: &str, value: Value| {
+        let time = history.len() as u64;
+        history.push(Op {
+            process,
+            ty,
+            f: f.to_string(),
+            value,
+            time,
+            node: "n0".to_string(),
+            error: None,
+        });
+    };
+
+    for _ in 0..steps {
+        let process = rng.below(CLIENTS as u64) as usize;
+        match pending[process].take() {
+            None => {
+                let key = rng.below(2);
+                let (f, value) = match rng.below(3) {
+                    0 => ("read", json!([key, null])),
+                    1 => ("write", json!([key, rng.below(3)])),
+                    _ => ("cas", json!([key, [rng.below(3), rng.below(3)]])),
+                };
+                record(process, OpType::Invoke, f, value.clone());
+                pending[process] = Some((f, value, None));
+            }
+            Some((f, value, None)) => {
+                let key = value[0].clone();
+                let request = match f {
+                    "read" => KvRequest::Read { key },
+                    "write" => KvRequest::Write {
+                        key,
+                        value: value[1].clone(),
+                    },
+                    _ => KvRequest::Cas {
+                        key,
+                        from: value[1][0].clone(),
+                        to: value[1][1].clone(),
+                        create_if_not_exists: false,
+                    },
+                };
+                let reply = match service.handle(&format!("c{process}"), request) {
+                    KvResponse::ReadOk { value: read } => json!([value[0], read]),
+                    KvResponse::Error { .. } => Value::Null,
+                    _ => value.clone(),
+                };
+                pending[process] = Some((f, value, Some(reply)));
+            }
+            Some((f, value, Some(reply))) => {
+                if reply.is_null() {
+                    record(process, OpType::Fail, f, value);
+                } else {
+                    record(process, OpType::Ok, f, reply);
+                }
+            }
+        }
+    }
+    history
+}
+
+proptest! {
+    #[test]
+    fn lin_kv_histories_are_linearizable(seed in any::<u64>()) {
+        let history = simulate(&mut LinKv::default(), seed, 300);
+        let result = linearizability::check(&history);
+        prop_assert!(result.valid, "{:?}", result.failures);
+    }
+}
+
+#[test]
+fn seq_kv_stale_reads_are_not_linearizable() {
+    let failing = (0..10)
+        .filter(|seed| {
+            let history = simulate(&mut SeqKv::new(Rng::seeded(*seed)), *seed, 300);
+            !linearizability::check(&history).valid
+        })
+        .count();
+    assert!(failing > 0);
+}
@@ -0,0 +1,91 @@
+use std::time::{Duration, Instant};
+
+use gossipy::pacing::{Observation, Pace, Pacer, PacingConfig};
+
+const NODES: usize = 25;
+/// Values broadcast to the cluster per second
+const RATE: usize = 100;
+const RTT: Duration = Duration::from_millis(200);
+
+/// Runs gossip rounds of a node with `peers` peers for 10 seconds, every round it learns the values
+/// broadcast since the previous one and sends them to all peers (or `fanout` of them)
+/// with acknowledgements, returns the final pace
+fn run(pacer: &mut Pacer, peers: usize) -> Pace {
+    let start = Instant::now();
+    let mut now = start;
+    let mut observed = Observation {
+        values: 0,
+        messages: 0,
+        backlog: 0,
+        peers,
+        nodes: NODES,
+    };
+    let mut pace = pacer.round(observed, now);
+    while now < start + Duration::from_secs(10) {
+        now += pace.interval;
+        observed.values += (RATE as f64 * pace.interval.as_secs_f64()) as usize;
+        observed.backlog = observed.values;
+        observed.messages += 2 * pace.fanout.unwrap_or(peers);
+        pacer.acked(RTT);
+        pace = pacer.round(observed, now);
+    }
+    pace
+}
+
+/// Returns messages per value at the pace
+fn msgs_per_op(pace: Pace, peers: usize) -> f64 {
+    let per_second = 2.0 * pace.fanout.unwrap_or(peers) as f64 / pace.interval.as_secs_f64();
+    per_second * NODES as f64 / RATE as f64
+}
+
+#[test]
+fn many_peers_gossip_as_often_as_budget_allows() {
+    let mut pacer = Pacer::new(PacingConfig::new(25.0), Duration::from_millis(200));
+    let pace = run(&mut pacer, 24);
+    assert_eq!(pace.fanout, None);
+    // 48 messages per round, 100 messages per second per node
+    assert!(
+        pace.interval > Duration::from_millis(450) && pace.interval < Duration::from_millis(500),
+        "{pace:?}"
+    );
+    assert!((msgs_per_op(pace, 24) - 25.0).abs() < 1.0, "{pace:?}");
+}
+
+#[test]
+fn few_peers_gossip_every_round_trip() {
+    let mut pacer = Pacer::new(PacingConfig::new(25.0), Duration::from_millis(100));
+    let pace = run(&mut pacer, 4);
+    assert_eq!(pace.interval, RTT);
+    assert_eq!(pace.fanout, None);
+    assert!(msgs_per_op(pace, 4) < 25.0);
+}
+
+#[test]
+fn fanout_is_reduced_when_rounds_would_be_too_long() {
+    let mut pacer = Pacer::new(PacingConfig::new(10.0), Duration::from_millis(200));
+    let pace = run(&mut pacer, 24);
+    assert_eq!(pace.interval, Duration::from_millis(500));
+    let fanout = pace.fanout.expect("reduced fanout");
+    assert!((9..=11).contains(&fanout), "{pace:?}");
+    assert!(msgs_per_op(pace, 24) < 11.0, "{pace:?}");
+}
+
+#[test]
+fn idle_rounds_keep_the_pace() {
+    let mut pacer = Pacer::new(PacingConfig::new(25.0), Duration::from_millis(200));
+    let pace = run(&mut pacer, 24);
+
+    // nothing new and nothing to send
+    let observed = Observation {
+        values: 0,
+        messages: 0,
+        backlog: 0,
+        peers: 24,
+        nodes: NODES,
+    };
+    let mut now = Instant::now() + Duration::from_secs(20);
+    for _ in 0..100 {
+        now += Duration::from_secs(1);
+        assert_eq!(pacer.round(observed, now), pace);
+    }
+}
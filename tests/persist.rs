@@ -0,0 +1,141 @@
+use std::{fs, io::Write, path::PathBuf};
+
+use gossipy::persist::{self, Config, Durable, Persisted, Storage};
+
+/// Append-only log, its records are not idempotent like the logs of `kafka-single-node`
+#[derive(Debug, Default, PartialEq)]
+struct Log(Vec<u64>);
+
+impl Durable for Log {
+    type Record = u64;
+    type Snapshot = Vec<u64>;
+
+    fn apply(&mut self, record: u64) {
+        self.0.push(record);
+    }
+
+    fn snapshot(&self) -> Vec<u64> {
+        self.0.clone()
+    }
+
+    fn restore(&mut self, snapshot: Vec<u64>) {
+        self.0 = snapshot;
+    }
+}
+
+/// Returns configuration of an empty data directory unique for the test
+fn config(test: &str, snapshot_every: usize) -> Config {
+    let dir: PathBuf =
+        std::env::temp_dir().join(format!("gossipy-persist-{test}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let mut config = Config::new(dir);
+    config.snapshot_every = snapshot_every;
+    config
+}
+
+fn open(config: &Config) -> Persisted<Log> {
+    let storage = Storage::open(config.clone()).expect("opening storage");
+    Persisted::with_storage(storage, Log::default()).expect("recovering")
+}
+
+/// Recovers the log, returns it with the number of replayed records
+fn recover(config: &Config) -> (Log, usize) {
+    let mut storage = Storage::open(config.clone()).expect("opening storage");
+    let mut log = Log::default();
+    let replayed = storage.recover(&mut log).expect("recovering");
+    (log, replayed)
+}
+
+#[test]
+fn wal_is_replayed() {
+    let config = config("replay", 1000);
+    let mut log = open(&config);
+    for record in 1..=5 {
+        log.apply(record).expect("applying");
+    }
+    drop(log);
+
+    assert_eq!(recover(&config), (Log(vec![1, 2, 3, 4, 5]), 5));
+
+    // records appended after recovery follow the recovered ones
+    let mut log = open(&config);
+    log.apply(6).expect("applying");
+    drop(log);
+    assert_eq!(recover(&config), (Log(vec![1, 2, 3, 4, 5, 6]), 6));
+}
+
+#[test]
+fn corrupted_wal_tail_is_truncated() {
+    let config = config("corrupted", 1000);
+    let mut log = open(&config);
+    log.apply(1).expect("applying");
+    log.apply(2).expect("applying");
+    drop(log);
+
+    // the node crashed in the middle of writing a record
+    let mut wal = fs::OpenOptions::new()
+        .append(true)
+        .open(config.dir.join("wal.jsonl"))
+        .expect("opening WAL");
+    wal.write_all(br#"{"seq":3,"rec"#).expect("writing WAL");
+    drop(wal);
+
+    let mut log = open(&config);
+    assert_eq!(*log, Log(vec![1, 2]));
+    log.apply(3).expect("applying");
+    drop(log);
+
+    assert_eq!(recover(&config), (Log(vec![1, 2, 3]), 3));
+}
+
+#[test]
+fn snapshot_and_wal_are_recovered() {
+    let config = config("snapshot", 3);
+    let mut log = open(&config);
+    for record in 1..=5 {
+        log.apply(record).expect("applying");
+    }
+    drop(log);
+
+    // the snapshot contains the first three records, the WAL the rest
+    assert_eq!(recover(&config), (Log(vec![1, 2, 3, 4, 5]), 2));
+
+    let mut log = open(&config);
+    log.apply(6).expect("applying");
+    drop(log);
+    assert_eq!(recover(&config), (Log(vec![1, 2, 3, 4, 5, 6]), 0));
+}
+
+#[test]
+fn records_in_snapshot_are_not_replayed_after_crash_before_truncation() {
+    let config = config("crash", 1000);
+    let wal_path = config.dir.join("wal.jsonl");
+    let mut storage = Storage::open(config.clone()).expect("opening storage");
+    for record in 1..=3u64 {
+        storage.append(&record).expect("appending");
+    }
+    let wal = fs::read(&wal_path).expect("reading WAL");
+    storage
+        .snapshot(&vec![1u64, 2, 3])
+        .expect("taking snapshot");
+    drop(storage);
+
+    // the node crashed after replacing the snapshot, before truncating the WAL
+    fs::write(&wal_path, &wal).expect("restoring WAL");
+    assert_eq!(recover(&config), (Log(vec![1, 2, 3]), 0));
+
+    // and numbering continues after the snapshot
+    let mut log = open(&config);
+    log.apply(4).expect("applying");
+    drop(log);
+    assert_eq!(recover(&config), (Log(vec![1, 2, 3, 4]), 1));
+}
+
+#[test]
+fn number_of_shards_must_not_change() {
+    let config = config("shards", 1000);
+    persist::check_shards(&config.dir, 4).expect("recording shards");
+    persist::check_shards(&config.dir, 4).expect("same number of shards");
+    let err = persist::check_shards(&config.dir, 2).expect_err("different number of shards");
+    assert!(err.to_string().contains("4 shards"), "{err}");
+}
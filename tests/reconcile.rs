@@ -0,0 +1,136 @@
+use gossipy::crdt::{Crdt, RangeSet};
+use gossipy::reconcile::{self, Part};
+use proptest::prelude::*;
+
+/// Reconciles the sets, returns the number of messages and bytes of their parts and missing elements
+fn reconcile(a: &mut RangeSet<i64>, b: &mut RangeSet<i64>) -> (usize, usize) {
+    let mut parts = reconcile::start(a);
+    let (mut messages, mut bytes) = (1, json_len(&parts));
+    // the sets take turns in replying
+    let (mut from, mut to) = (a, b);
+    while !parts.is_empty() {
+        let reply = reconcile::reply(to, &parts);
+        to.merge(&reply.learned);
+        if !reply.missing.is_empty() {
+            messages += 1;
+            bytes += json_len(&reply.missing);
+            from.merge(&reply.missing);
+        }
+        parts = reply.parts;
+        if !parts.is_empty() {
+            messages += 1;
+            bytes += json_len(&parts);
+        }
+        std::mem::swap(&mut from, &mut to);
+    }
+    (messages, bytes)
+}
+
+fn json_len(value: &impl serde::Serialize) -> usize {
+    serde_json::to_string(value).unwrap().len()
+}
+
+fn range_set() -> impl Strategy<Value = RangeSet<i64>> {
+    // clusters of values, so the sets have both long ranges and single values
+    let range = (-1000i64..1000, 0i64..50).prop_map(|(start, len)| (start, start + len));
+    let bound = prop_oneof![
+        Just((i64::MIN, i64::MIN)),
+        Just((i64::MAX, i64::MAX)),
+        range
+    ];
+    prop::collection::vec(bound, 0..100).prop_map(|ranges| {
+        let mut set = RangeSet::new();
+        for (start, end) in ranges {
+            set.insert_range(start, end);
+        }
+        set
+    })
+}
+
+proptest! {
+    #[test]
+    fn reconciled_sets_are_the_union(mut a in range_set(), mut b in range_set()) {
+        let mut union = a.clone();
+        union.merge(&b);
+
+        reconcile(&mut a, &mut b);
+        prop_assert_eq!(&a, &union);
+        prop_assert_eq!(&b, &union);
+    }
+
+    #[test]
+    fn parts_survive_serde_round_trip(a in range_set(), b in range_set()) {
+        let parts = reconcile::reply(&a, &reconcile::start(&b)).parts;
+        let json = serde_json::to_string(&parts).unwrap();
+        prop_assert_eq!(serde_json::from_str::<Vec<Part<i64>>>(&json).ok(), Some(parts));
+    }
+}
+
+#[test]
+fn equal_sets_exchange_one_fingerprint() {
+    let mut a: RangeSet<i64> = (0..10_000).map(|v| v * 3).collect();
+    let mut b = a.clone();
+    assert_eq!(
+        reconcile(&mut a, &mut b),
+        (1, json_len(&reconcile::start(&a)))
+    );
+}
+
+#[test]
+fn few_differences_in_large_sets_are_cheap() {
+    // 100000 single values, so the sets are large even as ranges
+    let mut a: RangeSet<i64> = (0..100_000).map(|v| v * 3).collect();
+    let mut b = a.clone();
+    a.insert(1);
+    b.insert(150_001);
+    b.insert(299_998);
+    let full = json_len(&a);
+    let mut union = a.clone();
+    union.merge(&b);
+
+    let (messages, bytes) = reconcile(&mut a, &mut b);
+    assert_eq!(a, union);
+    assert_eq!(b, union);
+    // one round trip per level of the window tree
+    assert!(messages <= 10, "{messages} messages");
+    assert!(
+        bytes * 20 < full,
+        "{bytes} bytes exchanged, the set takes {full}"
+    );
+}
+
+#[test]
+fn malformed_parts_are_ignored() {
+    let set: RangeSet<i64> = (0..100).collect();
+    let parts: Vec<Part<i64>> =
+        serde_json::from_str(r#"[{"start":5,"end":1,"hash":0},{"start":5,"end":1,"items":[0]}]"#)
+            .unwrap();
+    let reply = reconcile::reply(&set, &parts);
+    assert!(reply.parts.is_empty());
+    assert!(reply.learned.is_empty());
+    assert!(reply.missing.is_empty());
+
+    // elements outside of the window are not learned
+    let parts: Vec<Part<i64>> =
+        serde_json::from_str(r#"[{"start":200,"end":300,"items":[[0,1000]]}]"#).unwrap();
+    let reply = reconcile::reply(&set, &parts);
+    assert_eq!(reply.learned.ranges().collect::<Vec<_>>(), vec![(200, 300)]);
+}
+
+#[test]
+fn fingerprints_are_stable_across_element_types() {
+    // nodes may run different builds, so the hash is fixed by the ranges alone
+    let even: RangeSet<i64> = (0..20).map(|v| v * 2).collect();
+    let expected = vec![Part::Fingerprint {
+        start: i64::MIN,
+        end: i64::MAX,
+        hash: 12638095652012359717,
+    }];
+    assert_eq!(reconcile::start(&even), expected);
+
+    let even: RangeSet<u16> = (0..20).map(|v| v * 2).collect();
+    let [Part::Fingerprint { hash, .. }] = reconcile::start(&even)[..] else {
+        panic!("fingerprint expected");
+    };
+    assert_eq!(hash, 12638095652012359717);
+}
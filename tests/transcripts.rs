@@ -0,0 +1,272 @@
+//! Golden transcript tests of the challenge binaries
+//!
+//! Every case in `tests/transcripts/<binary>/` consists of:
+//! - `<case>.in.jsonl` - script of messages sent to the node, starting with `init`
+//! - `<case>.out.jsonl` - golden file with the messages the node emitted
+//!
+//! The script is fed line by line, the next line is sent after the node has been quiet for a while.
+//! Requests to `lin-kv`, `seq-kv` and `lww-kv` are answered by the local services from
+//! [`kv_store`](gossipy::kv_store), so the scripts contain only messages of clients and other nodes.
+//!
+//! Non-deterministic fields are replaced by placeholders:
+//! - `msg_id` of the k-th message emitted by the node is `"$k"`, the script can refer to it
+//!   (eg. `"in_reply_to": "$3"`), the line is then sent only after the node emitted the message
+//! - fields masked by the test are `"$masked"`
+//!
+//! Run with `GOSSIPY_BLESS=1` to (re)write the golden files.
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    process::{ChildStdin, Command, Stdio},
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    time::Duration,
+};
+
+use gossipy::{
+    kv_store::{self, KvServer},
+    rng::Rng,
+};
+use serde_json::Value;
+
+/// The node is considered idle when it emits nothing for this long
+const SETTLE: Duration = Duration::from_millis(50);
+/// Maximum time to wait for a message the script refers to
+const WAIT: Duration = Duration::from_secs(5);
+
+/// Field `field` of the emitted messages of type `ty` that is replaced by a placeholder
+type Mask = (&'static str, &'static str);
+
+#[test]
+fn echo() {
+    check("echo", env!("CARGO_BIN_EXE_echo"), &[], &[]);
+}
+
+#[test]
+fn unique_ids() {
+    check("unique-ids", env!("CARGO_BIN_EXE_unique-ids"), &[], &[]);
+}
+
+#[test]
+fn broadcast() {
+    // no periodic gossip, the transcript would depend on timing of the gossip rounds
+    check("broadcast", env!("CARGO_BIN_EXE_broadcast"), &["0"], &[]);
+}
+
+#[test]
+fn g_counter() {
+    // the timestamp written before every read
+    check(
+        "g-counter",
+        env!("CARGO_BIN_EXE_g-counter"),
+        &[],
+        &[("write", "value")],
+    );
+}
+
+#[test]
+fn kafka_single_node() {
+    check(
+        "kafka-single-node",
+        env!("CARGO_BIN_EXE_kafka-single-node"),
+        &[],
+        &[],
+    );
+}
+
+#[test]
+fn kafka_multi_node() {
+    check(
+        "kafka-multi-node",
+        env!("CARGO_BIN_EXE_kafka-multi-node"),
+        &[],
+        &[],
+    );
+}
+
+#[test]
+fn txn() {
+    check("txn", env!("CARGO_BIN_EXE_txn"), &[], &[]);
+}
+
+/// Runs all transcripts of the binary and compares them with the golden files
+fn check(name: &str, bin: &str, args: &[&str], masks: &[Mask]) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/transcripts")
+        .join(name);
+    let mut scripts: Vec<PathBuf> = fs::read_dir(&dir)
+        .unwrap_or_else(|e| panic!("reading {}: {e}", dir.display()))
+        .map(|entry| entry.expect("directory entry").path())
+        .filter(|path| path.to_string_lossy().ends_with(".in.jsonl"))
+        .collect();
+    scripts.sort();
+    assert!(!scripts.is_empty(), "no transcripts in {}", dir.display());
+
+    let bless = std::env::var_os("GOSSIPY_BLESS").is_some();
+    for script in scripts {
+        let golden = PathBuf::from(script.to_string_lossy().replace(".in.jsonl", ".out.jsonl"));
+        let input = fs::read_to_string(&script).expect("reading script");
+        let output = run(bin, args, &input, masks);
+
+        if bless {
+            fs::write(&golden, output).expect("writing golden file");
+            continue;
+        }
+        let expected = fs::read_to_string(&golden)
+            .unwrap_or_else(|e| panic!("reading {}: {e}", golden.display()));
+        assert_eq!(
+            output,
+            expected,
+            "transcript {} differs from the golden file",
+            script.display()
+        );
+    }
+}
+
+/// Runs the node with the script and returns the normalized messages it emitted
+fn run(bin: &str, args: &[&str], script: &str, masks: &[Mask]) -> String {
+    let mut command = Command::new(bin);
+    // configuration of the runtime must not leak from the environment
+    for (key, _) in std::env::vars().filter(|(key, _)| key.starts_with("GOSSIPY_")) {
+        command.env_remove(key);
+    }
+    let mut child = command
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap_or_else(|e| panic!("spawning {bin}: {e}"));
+
+    let stdout = child.stdout.take().expect("piped stdout");
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            let Ok(line) = line else { break };
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut transcript = Transcript {
+        stdin: child.stdin.take(),
+        rx,
+        services: [
+            kv_store::LIN_KV_SERVICE_ID,
+            kv_store::SEQ_KV_SERVICE_ID,
+            kv_store::LWW_KV_SERVICE_ID,
+        ]
+        .into_iter()
+        .map(|id| {
+            let service = kv_store::service(id, Rng::seeded(1)).expect("known service");
+            (id.to_string(), KvServer::new(id, service))
+        })
+        .collect(),
+        msg_ids: Vec::new(),
+        emitted: Vec::new(),
+    };
+
+    for line in script.lines().filter(|line| !line.trim().is_empty()) {
+        let mut msg: Value = serde_json::from_str(line)
+            .unwrap_or_else(|e| panic!("malformed script line {line}: {e}"));
+        transcript.resolve(&mut msg);
+        transcript.send(&msg);
+        while transcript.receive(SETTLE) {}
+    }
+
+    // the node stops at the end of input
+    transcript.stdin.take();
+    while transcript.receive(WAIT) {}
+    let status = child.wait().expect("waiting for the node");
+    assert!(status.success(), "{bin} exited with {status}");
+
+    let mut output = String::new();
+    for mut msg in transcript.emitted {
+        normalize(&mut msg, &transcript.msg_ids, masks);
+        output.push_str(&serde_json::to_string(&msg).expect("serializing message"));
+        output.push('\n');
+    }
+    output
+}
+
+/// Running conversation with the node
+struct Transcript {
+    stdin: Option<ChildStdin>,
+    rx: Receiver<String>,
+    services: HashMap<String, KvServer>,
+    /// IDs of the messages emitted by the node, in order
+    msg_ids: Vec<u64>,
+    emitted: Vec<Value>,
+}
+
+impl Transcript {
+    fn send(&mut self, msg: &Value) {
+        let stdin = self.stdin.as_mut().expect("open until the end of script");
+        writeln!(stdin, "{msg}").expect("writing to the node");
+    }
+
+    /// Receives the next message emitted by the node, answers it if it is sent to a service.
+    /// Returns `false` if the node emitted nothing within the timeout or stopped.
+    fn receive(&mut self, timeout: Duration) -> bool {
+        let line = match self.rx.recv_timeout(timeout) {
+            Ok(line) => line,
+            Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => return false,
+        };
+        let msg: Value = serde_json::from_str(&line)
+            .unwrap_or_else(|e| panic!("node emitted malformed message {line}: {e}"));
+        if let Some(msg_id) = msg["body"]["msg_id"].as_u64() {
+            self.msg_ids.push(msg_id);
+        }
+
+        let dst = msg["dest"].as_str().unwrap_or_default().to_string();
+        // the node may still send requests after the end of the script, they stay unanswered
+        let service = self.services.get_mut(&dst).filter(|_| self.stdin.is_some());
+        if let Some(service) = service {
+            let reply = service.handle_line(&line).expect("request to the service");
+            let reply = serde_json::to_value(reply).expect("serializing reply");
+            self.send(&reply);
+        }
+        self.emitted.push(msg);
+        true
+    }
+
+    /// Replaces placeholders `"$k"` by ID of the k-th emitted message, waits for it if necessary
+    fn resolve(&mut self, value: &mut Value) {
+        match value {
+            Value::String(s) => {
+                let Some(k) = s.strip_prefix('$').and_then(|k| k.parse::<usize>().ok()) else {
+                    return;
+                };
+                while self.msg_ids.len() < k {
+                    assert!(self.receive(WAIT), "node did not emit message ${k}");
+                }
+                *value = self.msg_ids[k - 1].into();
+            }
+            Value::Array(values) => values.iter_mut().for_each(|v| self.resolve(v)),
+            Value::Object(map) => map.values_mut().for_each(|v| self.resolve(v)),
+            _ => {}
+        }
+    }
+}
+
+/// Replaces non-deterministic fields of the emitted message by placeholders
+fn normalize(msg: &mut Value, msg_ids: &[u64], masks: &[Mask]) {
+    let body = &mut msg["body"];
+    if let Some(msg_id) = body["msg_id"].as_u64() {
+        let k = msg_ids
+            .iter()
+            .position(|id| *id == msg_id)
+            .expect("emitted message ID");
+        body["msg_id"] = format!("${}", k + 1).into();
+    }
+
+    let ty = body["type"].as_str().unwrap_or_default().to_string();
+    for (_, field) in masks.iter().filter(|(t, _)| *t == ty) {
+        if let Some(value) = body.get_mut(*field) {
+            *value = "$masked".into();
+        }
+    }
+}